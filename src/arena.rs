@@ -0,0 +1,641 @@
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::board::Board;
+use crate::movegen::{self, Move};
+use crate::pgn;
+use crate::search::{self, Limits, Settings};
+use crate::syzygy::{self, Tablebases};
+use crate::testsuite;
+
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+// How far past its clock an engine may go before it loses on time, by default
+const TIME_MARGIN_MS: u64 = 50;
+// How long an engine may take to answer uci or isready, and a move at a
+// fixed depth or node count, before it forfeits
+const READY_TIMEOUT_MS: u64 = 10_000;
+const FIXED_MOVE_TIMEOUT_MS: u64 = 600_000;
+// What a reported mate is worth when adjudicating
+const MATE_SCORE: i32 = 30000;
+
+// `cmd=<path>` runs a UCI engine as a child process; `cmd=internal` searches
+// with this build on the playing thread, with its own network, weights and
+// tablebases. Either kind takes UCI options and can play to a fixed depth or
+// node count instead of the clock.
+struct EngineSpec {
+    name: String,
+    cmd: String,
+    options: Vec<(String, String)>,
+    depth: Option<i32>,
+    nodes: Option<u64>,
+}
+
+struct Resign {
+    moves: usize,
+    score: i32,
+}
+
+struct DrawRule {
+    movenumber: usize,
+    moves: usize,
+    score: i32,
+}
+
+struct Sprt {
+    elo0: f64,
+    elo1: f64,
+    alpha: f64,
+    beta: f64,
+}
+
+pub struct Options {
+    engines: Vec<EngineSpec>,
+    games: usize,
+    concurrency: usize,
+    // Milliseconds
+    base: u64,
+    increment: u64,
+    margin: u64,
+    openings: Option<String>,
+    resign: Option<Resign>,
+    draw: Option<DrawRule>,
+    tb: Option<String>,
+    sprt: Option<Sprt>,
+    pgn: Option<String>,
+}
+
+// A start position and the moves played from it before the engines take over
+pub struct Opening {
+    pub fen: String,
+    pub moves: Vec<Move>,
+}
+
+pub struct GameRecord {
+    // Engine playing White
+    pub white: usize,
+    pub fen: String,
+    pub moves: Vec<Move>,
+    pub result: &'static str,
+    pub reason: String,
+}
+
+fn usage() {
+    eprintln!("usage: vesper match --engine cmd=<path|internal> [name=N] [depth=D] [nodes=N] [option.<name>=<value>]... \
+        --engine ... [--games N] [--concurrency N] [--tc base+inc] [--timemargin ms] [--openings file.epd|file.pgn] \
+        [--resign moves=N score=S] [--draw movenumber=N moves=N score=S] [--tb path] \
+        [--sprt elo0=E elo1=E alpha=A beta=B] [--pgn out.pgn]");
+}
+
+// The key=value words following a flag
+fn pairs(args: &[String]) -> Vec<(&str, &str)> {
+    args.iter().take_while(|a| !a.starts_with("--")).filter_map(|a| a.split_once('=')).collect()
+}
+
+pub fn parse_options(args: &[String]) -> Option<Options> {
+    let mut options = Options {
+        engines: Vec::new(),
+        games: 100,
+        concurrency: 1,
+        base: 10_000,
+        increment: 100,
+        margin: TIME_MARGIN_MS,
+        openings: None,
+        resign: None,
+        draw: None,
+        tb: None,
+        sprt: None,
+        pgn: None,
+    };
+
+    let mut i = 0;
+    while i < args.len() {
+        let flag = args[i].as_str();
+        let group = pairs(&args[i + 1..]);
+        let value = |key: &str| group.iter().find(|(k, _)| *k == key).map(|(_, v)| *v);
+        let mut used = 2;
+        match flag {
+            "--engine" => {
+                let cmd = value("cmd")?.to_string();
+                options.engines.push(EngineSpec {
+                    name: value("name").map_or_else(|| format!("engine{}", options.engines.len() + 1), String::from),
+                    options: group.iter()
+                        .filter_map(|(k, v)| Some((k.strip_prefix("option.")?.to_string(), v.to_string())))
+                        .collect(),
+                    depth: value("depth").map(str::parse).transpose().ok()?,
+                    nodes: value("nodes").map(str::parse).transpose().ok()?,
+                    cmd,
+                });
+                used = group.len() + 1;
+            }
+            "--resign" => {
+                options.resign = Some(Resign { moves: value("moves")?.parse().ok()?, score: value("score")?.parse().ok()? });
+                used = group.len() + 1;
+            }
+            "--draw" => {
+                options.draw = Some(DrawRule {
+                    movenumber: value("movenumber")?.parse().ok()?,
+                    moves: value("moves")?.parse().ok()?,
+                    score: value("score")?.parse().ok()?,
+                });
+                used = group.len() + 1;
+            }
+            "--sprt" => {
+                options.sprt = Some(Sprt {
+                    elo0: value("elo0")?.parse().ok()?,
+                    elo1: value("elo1")?.parse().ok()?,
+                    alpha: value("alpha").map_or(Some(0.05), |a| a.parse().ok())?,
+                    beta: value("beta").map_or(Some(0.05), |b| b.parse().ok())?,
+                });
+                used = group.len() + 1;
+            }
+            _ => {
+                let value = args.get(i + 1)?;
+                match flag {
+                    "--games" => options.games = value.parse().ok()?,
+                    "--concurrency" => options.concurrency = value.parse::<usize>().ok()?.max(1),
+                    "--tc" => {
+                        // Seconds, as in 10+0.1
+                        let (base, inc) = value.split_once('+').unwrap_or((value, "0"));
+                        options.base = (base.parse::<f64>().ok()? * 1000.0) as u64;
+                        options.increment = (inc.parse::<f64>().ok()? * 1000.0) as u64;
+                    }
+                    "--timemargin" => options.margin = value.parse().ok()?,
+                    "--openings" => options.openings = Some(value.clone()),
+                    "--tb" => options.tb = Some(value.clone()),
+                    "--pgn" => options.pgn = Some(value.clone()),
+                    _ => return None,
+                }
+            }
+        }
+        i += used;
+    }
+
+    if options.engines.len() != 2 {
+        return None;
+    }
+    Some(options)
+}
+
+// Every position of an EPD file, or the main line of every game of a PGN
+fn load_openings(path: &str) -> Result<Vec<Opening>, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("cannot read {}: {}", path, e))?;
+    let openings: Vec<Opening> = if path.ends_with(".pgn") {
+        pgn::parse(&text)
+            .iter()
+            .map(|game| Opening { fen: full_fen(game.tag("FEN").unwrap_or(START_FEN)), moves: game.mainline() })
+            .collect()
+    } else {
+        text.lines()
+            .filter_map(testsuite::parse_epd)
+            .map(|position| Opening { fen: position.fen, moves: Vec::new() })
+            .collect()
+    };
+    if openings.is_empty() {
+        return Err(format!("no openings in {}", path));
+    }
+    Ok(openings)
+}
+
+// All six FEN fields, as the UCI position command wants them
+fn full_fen(fen: &str) -> String {
+    let mut fields: Vec<&str> = fen.split_whitespace().collect();
+    let defaults = ["8/8/8/8/8/8/8/8", "w", "-", "-", "0", "1"];
+    fields.extend_from_slice(&defaults[fields.len().min(6)..]);
+    fields.join(" ")
+}
+
+pub enum Player {
+    Internal(Settings),
+    // Output lines come through a reader thread, so reads can time out
+    Uci { child: Child, input: ChildStdin, output: Receiver<String> },
+}
+
+impl Player {
+    fn start(spec: &EngineSpec) -> Result<Self, String> {
+        if spec.cmd == "internal" {
+            let mut settings = Settings::current();
+            for (name, value) in spec.options.iter() {
+                settings.set(name, value).map_err(|e| format!("{}: {}", spec.name, e))?;
+            }
+            return Ok(Player::Internal(settings));
+        }
+
+        let mut child = Command::new(&spec.cmd)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(|e| format!("cannot start {}: {}", spec.cmd, e))?;
+        let input = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        let (sender, output) = mpsc::channel();
+        // Ends when the child closes its output
+        thread::spawn(move || {
+            for line in stdout.lines().map_while(Result::ok) {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut player = Player::Uci { child, input, output };
+
+        let ready = || Instant::now() + Duration::from_millis(READY_TIMEOUT_MS);
+        player.send("uci")?;
+        player.read_until("uciok", ready())?;
+        for (name, value) in spec.options.iter() {
+            player.send(&format!("setoption name {} value {}", name, value))?;
+        }
+        player.send("isready")?;
+        player.read_until("readyok", ready())?;
+        Ok(player)
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), String> {
+        let Player::Uci { input, .. } = self else { return Ok(()) };
+        writeln!(input, "{}", command).and_then(|_| input.flush()).map_err(|e| format!("engine closed its input: {}", e))
+    }
+
+    // Lines up to and including the first that starts with `token`
+    fn read_until(&mut self, token: &str, deadline: Instant) -> Result<Vec<String>, String> {
+        let Player::Uci { output, .. } = self else { return Ok(Vec::new()) };
+        let mut lines = Vec::new();
+        loop {
+            let line = output.recv_timeout(deadline.saturating_duration_since(Instant::now())).map_err(|e| match e {
+                RecvTimeoutError::Timeout => format!("no {} in time", token),
+                RecvTimeoutError::Disconnected => "engine exited".to_string(),
+            })?;
+            let done = line.split_whitespace().next() == Some(token);
+            lines.push(line.trim().to_string());
+            if done {
+                return Ok(lines);
+            }
+        }
+    }
+
+    fn new_game(&mut self) -> Result<(), String> {
+        if let Player::Internal(_) = self {
+            return Ok(());
+        }
+        self.send("ucinewgame")?;
+        self.send("isready")?;
+        self.read_until("readyok", Instant::now() + Duration::from_millis(READY_TIMEOUT_MS)).map(|_| ())
+    }
+
+    // The move chosen, in UCI notation, and the score from the mover's side
+    // if one was reported by `deadline`. `clocks` are White's and Black's.
+    fn think(&mut self, spec: &EngineSpec, game: &GameRecord, board: &Board, clocks: [u64; 2], increment: u64, deadline: Instant)
+        -> Result<(String, Option<i32>), String> {
+        let own_clock = clocks[!board.white_to_move(0) as usize];
+        if let Player::Internal(settings) = self {
+            let movetime = if spec.depth.is_some() || spec.nodes.is_some() {
+                None
+            } else {
+                Some(search::time_for_move(own_clock, increment, None))
+            };
+            let limits = Limits { depth: spec.depth.unwrap_or(search::MAX_DEPTH), nodes: spec.nodes, movetime, silent: true };
+            let result = search::search_with_settings(*board, &limits, settings, &mut |_| {});
+            return Ok((result.best_move.to_string(), Some(result.score)));
+        }
+
+        let moves: Vec<String> = game.moves.iter().map(Move::to_string).collect();
+        if moves.is_empty() {
+            self.send(&format!("position fen {}", game.fen))?;
+        } else {
+            self.send(&format!("position fen {} moves {}", game.fen, moves.join(" ")))?;
+        }
+        let go = match (spec.depth, spec.nodes) {
+            (Some(depth), _) => format!("go depth {}", depth),
+            (None, Some(nodes)) => format!("go nodes {}", nodes),
+            (None, None) => format!("go wtime {} btime {} winc {} binc {}", clocks[0], clocks[1], increment, increment),
+        };
+        self.send(&go)?;
+
+        let lines = self.read_until("bestmove", deadline)?;
+        let best = lines.last().unwrap().split_whitespace().nth(1).ok_or("empty bestmove")?.to_string();
+        // The last score reported before the move
+        let score = lines.iter().rev().find_map(|line| {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let i = words.iter().position(|&w| w == "score")?;
+            let value: i32 = words.get(i + 2)?.parse().ok()?;
+            match words[i + 1] {
+                "cp" => Some(value),
+                "mate" => Some(if value > 0 { MATE_SCORE - value } else { -MATE_SCORE - value }),
+                _ => None,
+            }
+        });
+        Ok((best, score))
+    }
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        if let Player::Uci { child, .. } = self {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
+
+// Positions that can't be won by either side
+fn insufficient_material(board: &Board) -> bool {
+    let [white, black] = board.pieces(0);
+    let heavy = [0, 3, 4].iter().any(|&p| white[p] | black[p] != 0);
+    let minors = (white[1] | white[2] | black[1] | black[2]).count_ones();
+    !heavy && minors <= 1
+}
+
+fn decisive(white_wins: bool) -> &'static str {
+    if white_wins { "1-0" } else { "0-1" }
+}
+
+// Both engines of a match, started
+pub fn start_players(options: &Options) -> Result<[Player; 2], String> {
+    Ok([Player::start(&options.engines[0])?, Player::start(&options.engines[1])?])
+}
+
+// Plays one game with `white` (0 or 1) as the first mover's engine when
+// White moves. A player that fails or overruns is restarted for the next game.
+pub fn play_game(players: &mut [Player; 2], white: usize, opening: &Opening, options: &Options, tb: Option<&Tablebases>) -> GameRecord {
+    let mut board = Board::from_fen(&opening.fen);
+    let mut game = GameRecord { white, fen: opening.fen.clone(), moves: Vec::new(), result: "*", reason: String::new() };
+    // Positions since the last pawn move or capture, for repetitions and the 50-move rule
+    let mut reversible = vec![board.fen(0)];
+
+    let play = |board: &mut Board, game: &mut GameRecord, reversible: &mut Vec<String>, mv: Move| {
+        let irreversible = board.occupied().extract(0) & 1 << mv.to_square() != 0 || board.pawns.extract(0) & 1 << mv.from_square() != 0;
+        board.play(mv);
+        game.moves.push(mv);
+        if irreversible {
+            reversible.clear();
+        }
+        reversible.push(board.fen(0));
+    };
+    for &mv in opening.moves.iter() {
+        play(&mut board, &mut game, &mut reversible, mv);
+    }
+
+    for (i, player) in players.iter_mut().enumerate() {
+        if let Err(e) = player.new_game() {
+            // Neither side has moved, so the game simply doesn't count
+            game.reason = format!("{} failed to start a game: {}", options.engines[i].name, e);
+            return game;
+        }
+    }
+
+    let mut clocks = [options.base; 2];
+    // Scores reported by each engine, from its own side
+    let mut scores: [Vec<i32>; 2] = [Vec::new(), Vec::new()];
+
+    loop {
+        let white_to_move = board.white_to_move(0);
+        if movegen::legal_move_list(&board).is_empty() {
+            let checked = movegen::in_check(&board);
+            game.result = if checked { decisive(!white_to_move) } else { "1/2-1/2" };
+            game.reason = if checked { "checkmate" } else { "stalemate" }.to_string();
+            return game;
+        }
+        let draw = if insufficient_material(&board) {
+            Some("insufficient material")
+        } else if reversible.len() > 100 {
+            Some("fifty-move rule")
+        } else if reversible.iter().filter(|fen| **fen == reversible[reversible.len() - 1]).count() >= 3 {
+            Some("threefold repetition")
+        } else {
+            None
+        };
+        if let Some(reason) = draw {
+            game.result = "1/2-1/2";
+            game.reason = reason.to_string();
+            return game;
+        }
+        if let Some(tb) = tb
+            && board.occupied().extract(0).count_ones() as usize <= tb.max_pieces()
+            && let Some(wdl) = tb.probe_wdl(&board)
+        {
+            game.result = match wdl {
+                syzygy::WDL_WIN => decisive(white_to_move),
+                syzygy::WDL_LOSS => decisive(!white_to_move),
+                _ => "1/2-1/2",
+            };
+            game.reason = "tablebase adjudication".to_string();
+            return game;
+        }
+
+        let side = if white_to_move { white } else { 1 - white };
+        let spec = &options.engines[side];
+        let clock_pair = if white == 0 { clocks } else { [clocks[1], clocks[0]] };
+        let timed = spec.depth.is_none() && spec.nodes.is_none();
+        let allowed = Duration::from_millis(if timed { clocks[side] + options.margin } else { FIXED_MOVE_TIMEOUT_MS });
+        let started = Instant::now();
+        let reply = players[side].think(spec, &game, &board, clock_pair, options.increment, started + allowed);
+        let elapsed = started.elapsed();
+
+        let (best, score) = match reply {
+            Ok(reply) if elapsed <= allowed => reply,
+            reply => {
+                // An engine that failed or timed out, and may still be
+                // thinking, is replaced
+                if reply.is_err()
+                    && let Ok(player) = Player::start(spec)
+                {
+                    players[side] = player;
+                }
+                game.result = decisive(!white_to_move);
+                game.reason = match reply {
+                    Err(e) if elapsed <= allowed => format!("{} failed: {}", spec.name, e),
+                    _ if timed => format!("{} loses on time", spec.name),
+                    _ => format!("{} forfeits: no move in {} ms", spec.name, FIXED_MOVE_TIMEOUT_MS),
+                };
+                return game;
+            }
+        };
+        clocks[side] = clocks[side].saturating_sub(elapsed.as_millis() as u64) + options.increment;
+
+        let Some(mv) = movegen::legal_move_list(&board).iter().copied().find(|m| m.to_string() == best) else {
+            game.result = decisive(!white_to_move);
+            game.reason = format!("{} plays illegal move {}", spec.name, best);
+            return game;
+        };
+        play(&mut board, &mut game, &mut reversible, mv);
+
+        let Some(score) = score else { continue };
+        scores[side].push(score);
+        if let Some(resign) = &options.resign
+            && scores[side].len() >= resign.moves
+            && scores[side].iter().rev().take(resign.moves).all(|&s| s <= -resign.score)
+        {
+            game.result = decisive(!white_to_move);
+            game.reason = format!("{} resigns", spec.name);
+            return game;
+        }
+        if let Some(rule) = &options.draw
+            && game.moves.len() / 2 >= rule.movenumber
+            && scores.iter().all(|s| s.len() >= rule.moves && s.iter().rev().take(rule.moves).all(|v| v.abs() <= rule.score))
+        {
+            game.result = "1/2-1/2";
+            game.reason = "draw adjudication".to_string();
+            return game;
+        }
+    }
+}
+
+fn to_elo(score: f64) -> f64 {
+    let score = score.clamp(1e-6, 1.0 - 1e-6);
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+// Mean score and its per-game variance
+fn score_stats(wins: u32, losses: u32, draws: u32) -> Option<(f64, f64)> {
+    let n = (wins + losses + draws) as f64;
+    if n == 0.0 {
+        return None;
+    }
+    let score = (wins as f64 + draws as f64 / 2.0) / n;
+    let variance = (wins as f64 * (1.0 - score).powi(2) + losses as f64 * score.powi(2) + draws as f64 * (0.5 - score).powi(2)) / n;
+    Some((score, variance))
+}
+
+// Elo difference and its 95% margin, from the first engine's side
+pub fn elo(wins: u32, losses: u32, draws: u32) -> (f64, f64) {
+    let Some((score, variance)) = score_stats(wins, losses, draws) else { return (0.0, 0.0) };
+    let n = (wins + losses + draws) as f64;
+    let margin = 1.959964 * (variance / n).sqrt();
+    (to_elo(score), (to_elo(score + margin) - to_elo(score - margin)) / 2.0)
+}
+
+// Log-likelihood ratio of elo1 against elo0, in the usual normal
+// approximation to the trinomial GSPRT
+pub fn llr(wins: u32, losses: u32, draws: u32, elo0: f64, elo1: f64) -> f64 {
+    let Some((score, variance)) = score_stats(wins, losses, draws) else { return 0.0 };
+    if variance == 0.0 {
+        return 0.0;
+    }
+    let n = (wins + losses + draws) as f64;
+    let expected = |elo: f64| 1.0 / (1.0 + 10f64.powf(-elo / 400.0));
+    let (s0, s1) = (expected(elo0), expected(elo1));
+    (s1 - s0) * (2.0 * score - s0 - s1) * n / (2.0 * variance)
+}
+
+// Plays `--games` games (openings are played once with each colour) on
+// `--concurrency` threads, printing the score, Elo and SPRT state after
+// each one. The SPRT stops new games once either hypothesis is accepted.
+pub fn run(args: &[String]) {
+    let Some(options) = parse_options(args) else {
+        usage();
+        return;
+    };
+    let openings = match &options.openings {
+        Some(path) => match load_openings(path) {
+            Ok(openings) => openings,
+            Err(e) => {
+                eprintln!("{}", e);
+                return;
+            }
+        },
+        None => vec![Opening { fen: START_FEN.to_string(), moves: Vec::new() }],
+    };
+    let tb = match options.tb.as_deref().map(Tablebases::open).transpose() {
+        Ok(tb) => tb,
+        Err(e) => {
+            eprintln!("cannot load tablebases: {}", e);
+            return;
+        }
+    };
+    let mut pgn_out = match options.pgn.as_ref().map(|path| File::create(path).map(BufWriter::new).map_err(|e| (path, e))).transpose() {
+        Ok(out) => out,
+        Err((path, e)) => {
+            eprintln!("cannot create {}: {}", path, e);
+            return;
+        }
+    };
+
+    let names = [options.engines[0].name.as_str(), options.engines[1].name.as_str()];
+    let next_game = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let (sender, receiver) = mpsc::channel::<(usize, GameRecord)>();
+
+    thread::scope(|s| {
+        for _ in 0..options.concurrency {
+            let sender = sender.clone();
+            let (options, openings, tb, next_game, stop) = (&options, &openings, tb.as_ref(), &next_game, &stop);
+            s.spawn(move || {
+                let mut players = match start_players(options) {
+                    Ok(players) => players,
+                    Err(e) => {
+                        eprintln!("{}", e);
+                        stop.store(true, Ordering::Relaxed);
+                        return;
+                    }
+                };
+                while !stop.load(Ordering::Relaxed) {
+                    let index = next_game.fetch_add(1, Ordering::Relaxed);
+                    if index >= options.games {
+                        break;
+                    }
+                    let opening = &openings[(index / 2) % openings.len()];
+                    let game = play_game(&mut players, index % 2, opening, options, tb);
+                    if sender.send((index, game)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        let (mut wins, mut losses, mut draws) = (0, 0, 0);
+        for (index, game) in receiver {
+            let white = game.white;
+            let (white_name, black_name) = (names[white], names[1 - white]);
+            println!("Finished game {} ({} vs {}): {} {{{}}}", index + 1, white_name, black_name, game.result, game.reason);
+            if game.result == "*" {
+                continue;
+            }
+
+            match (game.result, white) {
+                ("1/2-1/2", _) => draws += 1,
+                ("1-0", 0) | ("0-1", 1) => wins += 1,
+                _ => losses += 1,
+            }
+            let played = wins + losses + draws;
+            let (score, _) = score_stats(wins, losses, draws).unwrap();
+            println!("Score of {} vs {}: {} - {} - {}  [{:.3}] {}", names[0], names[1], wins, losses, draws, score, played);
+            let (diff, margin) = elo(wins, losses, draws);
+            println!("Elo difference: {:.1} +/- {:.1}", diff, margin);
+
+            if let Some(sprt) = &options.sprt {
+                let (lower, upper) = ((sprt.beta / (1.0 - sprt.alpha)).ln(), ((1.0 - sprt.beta) / sprt.alpha).ln());
+                let ratio = llr(wins, losses, draws, sprt.elo0, sprt.elo1);
+                println!("SPRT: llr {:.2} ({:.2}, {:.2}) [{:.1}, {:.1}]", ratio, lower, upper, sprt.elo0, sprt.elo1);
+                if !stop.load(Ordering::Relaxed) && (ratio >= upper || ratio <= lower) {
+                    println!("SPRT: H{} accepted", if ratio >= upper { 1 } else { 0 });
+                    stop.store(true, Ordering::Relaxed);
+                }
+            }
+
+            if let Some(out) = pgn_out.as_mut() {
+                let mut record = pgn::Game::from_moves(&game.fen, &game.moves, game.result);
+                let tags = [
+                    ("Event", "Vesper match".to_string()),
+                    ("Round", (index + 1).to_string()),
+                    ("White", white_name.to_string()),
+                    ("Black", black_name.to_string()),
+                    ("Result", game.result.to_string()),
+                    ("Termination", game.reason.clone()),
+                ];
+                // The seven-tag roster comes first, FEN and SetUp after
+                record.tags.splice(0..0, tags.into_iter().map(|(k, v)| (k.to_string(), v)));
+                writeln!(out, "{}", pgn::write(&record)).unwrap();
+            }
+        }
+    });
+
+    if let Some(out) = pgn_out.as_mut() {
+        out.flush().unwrap();
+    }
+}
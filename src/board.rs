@@ -1,6 +1,8 @@
 use crate::lane::Lane;
-use crate::movegen::MoveField;
+use crate::movegen::{Move, MoveField};
 
+/// [`LANES`](crate::lane::LANES) positions, one per lane of each bitboard. Knights and kings are
+/// leapers, bishops, rooks and queens sliders, told apart by their traits.
 #[derive(Clone, Copy)]
 pub struct Board {
     pub pawns: Lane,
@@ -14,13 +16,20 @@ pub struct Board {
     pub diagonal: Lane,   // Trait: Bishops and Queens
     pub orthogonal: Lane, // Trait: Rooks and Queens
 
-    pub metadata: Lane, // [Meta1, Meta2, Meta3, Meta4]
+    pub metadata: Lane, // One word per lane
 }
 
 // Metadata bit offsets
 pub const META_TURN: u64 = 0; // 1 bit: 0 = white, 1 = black
-pub const META_CASTLING: u64 = 1; // 4 bits
-pub const META_EP: u64 = 5; // 7 bits (0-63, 64 for none)
+pub const META_CASTLING: u64 = 1; // 4 bits: K, Q, k, q as in FEN
+pub const META_EP: u64 = 5; // 7 bits: en passant target square, 0 (a1, never one) for none
+
+const CASTLING_MASK: u64 = 15 << META_CASTLING;
+const EP_MASK: u64 = 127 << META_EP;
+pub const LAST_RANKS: u64 = 0xff000000000000ff;
+
+// Squares whose king or rook moving, or being captured, gives up each right
+const CASTLING_SQUARES: [u64; 4] = [1 << 4 | 1 << 7, 1 << 4 | 1, 1 << 60 | 1 << 63, 1 << 60 | 1 << 56];
 
 impl Board {
     pub fn new_empty() -> Self {
@@ -41,24 +50,78 @@ impl Board {
         self.pawns | self.leapers | self.sliders | self.kings
     }
 
+    // Slider types are recovered from the trait lanes
+    pub fn bishops(&self) -> Lane {
+        self.sliders & self.diagonal & !self.orthogonal
+    }
+
+    pub fn rooks(&self) -> Lane {
+        self.sliders & self.orthogonal & !self.diagonal
+    }
+
+    pub fn queens(&self) -> Lane {
+        self.sliders & self.diagonal & self.orthogonal
+    }
+
+    // Pawns, knights, bishops, rooks, queens, kings, in PST order
+    pub fn piece_sets(&self) -> [Lane; 6] {
+        [self.pawns, self.leapers, self.bishops(), self.rooks(), self.queens(), self.kings]
+    }
+
+    /// Piece bitboards of one lane, indexed [color][piece type]
+    pub fn pieces(&self, lane: usize) -> [[u64; 6]; 2] {
+        let sets = self.piece_sets();
+        let sides = [self.white.extract(lane), self.black.extract(lane)];
+        sides.map(|side| sets.map(|set| set.extract(lane) & side))
+    }
+
+    /// Checks the placement, side to move, castling and en passant fields
+    /// that `from_fen` reads, which assumes them well formed. For FENs from
+    /// files and tags.
+    pub fn check_fen(fen: &str) -> Result<(), String> {
+        let parts: Vec<&str> = fen.split_whitespace().collect();
+        let Some(placement) = parts.first() else {
+            return Err("empty FEN".to_string());
+        };
+        let rows: Vec<&str> = placement.split('/').collect();
+        if rows.len() != 8 {
+            return Err(format!("{}: {} ranks", fen, rows.len()));
+        }
+        for row in rows {
+            let mut files = 0;
+            for c in row.chars() {
+                files += match c {
+                    '1'..='8' => c.to_digit(10).unwrap(),
+                    _ if "pnbrqkPNBRQK".contains(c) => 1,
+                    _ => return Err(format!("{}: bad character '{}'", fen, c)),
+                };
+            }
+            if files != 8 {
+                return Err(format!("{}: rank {} has {} files", fen, row, files));
+            }
+        }
+        match parts.get(1) {
+            None | Some(&"w") | Some(&"b") => {}
+            Some(side) => return Err(format!("{}: bad side to move {}", fen, side)),
+        }
+        if let Some(castling) = parts.get(2).filter(|c| **c != "-" && !c.chars().all(|c| "KQkq".contains(c))) {
+            return Err(format!("{}: bad castling rights {}", fen, castling));
+        }
+        match parts.get(3) {
+            None | Some(&"-") => Ok(()),
+            Some(ep) if parse_square(ep).is_some_and(|sq| sq / 8 == 2 || sq / 8 == 5) => Ok(()),
+            Some(ep) => Err(format!("{}: bad en passant square {}", fen, ep)),
+        }
+    }
+
+    /// The position in every lane. Missing castling and en passant fields
+    /// mean none; the move counters aren't read.
     pub fn from_fen(fen: &str) -> Self {
-        let mut board = Self::new_empty();
         let parts: Vec<&str> = fen.split_whitespace().collect();
         let rows: Vec<&str> = parts[0].split('/').collect();
 
-        let mut w_pawns = 0u64;
-        let mut w_knights = 0u64;
-        let mut w_bishops = 0u64;
-        let mut w_rooks = 0u64;
-        let mut w_queens = 0u64;
-        let mut w_king = 0u64;
-
-        let mut b_pawns = 0u64;
-        let mut b_knights = 0u64;
-        let mut b_bishops = 0u64;
-        let mut b_rooks = 0u64;
-        let mut b_queens = 0u64;
-        let mut b_king = 0u64;
+        // Indexed [color][piece type], in `piece_sets` order
+        let mut pieces = [[0u64; 6]; 2];
 
         for (i, row) in rows.iter().enumerate() {
             let rank = 7 - i;
@@ -68,27 +131,35 @@ impl Board {
                     file += digit;
                 } else {
                     let square = rank * 8 + file as usize;
-                    let bit = 1u64 << square;
-                    match c {
-                        'P' => w_pawns |= bit,
-                        'N' => w_knights |= bit,
-                        'B' => w_bishops |= bit,
-                        'R' => w_rooks |= bit,
-                        'Q' => w_queens |= bit,
-                        'K' => w_king |= bit,
-                        'p' => b_pawns |= bit,
-                        'n' => b_knights |= bit,
-                        'b' => b_bishops |= bit,
-                        'r' => b_rooks |= bit,
-                        'q' => b_queens |= bit,
-                        'k' => b_king |= bit,
-                        _ => {}
+                    if let Some(piece) = "pnbrqk".find(c.to_ascii_lowercase()) {
+                        pieces[c.is_ascii_lowercase() as usize][piece] |= 1u64 << square;
                     }
                     file += 1;
                 }
             }
         }
 
+        let mut board = Self::from_pieces(&pieces, parts.get(1) != Some(&"b"));
+        let mut meta = 0u64;
+        for (i, c) in "KQkq".chars().enumerate() {
+            if parts.get(2).is_some_and(|castling| castling.contains(c)) {
+                meta |= 1 << (META_CASTLING + i as u64);
+            }
+        }
+        if let Some(ep) = parts.get(3).and_then(|ep| parse_square(ep)) {
+            meta |= (ep as u64) << META_EP;
+        }
+        board.metadata |= Lane::from_single(meta);
+        board
+    }
+
+    /// The position in every lane, from bitboards indexed [color][piece type]
+    pub fn from_pieces(pieces: &[[u64; 6]; 2], white_to_move: bool) -> Self {
+        let mut board = Self::new_empty();
+        let [w, b] = pieces;
+        let [w_pawns, w_knights, w_bishops, w_rooks, w_queens, w_king] = *w;
+        let [b_pawns, b_knights, b_bishops, b_rooks, b_queens, b_king] = *b;
+
         board.pawns = Lane::from_single(w_pawns | b_pawns);
         board.leapers = Lane::from_single(w_knights | b_knights);
         board.sliders = Lane::from_single(w_bishops | b_bishops | w_rooks | b_rooks | w_queens | b_queens);
@@ -101,7 +172,7 @@ impl Board {
         board.orthogonal = Lane::from_single(w_rooks | b_rooks | w_queens | b_queens);
 
         let mut meta = 0u64;
-        if parts.len() > 1 && parts[1] == "b" {
+        if !white_to_move {
             meta |= 1 << META_TURN;
         }
         board.metadata = Lane::from_single(meta);
@@ -109,7 +180,127 @@ impl Board {
         board
     }
 
+    pub fn white_to_move(&self, lane: usize) -> bool {
+        (self.metadata.extract(lane) >> META_TURN) & 1 == 0
+    }
+
+    /// Castling rights of `lane` as 4 bits: K, Q, k, q
+    pub fn castling_rights(&self, lane: usize) -> u64 {
+        (self.metadata.extract(lane) & CASTLING_MASK) >> META_CASTLING
+    }
+
+    /// The square a pawn that just made a double push skipped
+    pub fn ep_square(&self, lane: usize) -> Option<u32> {
+        let sq = ((self.metadata.extract(lane) & EP_MASK) >> META_EP) as u32;
+        (sq != 0).then_some(sq)
+    }
+
+    /// The move counters are always 0 and 1
+    pub fn fen(&self, lane: usize) -> String {
+        let sets = self.piece_sets();
+        let white = self.white.extract(lane);
+        let mut rows = Vec::new();
+        for rank in (0..8).rev() {
+            let mut row = String::new();
+            let mut empty = 0;
+            for file in 0..8 {
+                let bit = 1u64 << (rank * 8 + file);
+                match sets.iter().position(|set| set.extract(lane) & bit != 0) {
+                    Some(piece) => {
+                        if empty > 0 {
+                            row.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        let c = b"pnbrqk"[piece] as char;
+                        row.push(if white & bit != 0 { c.to_ascii_uppercase() } else { c });
+                    }
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                row.push_str(&empty.to_string());
+            }
+            rows.push(row);
+        }
+        let turn = if self.white_to_move(lane) { "w" } else { "b" };
+        let rights = self.castling_rights(lane);
+        let castling: String = "KQkq".chars().enumerate().filter(|(i, _)| rights & 1 << i != 0).map(|(_, c)| c).collect();
+        let castling = if castling.is_empty() { "-".to_string() } else { castling };
+        let ep = self.ep_square(lane).map_or("-".to_string(), square_name);
+        format!("{} {} {} {} 0 1", rows.join("/"), turn, castling, ep)
+    }
+
+    /// Plays a legal move of lane 0 in every lane: castling, en passant and
+    /// any promotion, keeping the castling rights and en passant square up to
+    /// date. Games play moves this way; search packs them into MoveFields.
+    pub fn play(&mut self, mv: Move) {
+        let (from, to) = (mv.from_square(), mv.to_square());
+        let white = self.white_to_move(0);
+        let touched = 1u64 << from | 1u64 << to;
+        let lost = (0..4).filter(|&i| CASTLING_SQUARES[i] & touched != 0).fold(0, |lost, i| lost | 1 << i);
+        let rights = self.castling_rights(0) & !lost;
+        let double_push = self.pawns.extract(0) & 1 << from != 0 && from.abs_diff(to) == 16;
+
+        self.apply_move(&mv.to_field());
+        match mv.kind() {
+            Move::CASTLING => {
+                // The rook lands on the square the king crossed
+                let (rook_from, rook_to) = if to > from { (from + 3, from + 1) } else { (from - 4, from - 1) };
+                self.apply_move(&Move::new(rook_from, rook_to).to_field());
+                self.metadata ^= Lane::from_single(1 << META_TURN);
+            }
+            Move::EN_PASSANT => {
+                // The captured pawn is level with the mover's start square
+                self.clear(Lane::from_single(1 << if white { to - 8 } else { to + 8 }));
+            }
+            _ => {}
+        }
+        // apply_move made it a queen
+        if let Some(piece) = mv.promotion_piece() {
+            let bit = Lane::from_single(1 << to);
+            match piece {
+                1 => {
+                    self.sliders &= !bit;
+                    self.diagonal &= !bit;
+                    self.orthogonal &= !bit;
+                    self.leapers |= bit;
+                }
+                2 => self.orthogonal &= !bit,
+                3 => self.diagonal &= !bit,
+                _ => {}
+            }
+        }
+
+        let ep = if double_push { (from + to) / 2 } else { 0 };
+        let meta = rights << META_CASTLING | (ep as u64) << META_EP;
+        self.metadata = (self.metadata & Lane::from_single(!(CASTLING_MASK | EP_MASK))) | Lane::from_single(meta);
+    }
+
+    /// Plays a pseudo-legal move in each lane and passes the turn in all of them
     pub fn apply_move(&mut self, mv: &MoveField) {
+        #[cfg(target_arch = "x86_64")]
+        match crate::cpu::path() {
+            crate::cpu::Path::Avx512 => return unsafe { self.apply_move_avx512(mv) },
+            crate::cpu::Path::Avx2 => return unsafe { self.apply_move_avx2(mv) },
+            _ => {}
+        }
+        self.apply(mv)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx512f,avx512bw,avx512vl,avx2,popcnt,bmi1")]
+    unsafe fn apply_move_avx512(&mut self, mv: &MoveField) {
+        self.apply(mv)
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    #[target_feature(enable = "avx2,popcnt,bmi1")]
+    unsafe fn apply_move_avx2(&mut self, mv: &MoveField) {
+        self.apply(mv)
+    }
+
+    #[inline(always)]
+    fn apply(&mut self, mv: &MoveField) {
         let from = mv.from;
         let to = mv.to;
 
@@ -124,39 +315,50 @@ impl Board {
         let was_white = (self.white & from).is_not_zero_mask();
         let was_black = (self.black & from).is_not_zero_mask();
 
-        // Remove piece from 'from'
-        let not_from = !from;
-        self.pawns &= not_from;
-        self.leapers &= not_from;
-        self.sliders &= not_from;
-        self.kings &= not_from;
-        self.white &= not_from;
-        self.black &= not_from;
-        self.diagonal &= not_from;
-        self.orthogonal &= not_from;
-
-        // Remove captured piece from 'to'
-        let not_to = !to;
-        self.pawns &= not_to;
-        self.leapers &= not_to;
-        self.sliders &= not_to;
-        self.kings &= not_to;
-        self.white &= not_to;
-        self.black &= not_to;
-        self.diagonal &= not_to;
-        self.orthogonal &= not_to;
+        // Remove piece from 'from', and any captured piece from 'to'
+        self.clear(from | to);
+
+        // A pawn reaching the last rank becomes a queen; `play` makes the
+        // other promotions
+        let promoted = to & is_pawn & Lane::from_single(LAST_RANKS);
 
         // Place piece at 'to'
-        self.pawns |= to & is_pawn;
+        self.pawns |= (to & is_pawn) ^ promoted;
         self.leapers |= to & is_leaper;
-        self.sliders |= to & is_slider;
+        self.sliders |= (to & is_slider) | promoted;
         self.kings |= to & is_king;
         self.white |= to & was_white;
         self.black |= to & was_black;
-        self.diagonal |= to & is_diag;
-        self.orthogonal |= to & is_ortho;
+        self.diagonal |= (to & is_diag) | promoted;
+        self.orthogonal |= (to & is_ortho) | promoted;
 
-        // Update turn (flip bit)
-        self.metadata ^= Lane::from_single(1 << META_TURN);
+        // Update turn (flip bit); an en passant square lasts one move
+        self.metadata = (self.metadata & Lane::from_single(!EP_MASK)) ^ Lane::from_single(1 << META_TURN);
     }
+
+    #[inline(always)]
+    fn clear(&mut self, squares: Lane) {
+        let keep = !squares;
+        self.pawns &= keep;
+        self.leapers &= keep;
+        self.sliders &= keep;
+        self.kings &= keep;
+        self.white &= keep;
+        self.black &= keep;
+        self.diagonal &= keep;
+        self.orthogonal &= keep;
+    }
+}
+
+/// Square index of a name like "e4"
+pub fn parse_square(s: &str) -> Option<u32> {
+    let b = s.as_bytes();
+    if b.len() != 2 || !(b'a'..=b'h').contains(&b[0]) || !(b'1'..=b'8').contains(&b[1]) {
+        return None;
+    }
+    Some((b[1] - b'1') as u32 * 8 + (b[0] - b'a') as u32)
+}
+
+pub fn square_name(sq: u32) -> String {
+    format!("{}{}", (b'a' + (sq % 8) as u8) as char, sq / 8 + 1)
 }
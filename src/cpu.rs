@@ -0,0 +1,65 @@
+use std::sync::OnceLock;
+
+/// The code path the hot loops (movegen, eval, apply_move) run on, picked
+/// once from what the CPU supports
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Path {
+    // 512-bit registers with ternary logic and mask registers
+    Avx512,
+    // 256-bit registers, with popcnt and tzcnt
+    Avx2,
+    // Anything else, including x86_64 without AVX2, where the compiler still
+    // vectorizes the portable code 128 bits at a time
+    Scalar,
+}
+
+static PATH: OnceLock<Path> = OnceLock::new();
+
+impl Path {
+    pub fn name(self) -> &'static str {
+        match self {
+            Path::Avx512 => "AVX-512",
+            Path::Avx2 => "AVX2",
+            Path::Scalar => "scalar",
+        }
+    }
+}
+
+/// The path for this CPU
+pub fn path() -> Path {
+    *PATH.get_or_init(detect)
+}
+
+/// Fails on a CPU without the instructions the binary was built for (AVX2 or
+/// AVX-512 with -C target-cpu=native), which would otherwise SIGILL in the
+/// first Lane op; `init` checks this before running any.
+pub fn check() -> Result<(), String> {
+    let built_for = if cfg!(target_feature = "avx512f") { Path::Avx512 } else if cfg!(target_feature = "avx2") { Path::Avx2 } else { Path::Scalar };
+    let rank = |p: Path| match p {
+        Path::Avx512 => 2,
+        Path::Avx2 => 1,
+        Path::Scalar => 0,
+    };
+    if rank(path()) < rank(built_for) {
+        return Err(format!("this binary was built for {} but the CPU lacks it; rebuild without -C target-cpu", built_for.name()));
+    }
+    Ok(())
+}
+
+#[cfg(target_arch = "x86_64")]
+fn detect() -> Path {
+    let avx2 = is_x86_feature_detected!("avx2") && is_x86_feature_detected!("popcnt") && is_x86_feature_detected!("bmi1");
+    let avx512 = avx2 && is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("avx512bw") && is_x86_feature_detected!("avx512vl");
+    if avx512 {
+        Path::Avx512
+    } else if avx2 {
+        Path::Avx2
+    } else {
+        Path::Scalar
+    }
+}
+
+#[cfg(not(target_arch = "x86_64"))]
+fn detect() -> Path {
+    Path::Scalar
+}
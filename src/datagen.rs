@@ -0,0 +1,335 @@
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use crate::board::Board;
+use crate::movegen;
+use crate::nnue::{self, Network};
+use crate::search::{self, Limits};
+
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+// Scores past this are a forced king capture, which ends the game
+const DECISIVE_SCORE: i32 = 5000;
+const MAX_GAME_PLIES: usize = 400;
+// Quiet shuffling with a level score is called a draw
+const DRAW_SCORE: i32 = 10;
+const DRAW_PLIES: usize = 40;
+const MIN_DRAW_PLY: usize = 80;
+
+// Binary record: occupancy u64, then one nibble per occupied square in
+// ascending order (bit 3 = black, bits 0-2 = piece type), score i16 from
+// White's side, result u8 (0 = black win, 1 = draw, 2 = white win), side to move u8
+pub const RECORD_BYTES: usize = 8 + 16 + 2 + 1 + 1;
+
+struct Options {
+    games: usize,
+    threads: usize,
+    depth: i32,
+    nodes: Option<u64>,
+    random_plies: usize,
+    book: Option<String>,
+    net: Option<String>,
+    seed: u64,
+    text: Option<String>,
+    binary: Option<String>,
+}
+
+struct Position {
+    board: Board,
+    score: i32,
+}
+
+struct Game {
+    positions: Vec<Position>,
+    // From White's side: 1.0, 0.5 or 0.0
+    result: f64,
+    nodes: u64,
+}
+
+fn usage() {
+    eprintln!("usage: vesper datagen [--games N] [--threads N] [--depth D | --nodes N] [--random-plies N] \
+        [--book fens.txt] [--net file.nnue] [--seed N] [--text out.txt] [--binary out.bin]");
+}
+
+fn parse_options(args: &[String]) -> Option<Options> {
+    let mut options = Options {
+        games: 100,
+        threads: thread::available_parallelism().map_or(1, |n| n.get()),
+        depth: search::MAX_DEPTH,
+        nodes: Some(5000),
+        random_plies: 8,
+        book: None,
+        net: None,
+        seed: SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64),
+        text: None,
+        binary: None,
+    };
+
+    let mut i = 0;
+    while i < args.len() {
+        let value = args.get(i + 1)?;
+        match args[i].as_str() {
+            "--games" => options.games = value.parse().ok()?,
+            "--threads" => options.threads = value.parse::<usize>().ok()?.max(1),
+            "--depth" => {
+                options.depth = value.parse().ok()?;
+                options.nodes = None;
+            }
+            "--nodes" => options.nodes = Some(value.parse().ok()?),
+            "--random-plies" => options.random_plies = value.parse().ok()?,
+            "--book" => options.book = Some(value.clone()),
+            "--net" => options.net = Some(value.clone()),
+            "--seed" => options.seed = value.parse().ok()?,
+            "--text" => options.text = Some(value.clone()),
+            "--binary" => options.binary = Some(value.clone()),
+            _ => return None,
+        }
+        i += 2;
+    }
+
+    if options.text.is_none() && options.binary.is_none() {
+        options.text = Some("datagen.txt".to_string());
+    }
+    Some(options)
+}
+
+pub fn run(args: &[String]) {
+    let Some(options) = parse_options(args) else {
+        usage();
+        return;
+    };
+
+    if let Some(path) = &options.net {
+        match Network::load(path) {
+            Ok(net) => nnue::set_network(Some(Arc::new(net))),
+            Err(e) => {
+                eprintln!("cannot load {}: {}", path, e);
+                return;
+            }
+        }
+    }
+
+    let book: Vec<String> = match &options.book {
+        Some(path) => match fs::read_to_string(path) {
+            Ok(text) => text.lines().map(str::trim).filter(|l| !l.is_empty() && !l.starts_with('#')).map(String::from).collect(),
+            Err(e) => {
+                eprintln!("cannot read {}: {}", path, e);
+                return;
+            }
+        },
+        None => Vec::new(),
+    };
+
+    let open = |path: &Option<String>| -> Result<Option<BufWriter<File>>, String> {
+        path.as_ref()
+            .map(|p| File::create(p).map(BufWriter::new).map_err(|e| format!("cannot create {}: {}", p, e)))
+            .transpose()
+    };
+    let (mut text, mut binary) = match (open(&options.text), open(&options.binary)) {
+        (Ok(t), Ok(b)) => (t, b),
+        (Err(e), _) | (_, Err(e)) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+
+    let start = Instant::now();
+    let next_game = AtomicUsize::new(0);
+    let (sender, receiver) = mpsc::channel::<Game>();
+
+    thread::scope(|s| {
+        for t in 0..options.threads {
+            let sender = sender.clone();
+            let (options, book, next_game) = (&options, &book, &next_game);
+            s.spawn(move || {
+                let mut rng = Rng::new(options.seed ^ (t as u64 + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+                while next_game.fetch_add(1, Ordering::Relaxed) < options.games {
+                    let game = play_game(options, book, &mut rng);
+                    if sender.send(game).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        // Writing happens on this thread so records from one game stay together
+        let (mut games, mut positions, mut nodes) = (0, 0, 0);
+        for game in receiver {
+            for position in game.positions.iter() {
+                if let Some(out) = text.as_mut() {
+                    writeln!(out, "{}", text_record(&position.board, position.score, game.result)).unwrap();
+                }
+                if let Some(out) = binary.as_mut() {
+                    out.write_all(&binary_record(&position.board, position.score, game.result)).unwrap();
+                }
+            }
+            games += 1;
+            positions += game.positions.len();
+            nodes += game.nodes;
+            if games % 10 == 0 || games == options.games {
+                let seconds = start.elapsed().as_secs_f64();
+                println!("{} games, {} positions, {:.1}s, {:.0} nps", games, positions, seconds, nodes as f64 / seconds);
+            }
+        }
+    });
+
+    for out in [text.as_mut(), binary.as_mut()].into_iter().flatten() {
+        out.flush().unwrap();
+    }
+}
+
+fn play_game(options: &Options, book: &[String], rng: &mut Rng) -> Game {
+    let mut board = if book.is_empty() {
+        Board::from_fen(START_FEN)
+    } else {
+        Board::from_fen(&book[rng.below(book.len())])
+    };
+
+    // Random plies diversify the openings; losing a king to one starts over
+    for _ in 0..options.random_plies {
+        let moves = movegen::generate_move_list(&board, 0);
+        if moves.is_empty() {
+            break;
+        }
+        board.play(moves[rng.below(moves.len())]);
+        if winner(&board).is_some() {
+            return play_game(options, book, rng);
+        }
+    }
+
+    let limits = Limits { depth: options.depth, nodes: options.nodes, movetime: None, silent: true };
+    let mut positions = Vec::new();
+    let mut nodes = 0;
+    let mut quiet_plies = 0;
+
+    let result = 'game: {
+        for ply in 0..MAX_GAME_PLIES {
+            if let Some(white_won) = winner(&board) {
+                break 'game if white_won { 1.0 } else { 0.0 };
+            }
+
+            let found = search::search_with(board, &limits);
+            nodes += found.nodes;
+            if found.best_move.is_none() {
+                break 'game 0.5;
+            }
+            let white_score = if board.white_to_move(0) { found.score } else { -found.score };
+
+            if found.score.abs() >= DECISIVE_SCORE {
+                break 'game if white_score > 0 { 1.0 } else { 0.0 };
+            }
+            quiet_plies = if found.score.abs() <= DRAW_SCORE { quiet_plies + 1 } else { 0 };
+            if quiet_plies >= DRAW_PLIES && ply >= MIN_DRAW_PLY {
+                break 'game 0.5;
+            }
+
+            // Only quiet positions make useful training targets
+            let capture = board.occupied().extract(0) & 1 << found.best_move.to_square() != 0;
+            if !capture && !movegen::in_check(&board) {
+                positions.push(Position { board, score: white_score });
+            }
+            board.play(found.best_move);
+        }
+        0.5
+    };
+
+    Game { positions, result, nodes }
+}
+
+// Some(true) once Black's king has been captured, Some(false) for White's
+fn winner(board: &Board) -> Option<bool> {
+    let kings = board.kings.extract(0);
+    if kings & board.black.extract(0) == 0 {
+        Some(true)
+    } else if kings & board.white.extract(0) == 0 {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+// `fen | score | result`, with score and result from White's side
+pub fn text_record(board: &Board, score: i32, result: f64) -> String {
+    format!("{} | {} | {:.1}", board.fen(0), score, result)
+}
+
+pub fn binary_record(board: &Board, score: i32, result: f64) -> [u8; RECORD_BYTES] {
+    let mut record = [0u8; RECORD_BYTES];
+    let occupied = board.occupied().extract(0);
+    record[..8].copy_from_slice(&occupied.to_le_bytes());
+
+    let sets = board.piece_sets();
+    let black = board.black.extract(0);
+    let mut bb = occupied;
+    let mut i = 0;
+    while bb != 0 {
+        let bit = bb & bb.wrapping_neg();
+        let piece = sets.iter().position(|set| set.extract(0) & bit != 0).unwrap() as u8;
+        let nibble = piece | if black & bit != 0 { 8 } else { 0 };
+        record[8 + i / 2] |= nibble << ((i % 2) * 4);
+        bb &= bb - 1;
+        i += 1;
+    }
+
+    let score = score.clamp(i16::MIN as i32, i16::MAX as i32) as i16;
+    record[24..26].copy_from_slice(&score.to_le_bytes());
+    record[26] = (result * 2.0).round() as u8;
+    record[27] = !board.white_to_move(0) as u8;
+    record
+}
+
+// Inverse of `binary_record`, returning the position, score and result.
+// Records come from files, so anything `binary_record` can't have written
+// is an error rather than a panic.
+pub fn read_binary_record(record: &[u8]) -> Result<(Board, i32, f64), String> {
+    if record.len() != RECORD_BYTES {
+        return Err(format!("record of {} bytes", record.len()));
+    }
+    let occupied = u64::from_le_bytes(record[..8].try_into().unwrap());
+    // Two pieces per byte of the 16 that hold them
+    if occupied.count_ones() > 32 {
+        return Err(format!("{} occupied squares", occupied.count_ones()));
+    }
+
+    let mut pieces = [[0u64; 6]; 2];
+    let mut bb = occupied;
+    let mut i = 0;
+    while bb != 0 {
+        let bit = bb & bb.wrapping_neg();
+        let nibble = (record[8 + i / 2] >> ((i % 2) * 4)) & 0xf;
+        if nibble & 7 >= 6 {
+            return Err(format!("piece code {}", nibble));
+        }
+        pieces[(nibble >> 3) as usize][(nibble & 7) as usize] |= bit;
+        bb &= bb - 1;
+        i += 1;
+    }
+    if record[26] > 2 || record[27] > 1 {
+        return Err(format!("result {} and side to move {}", record[26], record[27]));
+    }
+
+    let board = Board::from_pieces(&pieces, record[27] == 0);
+    let score = i16::from_le_bytes([record[24], record[25]]) as i32;
+    Ok((board, score, record[26] as f64 / 2.0))
+}
+
+// xorshift64*, seeded per thread
+pub struct Rng(u64);
+
+impl Rng {
+    pub fn new(seed: u64) -> Self {
+        Self(seed | 1)
+    }
+
+    pub fn below(&mut self, n: usize) -> usize {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        (self.0.wrapping_mul(0x2545_f491_4f6c_dd1d) >> 32) as usize % n
+    }
+}
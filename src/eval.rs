@@ -1,50 +1,968 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::ops::{Add, AddAssign, Mul, Neg, Sub};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, OnceLock, RwLock};
+
 use crate::board::Board;
+use crate::kpk;
+use crate::lane::{Lane, LANES};
 use crate::movegen;
+use crate::params::{self, Params};
+use crate::pst::{self, PstTables};
+use crate::zobrist;
+
+pub const PHASE_MAX: i32 = 24;
+
+/// The built-in weights, which `evaluate` and `trace` use
+pub static DEFAULT_WEIGHTS: Weights = Weights {
+    params: params::DEFAULT,
+    tables: PstTables::new(&params::DEFAULT.pst_mg, &params::DEFAULT.pst_eg),
+    salt: 0,
+};
+
+static WEIGHTS: RwLock<Option<Arc<Weights>>> = RwLock::new(None);
+static NEXT_SALT: AtomicU64 = AtomicU64::new(1);
+
+// The weights `ParamsFile` points at, if any
+pub fn weights() -> Option<Arc<Weights>> {
+    WEIGHTS.read().unwrap().clone()
+}
+
+pub fn set_weights(weights: Option<Arc<Weights>>) {
+    *WEIGHTS.write().unwrap() = weights;
+}
+
+const PAWN_TABLE_SIZE: usize = 16384;
+
+#[derive(Clone, Copy, Default)]
+struct PawnEntry {
+    key: u64,
+    scores: [Score; 2],
+    passed: [u64; 2],
+}
+
+thread_local! {
+    static PAWN_TABLE: RefCell<Vec<PawnEntry>> = RefCell::new(vec![PawnEntry::default(); PAWN_TABLE_SIZE]);
+    static PAWN_HITS: Cell<u64> = const { Cell::new(0) };
+}
+
+// A middlegame/endgame score pair
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Score {
+    pub mg: i32,
+    pub eg: i32,
+}
+
+impl Score {
+    pub const fn new(mg: i32, eg: i32) -> Self {
+        Self { mg, eg }
+    }
+}
+
+impl Add for Score {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.mg + rhs.mg, self.eg + rhs.eg)
+    }
+}
+
+impl AddAssign for Score {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl Sub for Score {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        Self::new(self.mg - rhs.mg, self.eg - rhs.eg)
+    }
+}
+
+impl Neg for Score {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Self::new(-self.mg, -self.eg)
+    }
+}
+
+impl Mul<i32> for Score {
+    type Output = Self;
+    fn mul(self, rhs: i32) -> Self {
+        Self::new(self.mg * rhs, self.eg * rhs)
+    }
+}
+
+// Every evaluation term for one lane, indexed [white, black] where per side.
+// `evaluate` and `trace` both reduce this, so they can never disagree.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Trace {
+    pub material: [Score; 2],
+    pub pst: [Score; 2],
+    pub mobility: [Score; 2],
+    pub pawns: [Score; 2],
+    pub passed_king: [Score; 2],
+    pub king_attack: [Score; 2],
+    pub king_shield: [Score; 2],
+    pub king_storm: [Score; 2],
+    pub king_files: [Score; 2],
+    pub tension: i32,
+    pub tempo: i32,
+    pub phase: i32,
+    pub white_turn: bool,
+    // Specialised endgame rule, with the white-relative value it replaces the
+    // evaluation with, if any, and the endgame scale factor out of SCALE_NORMAL
+    pub endgame: Option<&'static str>,
+    pub endgame_value: Option<i32>,
+    pub scale: i32,
+}
+
+impl Trace {
+    pub fn rows(&self) -> [(&'static str, [Score; 2]); 9] {
+        [
+            ("Material", self.material),
+            ("PST", self.pst),
+            ("Mobility", self.mobility),
+            ("Pawns", self.pawns),
+            ("Passed/king", self.passed_king),
+            ("King attack", self.king_attack),
+            ("King shield", self.king_shield),
+            ("King storm", self.king_storm),
+            ("King files", self.king_files),
+        ]
+    }
+
+    // White minus black, before tapering
+    pub fn total(&self) -> Score {
+        self.rows().iter().fold(Score::default(), |acc, (_, s)| acc + s[0] - s[1])
+    }
+
+    // Final score relative to the side to move
+    pub fn score(&self) -> i32 {
+        if let Some(value) = self.endgame_value {
+            return if self.white_turn { value } else { -value };
+        }
+        let total = self.total();
+        let white_score = taper(total.mg, total.eg * self.scale / SCALE_NORMAL, self.phase);
+        let score = if self.white_turn { white_score } else { -white_score };
+        score + self.tempo + self.tension
+    }
+}
+
+impl fmt::Display for Trace {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "        Term |     White     |     Black     |     Total")?;
+        writeln!(f, "             |   MG     EG   |   MG     EG   |   MG     EG")?;
+        writeln!(f, "-------------+---------------+---------------+---------------")?;
+        for (name, [w, b]) in self.rows() {
+            let t = w - b;
+            writeln!(f, "{:>12} | {:>5}  {:>5}  | {:>5}  {:>5}  | {:>5}  {:>5}",
+                name, w.mg, w.eg, b.mg, b.eg, t.mg, t.eg)?;
+        }
+        writeln!(f, "-------------+---------------+---------------+---------------")?;
+        let total = self.total();
+        writeln!(f, "{:>12} |               |               | {:>5}  {:>5}", "Total", total.mg, total.eg)?;
+        writeln!(f)?;
+        writeln!(f, "Phase: {}/{}", self.phase, PHASE_MAX)?;
+        writeln!(f, "Endgame scale: {}/{}", self.scale, SCALE_NORMAL)?;
+        writeln!(f, "Tapered (white): {}", taper(total.mg, total.eg * self.scale / SCALE_NORMAL, self.phase))?;
+        writeln!(f, "Side to move: {}", if self.white_turn { "white" } else { "black" })?;
+        writeln!(f, "Tempo: {}", self.tempo)?;
+        writeln!(f, "Tension: {}", self.tension)?;
+        if let Some(name) = self.endgame {
+            match self.endgame_value {
+                Some(value) => writeln!(f, "Endgame: {} (white: {})", name, value)?,
+                None => writeln!(f, "Endgame: {}", name)?,
+            }
+        }
+        write!(f, "Final (side to move): {}", self.score())
+    }
+}
+
+/// Evaluation parameters with their PSTs sliced for `pst::evaluate`, as
+/// `ParamsFile` loads them
+pub struct Weights {
+    pub params: Params,
+    tables: PstTables,
+    // Mixed into pawn hash keys, so entries scored with other params miss
+    salt: u64,
+}
+
+impl Weights {
+    pub fn new(params: Params) -> Self {
+        let salt = NEXT_SALT.fetch_add(1, Ordering::Relaxed).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+        Self { tables: PstTables::new(&params.pst_mg, &params.pst_eg), params, salt }
+    }
+
+    /// Params in the text format `tune` writes
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Params::from_text(&text).map(Self::new)
+    }
+
+    /// Classical evaluation of each lane, from the side to move
+    pub fn evaluate(&self, board: &Board) -> [i32; LANES] {
+        #[cfg(target_arch = "x86_64")]
+        match crate::cpu::path() {
+            crate::cpu::Path::Avx512 => return unsafe { evaluate_avx512(board, self) },
+            crate::cpu::Path::Avx2 => return unsafe { evaluate_avx2(board, self) },
+            _ => {}
+        }
+        evaluate_terms(board, &self.params, &self.tables, Some(self)).map(|t| t.score())
+    }
+
+    /// Term breakdown for lane 0
+    pub fn trace(&self, board: &Board) -> Trace {
+        evaluate_terms(board, &self.params, &self.tables, Some(self))[0]
+    }
 
-pub fn evaluate(board: &Board) -> [i32; 4] {
-    let mut scores = [0i32; 4];
+    /// `pawn_structure` through this thread's pawn hash table
+    #[inline(always)]
+    pub fn pawn_structure(&self, board: &Board) -> ([[Score; 2]; LANES], [[Score; 2]; LANES]) {
+        let mut scores = [[Score::default(); 2]; LANES];
+        let mut passed = [[0u64; 2]; LANES];
+
+        PAWN_TABLE.with(|table| {
+            let mut table = table.borrow_mut();
+            let mut keys = [0u64; LANES];
+            let mut misses = [false; LANES];
+
+            for i in 0..LANES {
+                keys[i] = zobrist::pawn_key(board, i) ^ self.salt;
+                let entry = &table[keys[i] as usize % PAWN_TABLE_SIZE];
+                if entry.key == keys[i] {
+                    scores[i] = entry.scores;
+                    passed[i] = entry.passed;
+                } else {
+                    misses[i] = true;
+                }
+            }
+            PAWN_HITS.with(|hits| hits.set(hits.get() + misses.iter().filter(|&&m| !m).count() as u64));
+
+            if misses.iter().any(|&m| m) {
+                let (fresh, fresh_passed) = score_pawns(board, &self.params);
+                for i in (0..LANES).filter(|&i| misses[i]) {
+                    scores[i] = fresh[i];
+                    passed[i] = fresh_passed[i];
+                    table[keys[i] as usize % PAWN_TABLE_SIZE] = PawnEntry {
+                        key: keys[i],
+                        scores: scores[i],
+                        passed: passed[i],
+                    };
+                }
+            }
+        });
+
+        (scores, passed_king_proximity(board, &passed, &self.params))
+    }
+}
+
+/// Classical evaluation of each lane with the built-in weights, from the side to move
+pub fn evaluate(board: &Board) -> [i32; LANES] {
+    DEFAULT_WEIGHTS.evaluate(board)
+}
+
+/// `evaluate` with other params, slicing their PSTs and scoring pawns afresh
+/// on every call. For tools and tests; searches load `Weights` once.
+pub fn evaluate_with(board: &Board, params: &Params) -> [i32; LANES] {
+    let tables = PstTables::new(&params.pst_mg, &params.pst_eg);
+    evaluate_terms(board, params, &tables, None).map(|t| t.score())
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f,avx512bw,avx512vl,avx2,popcnt,bmi1")]
+unsafe fn evaluate_avx512(board: &Board, weights: &Weights) -> [i32; LANES] {
+    evaluate_terms(board, &weights.params, &weights.tables, Some(weights)).map(|t| t.score())
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,popcnt,bmi1")]
+unsafe fn evaluate_avx2(board: &Board, weights: &Weights) -> [i32; LANES] {
+    evaluate_terms(board, &weights.params, &weights.tables, Some(weights)).map(|t| t.score())
+}
+
+/// Term breakdown for lane 0 with the built-in weights
+pub fn trace(board: &Board) -> Trace {
+    DEFAULT_WEIGHTS.trace(board)
+}
+
+#[inline(always)]
+// Pawns go through the pawn hash table of `cache`, if given
+fn evaluate_terms(board: &Board, params: &Params, tables: &PstTables, cache: Option<&Weights>) -> [Trace; LANES] {
+    let mut traces = [Trace::default(); LANES];
 
     // Mobility plane (parallel)
-    let white_attacks = movegen::get_attacks(board, board.white, true);
-    let black_attacks = movegen::get_attacks(board, board.black, false);
+    let white_map = movegen::get_attacks(board, board.white, true);
+    let black_map = movegen::get_attacks(board, board.black, false);
+    let white_attacks = white_map.all();
+    let black_attacks = black_map.all();
 
-    for i in 0..4 {
-        let mut score = 0;
+    let king = [king_safety(board, &black_map, true, params), king_safety(board, &white_map, false, params)];
+    let pst = [pst::evaluate(board, true, tables), pst::evaluate(board, false, tables)];
+    let (pawns, passed_king) = match cache {
+        Some(weights) => weights.pawn_structure(board),
+        None => pawn_structure(board, params),
+    };
+    let phase = game_phase(board);
 
-        let w_pawns = (board.pawns & board.white).extract(i).count_ones() as i32;
-        let b_pawns = (board.pawns & board.black).extract(i).count_ones() as i32;
-        let w_leapers = (board.leapers & board.white).extract(i).count_ones() as i32;
-        let b_leapers = (board.leapers & board.black).extract(i).count_ones() as i32;
-        let w_sliders = (board.sliders & board.white).extract(i).count_ones() as i32;
-        let b_sliders = (board.sliders & board.black).extract(i).count_ones() as i32;
-        let w_kings = (board.kings & board.white).extract(i).count_ones() as i32;
-        let b_kings = (board.kings & board.black).extract(i).count_ones() as i32;
+    let sets = [
+        (board.pawns, params.pawn),
+        (board.leapers, params.knight),
+        (board.bishops(), params.bishop),
+        (board.rooks(), params.rook),
+        (board.queens(), params.queen),
+        (board.kings, params.king),
+    ];
+
+    // The counting terms for every lane at once
+    let material = [board.white, board.black].map(|side| {
+        let mut total = Lane::EMPTY;
+        for (set, value) in sets.iter() {
+            total += (*set & side).popcount().mul_const(*value as i64);
+        }
+        total.to_i32s()
+    });
+    let mobility = [white_attacks, black_attacks].map(|attacks| attacks.popcount().mul_const(params.mobility as i64).to_i32s());
+    let tension = (white_attacks & black_attacks).popcount().mul_const(params.tension as i64).to_i32s();
 
-        score += (w_pawns - b_pawns) * 100;
-        score += (w_leapers - b_leapers) * 300;
-        score += (w_sliders - b_sliders) * 500;
-        score += (w_kings - b_kings) * 10000;
+    for (i, t) in traces.iter_mut().enumerate() {
+        for color in 0..2 {
+            t.material[color] = Score::new(material[color][i], material[color][i]);
+            t.pst[color] = Score::new(pst[color].0[i], pst[color].1[i]);
+            t.mobility[color] = Score::new(mobility[color][i], mobility[color][i]);
+
+            t.pawns[color] = pawns[i][color];
+            t.passed_king[color] = passed_king[i][color];
+
+            // King safety only matters while there is material to attack with
+            let k = king[color][i];
+            t.king_attack[color] = Score::new(k.attack, 0);
+            t.king_shield[color] = Score::new(k.shield, 0);
+            t.king_storm[color] = Score::new(k.storm, 0);
+            t.king_files[color] = Score::new(k.open_files, 0);
+        }
 
-        // Mobility
-        let w_mobility = white_attacks.extract(i).count_ones() as i32;
-        let b_mobility = black_attacks.extract(i).count_ones() as i32;
-        score += (w_mobility - b_mobility) * 5;
+        t.phase = phase[i];
+        t.white_turn = (board.metadata.extract(i) & (1 << crate::board::META_TURN)) == 0;
+        t.tempo = params.tempo;
 
-        // Tension
-        let tension = (white_attacks & black_attacks).extract(i).count_ones() as i32;
-        score += tension * 2;
+        // Tension favours the side to move, who gets to resolve it first
+        t.tension = tension[i];
 
-        // Side to move bonus
-        let meta = board.metadata.extract(i);
-        let white_turn = (meta & (1 << crate::board::META_TURN)) == 0;
-        if white_turn {
-            score += 10;
-        } else {
-            score -= 10;
+        t.scale = SCALE_NORMAL;
+        if t.phase <= ENDGAME_PHASE {
+            (t.endgame, t.endgame_value, t.scale) = endgame(board, i, params);
         }
+    }
+
+    traces
+}
+
+// Game phase from non-pawn material: PHASE_MAX at the start, 0 with bare kings and pawns
+#[inline(always)]
+pub fn game_phase(board: &Board) -> [i32; LANES] {
+    let minors = (board.leapers | board.bishops()).popcount();
+    let rooks = board.rooks().popcount().mul_const(2);
+    let queens = board.queens().popcount().mul_const(4);
+    (minors + rooks + queens).min(Lane::from_single(PHASE_MAX as u64)).to_i32s()
+}
+
+pub fn taper(mg: i32, eg: i32, phase: i32) -> i32 {
+    (mg * phase + eg * (PHASE_MAX - phase)) / PHASE_MAX
+}
 
-        scores[i] = score;
+struct PawnMasks {
+    doubled: Lane,
+    isolated: Lane,
+    backward: Lane,
+    connected: Lane,
+    phalanx: Lane,
+    passed: Lane,
+}
+
+#[inline(always)]
+fn forward_span(pawns: Lane, white: bool) -> Lane {
+    let full = Lane::from_single(u64::MAX);
+    if white { pawns.shift_north().fill_north(full) } else { pawns.shift_south().fill_south(full) }
+}
+
+#[inline(always)]
+fn pawn_attacks(pawns: Lane, white: bool) -> Lane {
+    if white {
+        pawns.shift_north_east() | pawns.shift_north_west()
+    } else {
+        pawns.shift_south_east() | pawns.shift_south_west()
+    }
+}
+
+// Pawn classification for one side, computed for all lanes at once
+#[inline(always)]
+fn pawn_masks(ours: Lane, theirs: Lane, white: bool) -> PawnMasks {
+    let full = Lane::from_single(u64::MAX);
+
+    let behind_ours = forward_span(ours, !white);
+    let their_front = forward_span(theirs, !white);
+    let files = ours.fill_north(full) | ours.fill_south(full);
+    let adjacent = ours.shift_east() | ours.shift_west();
+    let support_span = adjacent | forward_span(adjacent, white);
+
+    let stop_attacked = if white {
+        pawn_attacks(theirs, false).shift_south()
+    } else {
+        pawn_attacks(theirs, true).shift_north()
+    };
+
+    let isolated = ours & !(files.shift_east() | files.shift_west());
+
+    PawnMasks {
+        doubled: ours & behind_ours,
+        isolated,
+        backward: ours & !support_span & !isolated & stop_attacked,
+        connected: ours & pawn_attacks(ours, white),
+        phalanx: ours & adjacent,
+        passed: ours & !(their_front | their_front.shift_east() | their_front.shift_west()) & !behind_ours,
+    }
+}
+
+#[inline(always)]
+fn score_pawn_masks(masks: &PawnMasks, lane: usize, white: bool, params: &Params) -> Score {
+    let count = |l: Lane| l.extract(lane).count_ones() as i32;
+    let mut score = params.doubled * count(masks.doubled)
+        + params.isolated * count(masks.isolated)
+        + params.backward * count(masks.backward)
+        + params.connected * count(masks.connected)
+        + params.phalanx * count(masks.phalanx);
+
+    let mut passed = masks.passed.extract(lane);
+    while passed != 0 {
+        let rank = relative_rank(passed.trailing_zeros(), white);
+        score += params.passed[rank];
+        passed &= passed - 1;
     }
 
-    scores
+    score
+}
+
+fn relative_rank(sq: u32, white: bool) -> usize {
+    let rank = (sq / 8) as usize;
+    if white { rank } else { 7 - rank }
+}
+
+fn distance(a: u32, b: u32) -> i32 {
+    let df = (a % 8) as i32 - (b % 8) as i32;
+    let dr = (a / 8) as i32 - (b / 8) as i32;
+    df.abs().max(dr.abs())
+}
+
+// Per-side pawn structure scores and the king proximity to passed pawns,
+// computed from scratch; `Weights::pawn_structure` caches the first part
+pub fn pawn_structure(board: &Board, params: &Params) -> ([[Score; 2]; LANES], [[Score; 2]; LANES]) {
+    let (scores, passed) = score_pawns(board, params);
+    (scores, passed_king_proximity(board, &passed, params))
+}
+
+// Pawn hash table hits on this thread so far
+pub fn pawn_table_hits() -> u64 {
+    PAWN_HITS.with(|hits| hits.get())
+}
+
+#[inline(always)]
+fn score_pawns(board: &Board, params: &Params) -> ([[Score; 2]; LANES], [[u64; 2]; LANES]) {
+    let white = pawn_masks(board.pawns & board.white, board.pawns & board.black, true);
+    let black = pawn_masks(board.pawns & board.black, board.pawns & board.white, false);
+    let scores = std::array::from_fn(|i| [score_pawn_masks(&white, i, true, params), score_pawn_masks(&black, i, false, params)]);
+    let passed = std::array::from_fn(|i| [white.passed.extract(i), black.passed.extract(i)]);
+    (scores, passed)
+}
+
+// King proximity to passed pawns depends on more than the pawns, so it is not cached
+#[inline(always)]
+fn passed_king_proximity(board: &Board, passed: &[[u64; 2]; LANES], params: &Params) -> [[Score; 2]; LANES] {
+    let mut proximity = [[Score::default(); 2]; LANES];
+    for (i, out) in proximity.iter_mut().enumerate() {
+        let kings = [
+            (board.kings & board.white).extract(i),
+            (board.kings & board.black).extract(i),
+        ];
+        if kings[0] == 0 || kings[1] == 0 {
+            continue;
+        }
+
+        for color in 0..2 {
+            let white = color == 0;
+            let own_king = kings[color].trailing_zeros();
+            let enemy_king = kings[1 - color].trailing_zeros();
+
+            let mut p = passed[i][color];
+            while p != 0 {
+                let sq = p.trailing_zeros();
+                let weight = relative_rank(sq, white) as i32 - 2;
+                // A pawn on the last rank (only in a made-up FEN) has no stop square
+                if weight > 0 && relative_rank(sq, white) < 7 {
+                    let stop = if white { sq + 8 } else { sq - 8 };
+                    out[color].eg += weight * (params.passed_enemy_king * distance(enemy_king, stop)
+                        - params.passed_own_king * distance(own_king, stop));
+                }
+                p &= p - 1;
+            }
+        }
+    }
+    proximity
+}
+
+// King safety terms for one side, middlegame only, from that side's perspective
+#[derive(Clone, Copy, Debug, Default)]
+pub struct KingSafety {
+    pub attack: i32,
+    pub shield: i32,
+    pub storm: i32,
+    pub open_files: i32,
+}
+
+// Raw counts behind the king safety terms, shared with the tuner's features
+#[derive(Clone, Copy, Debug, Default)]
+pub struct KingCounts {
+    // Safety table index, or None when fewer than two attacker types hit the zone
+    pub safety_index: Option<usize>,
+    pub shield_near: i32,
+    pub shield_far: i32,
+    pub storm: i32,
+    pub semi_open_files: i32,
+    pub open_files: i32,
+}
+
+#[inline(always)]
+pub fn king_counts(board: &Board, enemy: &movegen::AttackMap, white: bool, params: &Params) -> [KingCounts; LANES] {
+    let mut counts = [KingCounts::default(); LANES];
+
+    let (us, them) = if white { (board.white, board.black) } else { (board.black, board.white) };
+    let king = board.kings & us;
+    let own_pawns = board.pawns & us;
+    let enemy_pawns = board.pawns & them;
+
+    let ring = king | king.king_attacks();
+    let (zone, near) = if white {
+        (ring | ring.shift_north(), (king | king.shift_east() | king.shift_west()).shift_north())
+    } else {
+        (ring | ring.shift_south(), (king | king.shift_east() | king.shift_west()).shift_south())
+    };
+    let far = if white { near.shift_north() } else { near.shift_south() };
+    let storm_zone = near | far | if white { far.shift_north() } else { far.shift_south() };
+
+    let full = Lane::from_single(u64::MAX);
+    let king_files = king.fill_north(full) | king.fill_south(full);
+    let files = king_files | king_files.shift_east() | king_files.shift_west();
+
+    let attackers = [
+        (enemy.knights & zone, params.attack_units[0]),
+        (enemy.bishops & zone, params.attack_units[1]),
+        (enemy.rooks & zone, params.attack_units[2]),
+        (enemy.queens & zone, params.attack_units[3]),
+    ];
+    let shield_near = own_pawns & near;
+    let shield_far = own_pawns & far;
+    let storm = enemy_pawns & storm_zone;
+
+    for (i, out) in counts.iter_mut().enumerate() {
+        if king.extract(i) == 0 {
+            continue;
+        }
+
+        let mut units = 0;
+        let mut attacker_types = 0;
+        for (attacks, weight) in attackers.iter() {
+            let n = attacks.extract(i).count_ones() as i32;
+            if n > 0 {
+                units += n * weight;
+                attacker_types += 1;
+            }
+        }
+        // A lone attacker is rarely dangerous
+        if attacker_types >= 2 {
+            out.safety_index = Some((units.max(0) as usize).min(99));
+        }
+
+        out.shield_near = shield_near.extract(i).count_ones() as i32;
+        out.shield_far = shield_far.extract(i).count_ones() as i32;
+        out.storm = storm.extract(i).count_ones() as i32;
+
+        let file_mask = files.extract(i);
+        let own = own_pawns.extract(i);
+        let enemy_p = enemy_pawns.extract(i);
+        for file in 0..8 {
+            let mask = 0x0101010101010101u64 << file;
+            if mask & file_mask == 0 || mask & own != 0 {
+                continue;
+            }
+            if mask & enemy_p == 0 {
+                out.open_files += 1;
+            } else {
+                out.semi_open_files += 1;
+            }
+        }
+    }
+
+    counts
+}
+
+#[inline(always)]
+pub fn king_safety(board: &Board, enemy: &movegen::AttackMap, white: bool, params: &Params) -> [KingSafety; LANES] {
+    king_counts(board, enemy, white, params).map(|c| KingSafety {
+        attack: c.safety_index.map_or(0, |units| -params.safety[units]),
+        shield: c.shield_near * params.shield_near + c.shield_far * params.shield_far,
+        storm: c.storm * params.storm,
+        open_files: c.open_files * params.open_file + c.semi_open_files * params.semi_open_file,
+    })
+}
+
+// One linear coefficient of the evaluation. Summed over all features, the
+// white-relative score is taper(sum(mg * param), sum(eg * param), phase).
+#[derive(Clone, Copy, Debug)]
+pub struct Feature {
+    pub index: u16,
+    pub mg: i16,
+    pub eg: i16,
+}
+
+// Linear features of lane 0 against the flat `Params` vector, plus its game phase.
+// King safety is only linear in the safety table, so attack units stay fixed.
+pub fn features(board: &Board, params: &Params) -> (Vec<Feature>, i32) {
+    let mut coefs: Vec<(usize, i32, i32)> = Vec::new();
+
+    let maps = [
+        movegen::get_attacks(board, board.white, true),
+        movegen::get_attacks(board, board.black, false),
+    ];
+    let masks = [
+        pawn_masks(board.pawns & board.white, board.pawns & board.black, true),
+        pawn_masks(board.pawns & board.black, board.pawns & board.white, false),
+    ];
+    let kings = [
+        king_counts(board, &maps[1], true, params)[0],
+        king_counts(board, &maps[0], false, params)[0],
+    ];
+    let king_squares = [
+        (board.kings & board.white).extract(0),
+        (board.kings & board.black).extract(0),
+    ];
+    let sets = board.piece_sets();
+
+    for (color, side) in [board.white, board.black].iter().enumerate() {
+        let white = color == 0;
+        let sign = if white { 1 } else { -1 };
+
+        for (p, set) in sets.iter().enumerate() {
+            let mut pieces = (*set & *side).extract(0);
+            let n = pieces.count_ones() as i32;
+            coefs.push((params::MATERIAL + p, sign * n, sign * n));
+            while pieces != 0 {
+                let sq = pieces.trailing_zeros() as usize;
+                let index = if white { sq ^ 56 } else { sq };
+                coefs.push((params::PST_MG + p * 64 + index, sign, 0));
+                coefs.push((params::PST_EG + p * 64 + index, 0, sign));
+                pieces &= pieces - 1;
+            }
+        }
+
+        let mobility = maps[color].all().extract(0).count_ones() as i32;
+        coefs.push((params::MOBILITY, sign * mobility, sign * mobility));
+
+        let m = &masks[color];
+        let pawn_terms = [
+            (params::DOUBLED, m.doubled),
+            (params::ISOLATED, m.isolated),
+            (params::BACKWARD, m.backward),
+            (params::CONNECTED, m.connected),
+            (params::PHALANX, m.phalanx),
+        ];
+        for (index, mask) in pawn_terms {
+            let n = mask.extract(0).count_ones() as i32;
+            coefs.push((index, sign * n, 0));
+            coefs.push((index + 1, 0, sign * n));
+        }
+
+        let mut passed = m.passed.extract(0);
+        while passed != 0 {
+            let sq = passed.trailing_zeros();
+            let rank = relative_rank(sq, white);
+            coefs.push((params::PASSED + 2 * rank, sign, 0));
+            coefs.push((params::PASSED + 2 * rank + 1, 0, sign));
+
+            let weight = rank as i32 - 2;
+            if weight > 0 && king_squares[0] != 0 && king_squares[1] != 0 {
+                let stop = if white { sq + 8 } else { sq - 8 };
+                let own_king = king_squares[color].trailing_zeros();
+                let enemy_king = king_squares[1 - color].trailing_zeros();
+                coefs.push((params::PASSED_ENEMY_KING, 0, sign * weight * distance(enemy_king, stop)));
+                coefs.push((params::PASSED_OWN_KING, 0, -sign * weight * distance(own_king, stop)));
+            }
+            passed &= passed - 1;
+        }
+
+        let k = &kings[color];
+        if let Some(units) = k.safety_index {
+            coefs.push((params::SAFETY + units, -sign, 0));
+        }
+        coefs.push((params::SHIELD_NEAR, sign * k.shield_near, 0));
+        coefs.push((params::SHIELD_FAR, sign * k.shield_far, 0));
+        coefs.push((params::STORM, sign * k.storm, 0));
+        coefs.push((params::SEMI_OPEN_FILE, sign * k.semi_open_files, 0));
+        coefs.push((params::OPEN_FILE, sign * k.open_files, 0));
+    }
+
+    // Side-to-move terms are untapered, which equal mg and eg coefficients reproduce
+    let white_turn = (board.metadata.extract(0) & (1 << crate::board::META_TURN)) == 0;
+    let stm = if white_turn { 1 } else { -1 };
+    let tension = (maps[0].all() & maps[1].all()).extract(0).count_ones() as i32;
+    coefs.push((params::TEMPO, stm, stm));
+    coefs.push((params::TENSION, stm * tension, stm * tension));
+
+    coefs.sort_by_key(|c| c.0);
+    let mut features: Vec<Feature> = Vec::new();
+    for (index, mg, eg) in coefs {
+        match features.last_mut() {
+            Some(f) if f.index as usize == index => {
+                f.mg += mg as i16;
+                f.eg += eg as i16;
+            }
+            _ => features.push(Feature { index: index as u16, mg: mg as i16, eg: eg as i16 }),
+        }
+    }
+    features.retain(|f| f.mg != 0 || f.eg != 0);
+
+    (features, game_phase(board)[0])
+}
+
+pub const SCALE_NORMAL: i32 = 64;
+// Specialised endgames are only looked up once this little material is left
+const ENDGAME_PHASE: i32 = 8;
+const KNOWN_WIN: i32 = 2000;
+
+// Scores are from the strong side's point of view
+#[derive(Clone, Copy)]
+enum EndgameKind {
+    // Replaces the whole evaluation
+    Value(fn(&EndgamePosition, &Params) -> i32),
+    // Scales the endgame half of the evaluation, out of SCALE_NORMAL
+    Scale(fn(&EndgamePosition, &Params) -> i32),
+}
+
+#[derive(Clone, Copy)]
+struct Endgame {
+    name: &'static str,
+    strong: usize,
+    kind: EndgameKind,
+}
+
+// One lane's pieces, indexed [color][piece type]
+struct EndgamePosition {
+    pieces: [[u64; 6]; 2],
+    strong: usize,
+    white_to_move: bool,
+}
+
+impl EndgamePosition {
+    fn weak(&self) -> usize {
+        1 - self.strong
+    }
+
+    fn king(&self, color: usize) -> u32 {
+        self.pieces[color][5].trailing_zeros()
+    }
+
+    fn count(&self, color: usize, piece: usize) -> i32 {
+        self.pieces[color][piece].count_ones() as i32
+    }
+
+    fn material(&self, color: usize, params: &Params) -> i32 {
+        let values = [params.pawn, params.knight, params.bishop, params.rook, params.queen];
+        values.iter().enumerate().map(|(p, v)| self.count(color, p) * v).sum()
+    }
+}
+
+// Strong side first, e.g. "KBNvK"
+const ENDGAMES: [(&str, EndgameKind); 11] = [
+    ("KvK", EndgameKind::Value(draw)),
+    ("KNvK", EndgameKind::Value(draw)),
+    ("KBvK", EndgameKind::Value(draw)),
+    ("KNNvK", EndgameKind::Value(draw)),
+    ("KPvK", EndgameKind::Value(kpk)),
+    ("KBNvK", EndgameKind::Value(kbnk)),
+    ("KRvK", EndgameKind::Value(kxk)),
+    ("KQvK", EndgameKind::Value(kxk)),
+    ("KBPvK", EndgameKind::Scale(wrong_bishop)),
+    ("KBPPvK", EndgameKind::Scale(wrong_bishop)),
+    ("KBPPPvK", EndgameKind::Scale(wrong_bishop)),
+];
+
+static ENDGAME_REGISTRY: OnceLock<HashMap<u64, Endgame>> = OnceLock::new();
+
+// Piece counts other than kings, 4 bits per type and 20 bits per side. Also
+// names Syzygy tables.
+pub(crate) fn material_key(counts: &[[u32; 5]; 2]) -> u64 {
+    let mut key = 0;
+    for (color, side) in counts.iter().enumerate() {
+        for (p, n) in side.iter().enumerate() {
+            key |= (*n as u64).min(15) << (color * 20 + p * 4);
+        }
+    }
+    key
+}
+
+fn code_counts(code: &str, strong: usize) -> [[u32; 5]; 2] {
+    let (a, b) = code.split_once('v').unwrap();
+    let mut counts = [[0; 5]; 2];
+    for (color, side) in [(strong, a), (1 - strong, b)] {
+        for c in side.chars().filter(|&c| c != 'K') {
+            counts[color]["PNBRQ".find(c).unwrap()] += 1;
+        }
+    }
+    counts
+}
+
+fn endgame_registry() -> &'static HashMap<u64, Endgame> {
+    ENDGAME_REGISTRY.get_or_init(|| {
+        let mut registry = HashMap::new();
+        for (name, kind) in ENDGAMES {
+            for strong in 0..2 {
+                registry.insert(material_key(&code_counts(name, strong)), Endgame { name, strong, kind });
+            }
+        }
+        registry
+    })
+}
+
+fn find_endgame(pos: &mut EndgamePosition) -> Option<Endgame> {
+    let mut counts = [[0u32; 5]; 2];
+    for (color, side) in counts.iter_mut().enumerate() {
+        for (p, n) in side.iter_mut().enumerate() {
+            *n = pos.pieces[color][p].count_ones();
+        }
+    }
+
+    let found = endgame_registry().get(&material_key(&counts)).copied().or_else(|| {
+        // Bare king against enough pawnless material to mate, whatever the mix
+        (0..2).find(|&strong| {
+            counts[1 - strong].iter().all(|&n| n == 0)
+                && counts[strong][0] == 0
+                && (counts[strong][3] + counts[strong][4] > 0 || counts[strong][1] + counts[strong][2] >= 3)
+        }).map(|strong| Endgame { name: "KXvK", strong, kind: EndgameKind::Value(kxk) })
+    }).or_else(|| {
+        let bishops = [pos.pieces[0][2], pos.pieces[1][2]];
+        let opposite = bishops.iter().all(|b| b.count_ones() == 1)
+            && ((bishops[0] & DARK_SQUARES != 0) != (bishops[1] & DARK_SQUARES != 0));
+        opposite.then_some(Endgame { name: "Opposite bishops", strong: 0, kind: EndgameKind::Scale(opposite_bishops) })
+    });
+
+    if let Some(e) = found {
+        pos.strong = e.strong;
+    }
+    found
+}
+
+const DARK_SQUARES: u64 = 0xaa55_aa55_aa55_aa55;
+
+fn push_to_edge(sq: u32) -> i32 {
+    let file = (sq % 8).min(7 - sq % 8) as i32;
+    let rank = (sq / 8).min(7 - sq / 8) as i32;
+    20 * (6 - file - rank)
+}
+
+fn push_close(a: u32, b: u32) -> i32 {
+    10 * (8 - distance(a, b))
+}
+
+fn draw(_: &EndgamePosition, _: &Params) -> i32 {
+    0
+}
+
+// Drive the lone king to the edge and bring ours next to it
+fn kxk(pos: &EndgamePosition, params: &Params) -> i32 {
+    let (strong, weak) = (pos.strong, pos.weak());
+    let mut score = pos.material(strong, params) + push_to_edge(pos.king(weak)) + push_close(pos.king(strong), pos.king(weak));
+
+    let bishops = pos.pieces[strong][2];
+    let both_colors = bishops & DARK_SQUARES != 0 && bishops & !DARK_SQUARES != 0;
+    if pos.count(strong, 4) + pos.count(strong, 3) > 0 || both_colors || (bishops != 0 && pos.count(strong, 1) > 0) {
+        score += KNOWN_WIN;
+    }
+    score
+}
+
+// Mate only happens in a corner of the bishop's colour
+fn kbnk(pos: &EndgamePosition, params: &Params) -> i32 {
+    let (strong, weak) = (pos.strong, pos.weak());
+    let weak_king = pos.king(weak);
+    let corners = if pos.pieces[strong][2] & DARK_SQUARES != 0 { [0, 63] } else { [7, 56] };
+    let corner_distance = corners.iter().map(|&c| distance(weak_king, c)).min().unwrap();
+
+    KNOWN_WIN + pos.material(strong, params) + push_close(pos.king(strong), weak_king) + 20 * (7 - corner_distance)
+}
+
+fn kpk(pos: &EndgamePosition, params: &Params) -> i32 {
+    let (strong, weak) = (pos.strong, pos.weak());
+    // The bitbase has the pawn side moving up the board
+    let flip = if strong == 0 { 0 } else { 56 };
+    let pawn = pos.pieces[strong][0].trailing_zeros();
+    let strong_to_move = pos.white_to_move == (strong == 0);
+
+    // A pawn on the last rank (only in a made-up FEN) can't move again
+    if relative_rank(pawn, strong == 0) == 7 {
+        return 0;
+    }
+    if !kpk::probe(strong_to_move, pos.king(strong) ^ flip, pos.king(weak) ^ flip, pawn ^ flip) {
+        return 0;
+    }
+    KNOWN_WIN + params.pawn + 20 * relative_rank(pawn, strong == 0) as i32
+}
+
+// A rook pawn with a bishop that can't control the queening square is a
+// draw once the defending king gets to that corner
+fn wrong_bishop(pos: &EndgamePosition, _: &Params) -> i32 {
+    let strong = pos.strong;
+    let pawns = pos.pieces[strong][0];
+    const A_FILE: u64 = 0x0101_0101_0101_0101;
+
+    for file in [0, 7] {
+        if pawns & !(A_FILE << file) != 0 {
+            continue;
+        }
+        let queening = if strong == 0 { 56 + file } else { file };
+        let bishop_dark = pos.pieces[strong][2] & DARK_SQUARES != 0;
+        let square_dark = DARK_SQUARES & (1 << queening) != 0;
+        if bishop_dark != square_dark && distance(pos.king(pos.weak()), queening) <= 1 {
+            return 0;
+        }
+    }
+    SCALE_NORMAL
+}
+
+fn opposite_bishops(pos: &EndgamePosition, _: &Params) -> i32 {
+    let only_bishops = (0..2).all(|color| (1..5).all(|p| p == 2 || pos.count(color, p) == 0));
+    if only_bishops { SCALE_NORMAL / 4 } else { SCALE_NORMAL * 3 / 4 }
+}
+
+// Specialised endgame knowledge for one lane: the named rule, a
+// white-relative value replacing the evaluation if it has one, and the scale factor
+pub fn endgame(board: &Board, lane: usize, params: &Params) -> (Option<&'static str>, Option<i32>, i32) {
+    let mut pos = EndgamePosition {
+        pieces: board.pieces(lane),
+        strong: 0,
+        white_to_move: board.white_to_move(lane),
+    };
+    if pos.pieces[0][5] == 0 || pos.pieces[1][5] == 0 {
+        return (None, None, SCALE_NORMAL);
+    }
+
+    match find_endgame(&mut pos) {
+        Some(Endgame { name, kind: EndgameKind::Value(f), .. }) => {
+            let score = f(&pos, params);
+            (Some(name), Some(if pos.strong == 0 { score } else { -score }), SCALE_NORMAL)
+        }
+        Some(Endgame { name, kind: EndgameKind::Scale(f), .. }) => (Some(name), None, f(&pos, params)),
+        None => (None, None, SCALE_NORMAL),
+    }
 }
@@ -0,0 +1,146 @@
+use std::sync::OnceLock;
+
+use crate::lane::Lane;
+
+// King and pawn versus king, from the pawn side's point of view: White has
+// the pawn on files a-d, ranks 2-7. Indexed by pawn, white king, black king
+// and side to move.
+const PAWN_SQUARES: usize = 24;
+const SIZE: usize = PAWN_SQUARES * 64 * 64 * 2;
+
+const INVALID: u8 = 0;
+const UNKNOWN: u8 = 1;
+const DRAW: u8 = 2;
+const WIN: u8 = 4;
+
+static BITBASE: OnceLock<Vec<u64>> = OnceLock::new();
+
+// Builds the bitbase up front so the first probe in a search doesn't pay for it
+pub fn init() {
+    bitbase();
+}
+
+fn bitbase() -> &'static [u64] {
+    BITBASE.get_or_init(generate)
+}
+
+fn index(white_to_move: bool, white_king: u32, black_king: u32, pawn: u32) -> usize {
+    let pawn_index = (pawn % 8) as usize + 4 * ((pawn / 8) as usize - 1);
+    ((pawn_index * 64 + white_king as usize) * 64 + black_king as usize) * 2 + !white_to_move as usize
+}
+
+// Whether the pawn side wins. Squares are as seen by the pawn side with the
+// board flipped so that its pawn moves up; files e-h are mirrored here.
+pub fn probe(strong_to_move: bool, strong_king: u32, weak_king: u32, pawn: u32) -> bool {
+    let (strong_king, weak_king, pawn) = if pawn % 8 >= 4 {
+        (strong_king ^ 7, weak_king ^ 7, pawn ^ 7)
+    } else {
+        (strong_king, weak_king, pawn)
+    };
+    let i = index(strong_to_move, strong_king, weak_king, pawn);
+    bitbase()[i / 64] & (1 << (i % 64)) != 0
+}
+
+fn king_attack_table() -> [u64; 64] {
+    std::array::from_fn(|sq| Lane::from_single(1 << sq).king_attacks().extract(0))
+}
+
+fn pawn_attacks(sq: u32) -> u64 {
+    let bit = 1u64 << sq;
+    ((bit << 7) & !0x8080_8080_8080_8080) | ((bit << 9) & !0x0101_0101_0101_0101)
+}
+
+fn distance(a: u32, b: u32) -> u32 {
+    let df = (a % 8).abs_diff(b % 8);
+    let dr = (a / 8).abs_diff(b / 8);
+    df.max(dr)
+}
+
+// Immediate results, before any moves are looked at
+fn classify_initial(king_attacks: &[u64; 64], white_to_move: bool, wk: u32, bk: u32, pawn: u32) -> u8 {
+    if distance(wk, bk) <= 1 || wk == pawn || bk == pawn || (white_to_move && pawn_attacks(pawn) & (1 << bk) != 0) {
+        return INVALID;
+    }
+
+    if white_to_move {
+        // Promotes without the queen being taken
+        let queen = pawn + 8;
+        if pawn / 8 == 6 && wk != queen && bk != queen && (distance(bk, queen) > 1 || distance(wk, queen) == 1) {
+            return WIN;
+        }
+    } else {
+        let safe = king_attacks[bk as usize] & !(king_attacks[wk as usize] | pawn_attacks(pawn));
+        // Stalemate, or the pawn falls
+        if safe == 0 || (king_attacks[bk as usize] & (1 << pawn) != 0 && king_attacks[wk as usize] & (1 << pawn) == 0) {
+            return DRAW;
+        }
+    }
+
+    UNKNOWN
+}
+
+fn classify(king_attacks: &[u64; 64], results: &[u8], white_to_move: bool, wk: u32, bk: u32, pawn: u32) -> u8 {
+    let mut reachable = 0u8;
+
+    if white_to_move {
+        let mut moves = king_attacks[wk as usize] & !king_attacks[bk as usize] & !(1 << pawn);
+        while moves != 0 {
+            let to = moves.trailing_zeros();
+            reachable |= results[index(false, to, bk, pawn)];
+            moves &= moves - 1;
+        }
+        let push = pawn + 8;
+        if pawn / 8 < 6 && push != wk && push != bk {
+            reachable |= results[index(false, wk, bk, push)];
+            let double = push + 8;
+            if pawn / 8 == 1 && double != wk && double != bk {
+                reachable |= results[index(false, wk, bk, double)];
+            }
+        }
+        if reachable & WIN != 0 { WIN } else if reachable & UNKNOWN != 0 { UNKNOWN } else { DRAW }
+    } else {
+        let mut moves = king_attacks[bk as usize] & !(king_attacks[wk as usize] | pawn_attacks(pawn));
+        while moves != 0 {
+            let to = moves.trailing_zeros();
+            reachable |= results[index(true, wk, to, pawn)];
+            moves &= moves - 1;
+        }
+        if reachable & DRAW != 0 { DRAW } else if reachable & UNKNOWN != 0 { UNKNOWN } else { WIN }
+    }
+}
+
+// Retrograde-style fixpoint over every position, then packed to one bit each
+fn generate() -> Vec<u64> {
+    let king_attacks = king_attack_table();
+    let mut results = vec![INVALID; SIZE];
+    let positions = || {
+        (0..PAWN_SQUARES as u32).flat_map(|p| {
+            let pawn = p % 4 + 8 * (p / 4 + 1);
+            (0..64u32).flat_map(move |wk| (0..64u32).flat_map(move |bk| [true, false].map(|stm| (stm, wk, bk, pawn))))
+        })
+    };
+
+    for (stm, wk, bk, pawn) in positions() {
+        results[index(stm, wk, bk, pawn)] = classify_initial(&king_attacks, stm, wk, bk, pawn);
+    }
+
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (stm, wk, bk, pawn) in positions() {
+            let i = index(stm, wk, bk, pawn);
+            if results[i] == UNKNOWN {
+                results[i] = classify(&king_attacks, &results, stm, wk, bk, pawn);
+                changed |= results[i] != UNKNOWN;
+            }
+        }
+    }
+
+    let mut bits = vec![0u64; SIZE.div_ceil(64)];
+    for (i, r) in results.iter().enumerate() {
+        if *r == WIN {
+            bits[i / 64] |= 1 << (i % 64);
+        }
+    }
+    bits
+}
@@ -1,120 +1,212 @@
-#[cfg(target_arch = "x86_64")]
-use std::arch::x86_64::*;
-use std::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not};
+use std::ops::{Add, AddAssign, BitAnd, BitAndAssign, BitOr, BitOrAssign, BitXor, BitXorAssign, Not, Sub, SubAssign};
+
+// LANES 64-bit lanes, one board each: four by default, eight with the
+// `lanes8` feature. Everything here is built on the few primitives a
+// backend provides:
+//   lane_avx2   one __m256i, for four lanes when the target has AVX2 (-C target-cpu=native)
+//   lane_avx512 one __m512i, for eight lanes when the target has AVX-512F, BW and VL
+//   lane_simd   core::simd, with the `simd` feature (nightly)
+//   lane_scalar a [u64; LANES] for every other target, or with the `scalar` feature
+// All of them give bit-identical results, which tests.rs checks.
+#[cfg(not(feature = "lanes8"))]
+pub const LANES: usize = 4;
+#[cfg(feature = "lanes8")]
+pub const LANES: usize = 8;
+
+pub trait Backend: Copy {
+    const ZERO: Self;
+    fn from_array(values: [u64; LANES]) -> Self;
+    fn splat(value: u64) -> Self;
+    fn to_array(self) -> [u64; LANES];
+    fn and(self, other: Self) -> Self;
+    fn or(self, other: Self) -> Self;
+    fn xor(self, other: Self) -> Self;
+    // All ones in the lanes where the two are equal
+    fn eq_mask(self, other: Self) -> Self;
+    fn shl<const N: i32>(self) -> Self;
+    fn shr<const N: i32>(self) -> Self;
+
+    // Arithmetic treats each lane as an i64 and wraps
+    fn popcount(self) -> Self;
+    fn add(self, other: Self) -> Self;
+    fn sub(self, other: Self) -> Self;
+    fn mul(self, other: Self) -> Self;
+    // All ones in the lanes where self > other
+    fn gt_mask(self, other: Self) -> Self;
+
+    // `a` in the lanes where self is all ones, `b` where it is zero
+    #[inline]
+    fn select(self, a: Self, b: Self) -> Self {
+        a.and(self).or(b.and(self.xor(Self::splat(!0))))
+    }
+
+    #[inline]
+    fn min(self, other: Self) -> Self {
+        self.gt_mask(other).select(other, self)
+    }
+
+    #[inline]
+    fn max(self, other: Self) -> Self {
+        self.gt_mask(other).select(self, other)
+    }
+}
+
+#[cfg(all(feature = "scalar", feature = "simd"))]
+compile_error!("the `scalar` and `simd` features each pick a Lane backend; enable at most one");
+
+// The hand-written backend for this lane count, if the target has it
+#[cfg(all(not(feature = "lanes8"), target_arch = "x86_64", target_feature = "avx2"))]
+pub type Native = crate::lane_avx2::Avx2;
+#[cfg(all(feature = "lanes8", target_arch = "x86_64", target_feature = "avx512f", target_feature = "avx512bw", target_feature = "avx512vl"))]
+pub type Native = crate::lane_avx512::Avx512;
+#[cfg(not(any(
+    all(not(feature = "lanes8"), target_arch = "x86_64", target_feature = "avx2"),
+    all(feature = "lanes8", target_arch = "x86_64", target_feature = "avx512f", target_feature = "avx512bw", target_feature = "avx512vl"),
+)))]
+pub type Native = crate::lane_scalar::Scalar;
+
+#[cfg(feature = "scalar")]
+pub type Selected = crate::lane_scalar::Scalar;
+#[cfg(all(feature = "simd", not(feature = "scalar")))]
+pub type Selected = crate::lane_simd::Simd;
+#[cfg(not(any(feature = "scalar", feature = "simd")))]
+pub type Selected = Native;
 
 #[derive(Clone, Copy)]
 #[repr(transparent)]
-pub struct Lane(pub __m256i);
+pub struct LaneOf<B>(pub B);
+
+pub type Lane = LaneOf<Selected>;
 
 const NOT_A_FILE: u64 = !0x0101010101010101u64;
 const NOT_H_FILE: u64 = !0x8080808080808080u64;
 
-impl Lane {
-    pub const EMPTY: Self = unsafe { Self(_mm256_setzero_si256()) };
+impl<B: Backend> LaneOf<B> {
+    pub const EMPTY: Self = Self(B::ZERO);
+    pub const LANES: usize = LANES;
+
+    #[inline]
+    pub fn from_array(values: [u64; LANES]) -> Self {
+        Self(B::from_array(values))
+    }
 
     #[inline]
-    pub fn new(a: u64, b: u64, c: u64, d: u64) -> Self {
-        unsafe {
-            Self(_mm256_set_epi64x(d as i64, c as i64, b as i64, a as i64))
-        }
+    pub fn to_array(&self) -> [u64; LANES] {
+        self.0.to_array()
     }
 
     #[inline]
     pub fn from_single(val: u64) -> Self {
-        unsafe {
-            Self(_mm256_set1_epi64x(val as i64))
-        }
+        Self(B::splat(val))
     }
 
     #[inline]
     pub fn extract(&self, index: usize) -> u64 {
-        unsafe {
-            let mut arr = [0u64; 4];
-            _mm256_storeu_si256(arr.as_mut_ptr() as *mut __m256i, self.0);
-            arr[index]
-        }
+        self.0.to_array()[index]
+    }
+
+    #[inline]
+    pub fn eq(&self, other: Self) -> Self {
+        Self(self.0.eq_mask(other.0))
     }
 
+    // Lane-wise signed compare, all ones where self > other
     #[inline]
-    pub fn eq(&self, other: Lane) -> Lane {
-        unsafe { Self(_mm256_cmpeq_epi64(self.0, other.0)) }
+    pub fn gt(&self, other: Self) -> Self {
+        Self(self.0.gt_mask(other.0))
     }
 
+    // Whether every lane is zero
     #[inline]
-    pub fn is_zero_mask(&self) -> Lane {
+    pub fn is_empty(&self) -> bool {
+        self.to_array().iter().all(|&v| v == 0)
+    }
+
+    #[inline]
+    pub fn is_zero_mask(&self) -> Self {
         self.eq(Self::EMPTY)
     }
 
     #[inline]
-    pub fn is_not_zero_mask(&self) -> Lane {
+    pub fn is_not_zero_mask(&self) -> Self {
         !self.is_zero_mask()
     }
 
+    // Treating self as a mask from `eq` or `gt`
+    #[inline]
+    pub fn select(&self, if_set: Self, if_clear: Self) -> Self {
+        Self(self.0.select(if_set.0, if_clear.0))
+    }
+
+    // Set bits in each lane
+    #[inline]
+    pub fn popcount(&self) -> Self {
+        Self(self.0.popcount())
+    }
+
+    #[inline]
+    pub fn mul_const(&self, k: i64) -> Self {
+        Self(self.0.mul(B::splat(k as u64)))
+    }
+
+    #[inline]
+    pub fn min(&self, other: Self) -> Self {
+        Self(self.0.min(other.0))
+    }
+
+    #[inline]
+    pub fn max(&self, other: Self) -> Self {
+        Self(self.0.max(other.0))
+    }
+
+    // Each lane as a score
+    #[inline]
+    pub fn to_i32s(&self) -> [i32; LANES] {
+        self.to_array().map(|v| v as i64 as i32)
+    }
+
     #[inline]
     pub fn shift_north(&self) -> Self {
-        unsafe { Self(_mm256_slli_epi64(self.0, 8)) }
+        Self(self.0.shl::<8>())
     }
 
     #[inline]
     pub fn shift_south(&self) -> Self {
-        unsafe { Self(_mm256_srli_epi64(self.0, 8)) }
+        Self(self.0.shr::<8>())
     }
 
     #[inline]
     pub fn shift_east(&self) -> Self {
-        unsafe {
-            let shifted = _mm256_slli_epi64(self.0, 1);
-            let mask = _mm256_set1_epi64x(NOT_A_FILE as i64);
-            Self(_mm256_and_si256(shifted, mask))
-        }
+        Self(self.0.shl::<1>().and(B::splat(NOT_A_FILE)))
     }
 
     #[inline]
     pub fn shift_west(&self) -> Self {
-        unsafe {
-            let shifted = _mm256_srli_epi64(self.0, 1);
-            let mask = _mm256_set1_epi64x(NOT_H_FILE as i64);
-            Self(_mm256_and_si256(shifted, mask))
-        }
+        Self(self.0.shr::<1>().and(B::splat(NOT_H_FILE)))
     }
 
     #[inline]
     pub fn shift_north_east(&self) -> Self {
-        unsafe {
-            let shifted = _mm256_slli_epi64(self.0, 9);
-            let mask = _mm256_set1_epi64x(NOT_A_FILE as i64);
-            Self(_mm256_and_si256(shifted, mask))
-        }
+        Self(self.0.shl::<9>().and(B::splat(NOT_A_FILE)))
     }
 
     #[inline]
     pub fn shift_north_west(&self) -> Self {
-        unsafe {
-            let shifted = _mm256_slli_epi64(self.0, 7);
-            let mask = _mm256_set1_epi64x(NOT_H_FILE as i64);
-            Self(_mm256_and_si256(shifted, mask))
-        }
+        Self(self.0.shl::<7>().and(B::splat(NOT_H_FILE)))
     }
 
     #[inline]
     pub fn shift_south_east(&self) -> Self {
-        unsafe {
-            let shifted = _mm256_srli_epi64(self.0, 7);
-            let mask = _mm256_set1_epi64x(NOT_A_FILE as i64);
-            Self(_mm256_and_si256(shifted, mask))
-        }
+        Self(self.0.shr::<7>().and(B::splat(NOT_A_FILE)))
     }
 
     #[inline]
     pub fn shift_south_west(&self) -> Self {
-        unsafe {
-            let shifted = _mm256_srli_epi64(self.0, 9);
-            let mask = _mm256_set1_epi64x(NOT_H_FILE as i64);
-            Self(_mm256_and_si256(shifted, mask))
-        }
+        Self(self.0.shr::<9>().and(B::splat(NOT_H_FILE)))
     }
 
-    pub fn fill_north(&self, empty: Lane) -> Lane {
+    #[inline]
+    pub fn fill_north(&self, empty: Self) -> Self {
         let mut g = *self;
         g |= empty & g.shift_north();
         let mut e = empty & empty.shift_north();
@@ -124,7 +216,8 @@ impl Lane {
         g
     }
 
-    pub fn fill_south(&self, empty: Lane) -> Lane {
+    #[inline]
+    pub fn fill_south(&self, empty: Self) -> Self {
         let mut g = *self;
         g |= empty & g.shift_south();
         let mut e = empty & empty.shift_south();
@@ -134,7 +227,8 @@ impl Lane {
         g
     }
 
-    pub fn fill_east(&self, empty: Lane) -> Lane {
+    #[inline]
+    pub fn fill_east(&self, empty: Self) -> Self {
         let mut g = *self;
         g |= empty & g.shift_east();
         let mut e = empty & empty.shift_east();
@@ -144,7 +238,8 @@ impl Lane {
         g
     }
 
-    pub fn fill_west(&self, empty: Lane) -> Lane {
+    #[inline]
+    pub fn fill_west(&self, empty: Self) -> Self {
         let mut g = *self;
         g |= empty & g.shift_west();
         let mut e = empty & empty.shift_west();
@@ -154,7 +249,8 @@ impl Lane {
         g
     }
 
-    pub fn fill_north_east(&self, empty: Lane) -> Lane {
+    #[inline]
+    pub fn fill_north_east(&self, empty: Self) -> Self {
         let mut g = *self;
         g |= empty & g.shift_north_east();
         let mut e = empty & empty.shift_north_east();
@@ -164,7 +260,8 @@ impl Lane {
         g
     }
 
-    pub fn fill_north_west(&self, empty: Lane) -> Lane {
+    #[inline]
+    pub fn fill_north_west(&self, empty: Self) -> Self {
         let mut g = *self;
         g |= empty & g.shift_north_west();
         let mut e = empty & empty.shift_north_west();
@@ -174,7 +271,8 @@ impl Lane {
         g
     }
 
-    pub fn fill_south_east(&self, empty: Lane) -> Lane {
+    #[inline]
+    pub fn fill_south_east(&self, empty: Self) -> Self {
         let mut g = *self;
         g |= empty & g.shift_south_east();
         let mut e = empty & empty.shift_south_east();
@@ -184,7 +282,8 @@ impl Lane {
         g
     }
 
-    pub fn fill_south_west(&self, empty: Lane) -> Lane {
+    #[inline]
+    pub fn fill_south_west(&self, empty: Self) -> Self {
         let mut g = *self;
         g |= empty & g.shift_south_west();
         let mut e = empty & empty.shift_south_west();
@@ -194,6 +293,7 @@ impl Lane {
         g
     }
 
+    #[inline]
     pub fn knight_attacks(&self) -> Self {
         let n = self.shift_north();
         let s = self.shift_south();
@@ -206,6 +306,7 @@ impl Lane {
         (w.shift_north_west() | w.shift_south_west())
     }
 
+    #[inline]
     pub fn king_attacks(&self) -> Self {
         let n = self.shift_north();
         let s = self.shift_south();
@@ -215,65 +316,92 @@ impl Lane {
     }
 }
 
-impl BitAnd for Lane {
+impl<B: Backend> BitAnd for LaneOf<B> {
     type Output = Self;
     #[inline]
     fn bitand(self, rhs: Self) -> Self {
-        unsafe { Self(_mm256_and_si256(self.0, rhs.0)) }
+        Self(self.0.and(rhs.0))
     }
 }
 
-impl BitOr for Lane {
+impl<B: Backend> BitOr for LaneOf<B> {
     type Output = Self;
     #[inline]
     fn bitor(self, rhs: Self) -> Self {
-        unsafe { Self(_mm256_or_si256(self.0, rhs.0)) }
+        Self(self.0.or(rhs.0))
     }
 }
 
-impl BitXor for Lane {
+impl<B: Backend> BitXor for LaneOf<B> {
     type Output = Self;
     #[inline]
     fn bitxor(self, rhs: Self) -> Self {
-        unsafe { Self(_mm256_xor_si256(self.0, rhs.0)) }
+        Self(self.0.xor(rhs.0))
     }
 }
 
-impl Not for Lane {
+impl<B: Backend> Not for LaneOf<B> {
     type Output = Self;
     #[inline]
     fn not(self) -> Self {
-        unsafe {
-            let ones = _mm256_set1_epi64x(-1);
-            Self(_mm256_xor_si256(self.0, ones))
-        }
+        Self(self.0.xor(B::splat(!0)))
     }
 }
 
-impl BitAndAssign for Lane {
+impl<B: Backend> BitAndAssign for LaneOf<B> {
     #[inline]
     fn bitand_assign(&mut self, rhs: Self) {
         *self = *self & rhs;
     }
 }
 
-impl BitOrAssign for Lane {
+impl<B: Backend> BitOrAssign for LaneOf<B> {
     #[inline]
     fn bitor_assign(&mut self, rhs: Self) {
         *self = *self | rhs;
     }
 }
 
-impl BitXorAssign for Lane {
+impl<B: Backend> BitXorAssign for LaneOf<B> {
     #[inline]
     fn bitxor_assign(&mut self, rhs: Self) {
         *self = *self ^ rhs;
     }
 }
 
-impl std::fmt::Debug for Lane {
+impl<B: Backend> Add for LaneOf<B> {
+    type Output = Self;
+    #[inline]
+    fn add(self, rhs: Self) -> Self {
+        Self(self.0.add(rhs.0))
+    }
+}
+
+impl<B: Backend> Sub for LaneOf<B> {
+    type Output = Self;
+    #[inline]
+    fn sub(self, rhs: Self) -> Self {
+        Self(self.0.sub(rhs.0))
+    }
+}
+
+impl<B: Backend> AddAssign for LaneOf<B> {
+    #[inline]
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl<B: Backend> SubAssign for LaneOf<B> {
+    #[inline]
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl<B: Backend> std::fmt::Debug for LaneOf<B> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "Lane({:016x}, {:016x}, {:016x}, {:016x})",
-            self.extract(0), self.extract(1), self.extract(2), self.extract(3))
+        let lanes: Vec<String> = self.to_array().iter().map(|v| format!("{:016x}", v)).collect();
+        write!(f, "Lane({})", lanes.join(", "))
     }
 }
@@ -0,0 +1,112 @@
+use std::arch::x86_64::*;
+
+use crate::lane::Backend;
+
+// Only selected when the target enables AVX2, so the intrinsics inline
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct Avx2(pub __m256i);
+
+impl Backend for Avx2 {
+    // SAFETY: every bit pattern is a valid __m256i
+    const ZERO: Self = Self(unsafe { std::mem::transmute::<[u64; 4], __m256i>([0; 4]) });
+
+    #[inline]
+    fn from_array(values: [u64; 4]) -> Self {
+        let [a, b, c, d] = values.map(|v| v as i64);
+        unsafe { Self(_mm256_set_epi64x(d, c, b, a)) }
+    }
+
+    #[inline]
+    fn splat(value: u64) -> Self {
+        unsafe { Self(_mm256_set1_epi64x(value as i64)) }
+    }
+
+    #[inline]
+    fn to_array(self) -> [u64; 4] {
+        let mut values = [0u64; 4];
+        unsafe { _mm256_storeu_si256(values.as_mut_ptr() as *mut __m256i, self.0) };
+        values
+    }
+
+    #[inline]
+    fn and(self, other: Self) -> Self {
+        unsafe { Self(_mm256_and_si256(self.0, other.0)) }
+    }
+
+    #[inline]
+    fn or(self, other: Self) -> Self {
+        unsafe { Self(_mm256_or_si256(self.0, other.0)) }
+    }
+
+    #[inline]
+    fn xor(self, other: Self) -> Self {
+        unsafe { Self(_mm256_xor_si256(self.0, other.0)) }
+    }
+
+    #[inline]
+    fn eq_mask(self, other: Self) -> Self {
+        unsafe { Self(_mm256_cmpeq_epi64(self.0, other.0)) }
+    }
+
+    #[inline]
+    fn shl<const N: i32>(self) -> Self {
+        unsafe { Self(_mm256_slli_epi64::<N>(self.0)) }
+    }
+
+    #[inline]
+    fn shr<const N: i32>(self) -> Self {
+        unsafe { Self(_mm256_srli_epi64::<N>(self.0)) }
+    }
+
+    // Nibble lookup with vpshufb, then vpsadbw sums the eight byte counts of
+    // each lane
+    #[inline]
+    fn popcount(self) -> Self {
+        unsafe {
+            let lut = _mm256_setr_epi8(
+                0, 1, 1, 2, 1, 2, 2, 3, 1, 2, 2, 3, 2, 3, 3, 4,
+                0, 1, 1, 2, 1, 2, 2, 3, 1, 2, 2, 3, 2, 3, 3, 4,
+            );
+            let nibble = _mm256_set1_epi8(0x0f);
+            let low = _mm256_and_si256(self.0, nibble);
+            let high = _mm256_and_si256(_mm256_srli_epi16::<4>(self.0), nibble);
+            let bytes = _mm256_add_epi8(_mm256_shuffle_epi8(lut, low), _mm256_shuffle_epi8(lut, high));
+            Self(_mm256_sad_epu8(bytes, _mm256_setzero_si256()))
+        }
+    }
+
+    #[inline]
+    fn add(self, other: Self) -> Self {
+        unsafe { Self(_mm256_add_epi64(self.0, other.0)) }
+    }
+
+    #[inline]
+    fn sub(self, other: Self) -> Self {
+        unsafe { Self(_mm256_sub_epi64(self.0, other.0)) }
+    }
+
+    // No 64-bit multiply before AVX-512, so it's built from 32x32 products;
+    // the high halves only meet in the cross terms
+    #[inline]
+    fn mul(self, other: Self) -> Self {
+        unsafe {
+            let low = _mm256_mul_epu32(self.0, other.0);
+            let cross = _mm256_add_epi64(
+                _mm256_mul_epu32(_mm256_srli_epi64::<32>(self.0), other.0),
+                _mm256_mul_epu32(self.0, _mm256_srli_epi64::<32>(other.0)),
+            );
+            Self(_mm256_add_epi64(low, _mm256_slli_epi64::<32>(cross)))
+        }
+    }
+
+    #[inline]
+    fn gt_mask(self, other: Self) -> Self {
+        unsafe { Self(_mm256_cmpgt_epi64(self.0, other.0)) }
+    }
+
+    #[inline]
+    fn select(self, a: Self, b: Self) -> Self {
+        unsafe { Self(_mm256_blendv_epi8(b.0, a.0, self.0)) }
+    }
+}
@@ -0,0 +1,122 @@
+use std::arch::x86_64::*;
+
+use crate::lane::Backend;
+
+// Eight boards in one zmm register. Only selected when the target enables
+// AVX-512F, BW and VL, so the intrinsics inline and LLVM folds the and/or/not
+// chains of the fills into vpternlogq.
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct Avx512(pub __m512i);
+
+impl Backend for Avx512 {
+    // SAFETY: every bit pattern is a valid __m512i
+    const ZERO: Self = Self(unsafe { std::mem::transmute::<[u64; 8], __m512i>([0; 8]) });
+
+    #[inline]
+    fn from_array(values: [u64; 8]) -> Self {
+        unsafe { Self(_mm512_loadu_si512(values.as_ptr() as *const __m512i)) }
+    }
+
+    #[inline]
+    fn splat(value: u64) -> Self {
+        unsafe { Self(_mm512_set1_epi64(value as i64)) }
+    }
+
+    #[inline]
+    fn to_array(self) -> [u64; 8] {
+        let mut values = [0u64; 8];
+        unsafe { _mm512_storeu_si512(values.as_mut_ptr() as *mut __m512i, self.0) };
+        values
+    }
+
+    #[inline]
+    fn and(self, other: Self) -> Self {
+        unsafe { Self(_mm512_and_si512(self.0, other.0)) }
+    }
+
+    #[inline]
+    fn or(self, other: Self) -> Self {
+        unsafe { Self(_mm512_or_si512(self.0, other.0)) }
+    }
+
+    #[inline]
+    fn xor(self, other: Self) -> Self {
+        unsafe { Self(_mm512_xor_si512(self.0, other.0)) }
+    }
+
+    // The compare gives a mask register; a masked move widens it back
+    #[inline]
+    fn eq_mask(self, other: Self) -> Self {
+        unsafe { Self(_mm512_maskz_set1_epi64(_mm512_cmpeq_epi64_mask(self.0, other.0), -1)) }
+    }
+
+    // The immediate forms take a u32, which a const i32 can't become on
+    // stable; the variable shift by a constant compiles to the same vpsllq
+    #[inline]
+    fn shl<const N: i32>(self) -> Self {
+        unsafe { Self(_mm512_sllv_epi64(self.0, _mm512_set1_epi64(N as i64))) }
+    }
+
+    #[inline]
+    fn shr<const N: i32>(self) -> Self {
+        unsafe { Self(_mm512_srlv_epi64(self.0, _mm512_set1_epi64(N as i64))) }
+    }
+
+    // As for AVX2: nibble lookup with vpshufb, summed per lane by vpsadbw
+    #[inline]
+    fn popcount(self) -> Self {
+        unsafe {
+            let lut = _mm512_set4_epi32(0x0403_0302, 0x0302_0201, 0x0302_0201, 0x0201_0100);
+            let nibble = _mm512_set1_epi8(0x0f);
+            let low = _mm512_and_si512(self.0, nibble);
+            let high = _mm512_and_si512(_mm512_srli_epi16::<4>(self.0), nibble);
+            let bytes = _mm512_add_epi8(_mm512_shuffle_epi8(lut, low), _mm512_shuffle_epi8(lut, high));
+            Self(_mm512_sad_epu8(bytes, _mm512_setzero_si512()))
+        }
+    }
+
+    #[inline]
+    fn add(self, other: Self) -> Self {
+        unsafe { Self(_mm512_add_epi64(self.0, other.0)) }
+    }
+
+    #[inline]
+    fn sub(self, other: Self) -> Self {
+        unsafe { Self(_mm512_sub_epi64(self.0, other.0)) }
+    }
+
+    // vpmullq needs AVX-512DQ, so this is the AVX2 construction
+    #[inline]
+    fn mul(self, other: Self) -> Self {
+        unsafe {
+            let low = _mm512_mul_epu32(self.0, other.0);
+            let cross = _mm512_add_epi64(
+                _mm512_mul_epu32(_mm512_srli_epi64::<32>(self.0), other.0),
+                _mm512_mul_epu32(self.0, _mm512_srli_epi64::<32>(other.0)),
+            );
+            Self(_mm512_add_epi64(low, _mm512_slli_epi64::<32>(cross)))
+        }
+    }
+
+    #[inline]
+    fn gt_mask(self, other: Self) -> Self {
+        unsafe { Self(_mm512_maskz_set1_epi64(_mm512_cmpgt_epi64_mask(self.0, other.0), -1)) }
+    }
+
+    // Back to a mask register, then a masked blend
+    #[inline]
+    fn select(self, a: Self, b: Self) -> Self {
+        unsafe { Self(_mm512_mask_blend_epi64(_mm512_test_epi64_mask(self.0, self.0), b.0, a.0)) }
+    }
+
+    #[inline]
+    fn min(self, other: Self) -> Self {
+        unsafe { Self(_mm512_min_epi64(self.0, other.0)) }
+    }
+
+    #[inline]
+    fn max(self, other: Self) -> Self {
+        unsafe { Self(_mm512_max_epi64(self.0, other.0)) }
+    }
+}
@@ -0,0 +1,82 @@
+use crate::lane::{Backend, LANES};
+
+// Plain u64s; the compiler vectorizes what the target allows
+#[derive(Clone, Copy)]
+// Aligned like the vector register of the same width
+#[cfg_attr(not(feature = "lanes8"), repr(align(32)))]
+#[cfg_attr(feature = "lanes8", repr(align(64)))]
+pub struct Scalar(pub [u64; LANES]);
+
+impl Backend for Scalar {
+    const ZERO: Self = Self([0; LANES]);
+
+    #[inline]
+    fn from_array(values: [u64; LANES]) -> Self {
+        Self(values)
+    }
+
+    #[inline]
+    fn splat(value: u64) -> Self {
+        Self([value; LANES])
+    }
+
+    #[inline]
+    fn to_array(self) -> [u64; LANES] {
+        self.0
+    }
+
+    #[inline]
+    fn and(self, other: Self) -> Self {
+        Self(std::array::from_fn(|i| self.0[i] & other.0[i]))
+    }
+
+    #[inline]
+    fn or(self, other: Self) -> Self {
+        Self(std::array::from_fn(|i| self.0[i] | other.0[i]))
+    }
+
+    #[inline]
+    fn xor(self, other: Self) -> Self {
+        Self(std::array::from_fn(|i| self.0[i] ^ other.0[i]))
+    }
+
+    #[inline]
+    fn eq_mask(self, other: Self) -> Self {
+        Self(std::array::from_fn(|i| if self.0[i] == other.0[i] { !0 } else { 0 }))
+    }
+
+    #[inline]
+    fn shl<const N: i32>(self) -> Self {
+        Self(self.0.map(|v| v << N))
+    }
+
+    #[inline]
+    fn shr<const N: i32>(self) -> Self {
+        Self(self.0.map(|v| v >> N))
+    }
+
+    #[inline]
+    fn popcount(self) -> Self {
+        Self(self.0.map(|v| v.count_ones() as u64))
+    }
+
+    #[inline]
+    fn add(self, other: Self) -> Self {
+        Self(std::array::from_fn(|i| self.0[i].wrapping_add(other.0[i])))
+    }
+
+    #[inline]
+    fn sub(self, other: Self) -> Self {
+        Self(std::array::from_fn(|i| self.0[i].wrapping_sub(other.0[i])))
+    }
+
+    #[inline]
+    fn mul(self, other: Self) -> Self {
+        Self(std::array::from_fn(|i| self.0[i].wrapping_mul(other.0[i])))
+    }
+
+    #[inline]
+    fn gt_mask(self, other: Self) -> Self {
+        Self(std::array::from_fn(|i| if self.0[i] as i64 > other.0[i] as i64 { !0 } else { 0 }))
+    }
+}
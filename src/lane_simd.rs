@@ -0,0 +1,88 @@
+use std::simd::cmp::{SimdPartialEq, SimdPartialOrd};
+use std::simd::num::{SimdInt, SimdUint};
+
+use crate::lane::{Backend, LANES};
+
+type Vector = std::simd::Simd<u64, LANES>;
+type Signed = std::simd::Simd<i64, LANES>;
+
+// core::simd, for targets without a hand-written backend (nightly only)
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct Simd(pub Vector);
+
+impl Backend for Simd {
+    const ZERO: Self = Self(Vector::from_array([0; LANES]));
+
+    #[inline]
+    fn from_array(values: [u64; LANES]) -> Self {
+        Self(Vector::from_array(values))
+    }
+
+    #[inline]
+    fn splat(value: u64) -> Self {
+        Self(Vector::splat(value))
+    }
+
+    #[inline]
+    fn to_array(self) -> [u64; LANES] {
+        self.0.to_array()
+    }
+
+    #[inline]
+    fn and(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+
+    #[inline]
+    fn or(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    #[inline]
+    fn xor(self, other: Self) -> Self {
+        Self(self.0 ^ other.0)
+    }
+
+    #[inline]
+    fn eq_mask(self, other: Self) -> Self {
+        // A true mask lane is -1 as an i64, all ones as a u64
+        Self(self.0.simd_eq(other.0).to_simd().cast())
+    }
+
+    #[inline]
+    fn shl<const N: i32>(self) -> Self {
+        Self(self.0 << Vector::splat(N as u64))
+    }
+
+    #[inline]
+    fn shr<const N: i32>(self) -> Self {
+        Self(self.0 >> Vector::splat(N as u64))
+    }
+
+    #[inline]
+    fn popcount(self) -> Self {
+        Self(self.0.count_ones())
+    }
+
+    #[inline]
+    fn add(self, other: Self) -> Self {
+        Self(self.0 + other.0)
+    }
+
+    #[inline]
+    fn sub(self, other: Self) -> Self {
+        Self(self.0 - other.0)
+    }
+
+    #[inline]
+    fn mul(self, other: Self) -> Self {
+        Self(self.0 * other.0)
+    }
+
+    #[inline]
+    fn gt_mask(self, other: Self) -> Self {
+        let (a, b): (Signed, Signed) = (self.0.cast(), other.0.cast());
+        Self(a.simd_gt(b).to_simd().cast())
+    }
+}
@@ -0,0 +1,72 @@
+#![cfg_attr(feature = "simd", feature(portable_simd))]
+//! Vesper, a chess engine that keeps four positions (eight with the `lanes8`
+//! feature) side by side in SIMD lanes. Lane 0 is the position being played; the search, SAN and UCI code
+//! only look at lane 0.
+//!
+//! The API most tools need:
+//!
+//! - [`Board`] holds the positions; [`Board::from_fen`] and [`Board::play`]
+//!   set it up and play on.
+//! - [`Move`] is a move of lane 0; [`movegen::legal_move_list`] lists them.
+//!   Search plays a [`MoveField`], one-bit from and to squares per lane.
+//! - [`search::search_with_info`] searches lane 0 within [`Limits`], calling
+//!   back once per iteration with an [`Info`].
+//! - [`eval::evaluate`] is the classical evaluation of each lane;
+//!   [`eval::evaluate_with`] takes other [`params::Params`], such as tuned ones.
+//!
+//! ```no_run
+//! use vesper::{Board, Limits, search};
+//!
+//! vesper::init().unwrap();
+//! let board = Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+//! let limits = Limits { depth: 6, nodes: None, movetime: Some(1000), silent: true };
+//! let result = search::search_with_info(board, &limits, &mut |info| println!("depth {} score {}", info.depth, info.score));
+//! println!("{}", result.best_move);
+//! ```
+//!
+//! Search leaves out castling, en passant and underpromotion below the root,
+//! and a pawn reaching the last rank there always becomes a queen.
+
+pub mod cpu;
+pub mod lane;
+pub mod lane_scalar;
+#[cfg(all(target_arch = "x86_64", not(feature = "lanes8")))]
+pub mod lane_avx2;
+#[cfg(all(target_arch = "x86_64", feature = "lanes8"))]
+pub mod lane_avx512;
+#[cfg(feature = "simd")]
+pub mod lane_simd;
+pub mod board;
+pub mod movegen;
+pub mod eval;
+pub mod params;
+mod pst;
+mod kpk;
+pub mod syzygy;
+pub mod nnue;
+pub mod book;
+pub mod san;
+pub mod pgn;
+mod zobrist;
+pub mod search;
+pub mod uci;
+pub mod tune;
+pub mod datagen;
+pub mod testsuite;
+pub mod bench;
+pub mod arena;
+#[cfg(test)]
+mod tests;
+
+pub use board::Board;
+pub use movegen::{Move, MoveField};
+pub use search::{Info, Limits, SearchResult};
+
+/// Picks the code path for this CPU and builds the tables that are otherwise
+/// built on first use, so the first search doesn't pay for them. Fails if the
+/// CPU cannot run this binary; see [`cpu::check`].
+pub fn init() -> Result<(), String> {
+    cpu::check()?;
+    kpk::init();
+    Ok(())
+}
@@ -1,17 +1,42 @@
-#![feature(stdarch_const_x86)]
-mod lane;
-mod board;
-mod movegen;
-mod eval;
-mod search;
-mod uci;
-#[cfg(test)]
-mod tests;
+use vesper::{arena, bench, book, datagen, pgn, testsuite, tune, uci};
 
 fn main() {
     let args: Vec<String> = std::env::args().collect();
     if args.len() > 1 && args[1] == "test" {
         return;
     }
+    if let Err(e) = vesper::init() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+    if args.len() > 1 && args[1] == "tune" {
+        tune::run(&args[2..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "book" {
+        book::run(&args[2..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "pgn" {
+        pgn::run(&args[2..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "bench" {
+        let args: Vec<&str> = args[2..].iter().map(String::as_str).collect();
+        bench::run(&args);
+        return;
+    }
+    if args.len() > 1 && args[1] == "match" {
+        arena::run(&args[2..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "testsuite" {
+        testsuite::run(&args[2..]);
+        return;
+    }
+    if args.len() > 1 && args[1] == "datagen" {
+        datagen::run(&args[2..]);
+        return;
+    }
     uci::main_loop();
 }
@@ -1,51 +1,330 @@
-use crate::lane::Lane;
-use crate::board::Board;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
 
+use crate::lane::{Lane, LANES};
+use crate::board::{self, Board};
+
+/// A move per lane, as single-bit from and to masks; a lane with no move
+/// has both empty
 #[derive(Clone, Copy, Debug)]
 pub struct MoveField {
     pub from: Lane,
     pub to: Lane,
 }
 
-pub fn get_attacks(board: &Board, us: Lane, is_white: bool) -> Lane {
+/// A move of one position in 16 bits: from square (bits 0-5), to square
+/// (6-11), kind (12-13) and promotion piece (14-15). Lists, sorting and PVs
+/// use these; a MoveField is only built where lanes get packed.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, Default, Debug)]
+pub struct Move(u16);
+
+impl Move {
+    pub const NONE: Move = Move(0);
+
+    // Kinds
+    pub const NORMAL: u16 = 0;
+    pub const PROMOTION: u16 = 1;
+    pub const EN_PASSANT: u16 = 2;
+    pub const CASTLING: u16 = 3;
+
+    pub fn new(from: u32, to: u32) -> Self {
+        Self::with_kind(from, to, Self::NORMAL)
+    }
+
+    pub fn with_kind(from: u32, to: u32, kind: u16) -> Self {
+        Self(from as u16 | (to as u16) << 6 | kind << 12)
+    }
+
+    /// `piece` in `Board::piece_sets` order, knight (1) to queen (4)
+    pub fn promotion(from: u32, to: u32, piece: usize) -> Self {
+        Self(Self::with_kind(from, to, Self::PROMOTION).0 | ((piece - 1) as u16) << 14)
+    }
+
+    pub fn from_square(self) -> u32 {
+        (self.0 & 63) as u32
+    }
+
+    pub fn to_square(self) -> u32 {
+        ((self.0 >> 6) & 63) as u32
+    }
+
+    pub fn kind(self) -> u16 {
+        (self.0 >> 12) & 3
+    }
+
+    pub fn promotion_piece(self) -> Option<usize> {
+        (self.kind() == Self::PROMOTION).then(|| ((self.0 >> 14) + 1) as usize)
+    }
+
+    pub fn is_none(self) -> bool {
+        self == Self::NONE
+    }
+
+    /// The move in lane `lane`, or NONE if that lane has none
+    pub fn from_field(mv: &MoveField, lane: usize) -> Self {
+        let (from, to) = (mv.from.extract(lane), mv.to.extract(lane));
+        if from == 0 || to == 0 {
+            return Self::NONE;
+        }
+        Self::new(from.trailing_zeros(), to.trailing_zeros())
+    }
+
+    /// from_field, with a pawn reaching the last rank read as the queen
+    /// promotion apply_move makes it
+    pub fn on_board(board: &Board, mv: &MoveField, lane: usize) -> Self {
+        let m = Self::from_field(mv, lane);
+        if !m.is_none() && board.pawns.extract(lane) & mv.from.extract(lane) != 0 && mv.to.extract(lane) & crate::board::LAST_RANKS != 0 {
+            return Self::promotion(m.from_square(), m.to_square(), 4);
+        }
+        m
+    }
+
+    /// The move broadcast to every lane
+    pub fn to_field(self) -> MoveField {
+        if self.is_none() {
+            return MoveField { from: Lane::EMPTY, to: Lane::EMPTY };
+        }
+        MoveField { from: Lane::from_single(1 << self.from_square()), to: Lane::from_single(1 << self.to_square()) }
+    }
+}
+
+// UCI notation
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_none() {
+            return write!(f, "0000");
+        }
+        write!(f, "{}{}", board::square_name(self.from_square()), board::square_name(self.to_square()))?;
+        match self.promotion_piece() {
+            Some(piece) => write!(f, "{}", b"pnbrqk"[piece] as char),
+            None => Ok(()),
+        }
+    }
+}
+
+pub const MAX_MOVES: usize = 256;
+
+/// Up to MAX_MOVES moves, on the stack
+#[derive(Clone)]
+pub struct MoveList {
+    moves: [Move; MAX_MOVES],
+    len: usize,
+}
+
+impl MoveList {
+    pub fn new() -> Self {
+        Self { moves: [Move::NONE; MAX_MOVES], len: 0 }
+    }
+
+    // A made-up FEN can have more pseudo-legal moves than fit; those past
+    // MAX_MOVES are dropped
+    #[inline]
+    pub fn push(&mut self, mv: Move) {
+        if self.len < MAX_MOVES {
+            self.moves[self.len] = mv;
+            self.len += 1;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    pub fn extend_from_slice(&mut self, moves: &[Move]) {
+        for &mv in moves {
+            self.push(mv);
+        }
+    }
+}
+
+impl Default for MoveList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Deref for MoveList {
+    type Target = [Move];
+    fn deref(&self) -> &[Move] {
+        &self.moves[..self.len]
+    }
+}
+
+impl DerefMut for MoveList {
+    fn deref_mut(&mut self) -> &mut [Move] {
+        &mut self.moves[..self.len]
+    }
+}
+
+// Attacked squares broken down by attacker type
+#[derive(Clone, Copy, Debug)]
+pub struct AttackMap {
+    pub pawns: Lane,
+    pub knights: Lane,
+    pub bishops: Lane,
+    pub rooks: Lane,
+    pub queens: Lane,
+    pub king: Lane,
+}
+
+impl AttackMap {
+    pub fn all(&self) -> Lane {
+        self.pawns | self.knights | self.bishops | self.rooks | self.queens | self.king
+    }
+}
+
+#[inline(always)]
+pub fn get_attacks(board: &Board, us: Lane, is_white: bool) -> AttackMap {
     let occupied = board.occupied();
     let empty = !occupied;
 
     let pawns = board.pawns & us;
-    let leapers = board.leapers & us;
-    let sliders = board.sliders & us;
-    let kings = board.kings & us;
 
-    let mut attacks = Lane::EMPTY;
-
-    if is_white {
-        attacks |= pawns.shift_north_east() | pawns.shift_north_west();
+    let pawn_attacks = if is_white {
+        pawns.shift_north_east() | pawns.shift_north_west()
     } else {
-        attacks |= pawns.shift_south_east() | pawns.shift_south_west();
+        pawns.shift_south_east() | pawns.shift_south_west()
+    };
+
+    let queens = board.queens() & us;
+
+    AttackMap {
+        pawns: pawn_attacks,
+        knights: (board.leapers & us).knight_attacks(),
+        bishops: diagonal_attacks(board.bishops() & us, empty),
+        rooks: orthogonal_attacks(board.rooks() & us, empty),
+        queens: diagonal_attacks(queens, empty) | orthogonal_attacks(queens, empty),
+        king: (board.kings & us).king_attacks(),
     }
+}
 
-    attacks |= leapers.knight_attacks();
+/// Whether the side to move's king is attacked, in lane 0
+pub fn in_check(board: &Board) -> bool {
+    king_attacked(board, board.white_to_move(0))
+}
 
-    let diag = sliders & board.diagonal;
-    let ortho = sliders & board.orthogonal;
+pub fn king_attacked(board: &Board, white: bool) -> bool {
+    attacked_kings(board, white).extract(0) != 0
+}
 
-    attacks |= diag.fill_north_east(empty).shift_north_east();
-    attacks |= diag.fill_north_west(empty).shift_north_west();
-    attacks |= diag.fill_south_east(empty).shift_south_east();
-    attacks |= diag.fill_south_west(empty).shift_south_west();
+/// The king of `white`'s side in each lane where it is attacked
+pub fn attacked_kings(board: &Board, white: bool) -> Lane {
+    let (us, them) = if white { (board.white, board.black) } else { (board.black, board.white) };
+    let attacks = get_attacks(board, them, !white).all();
+    attacks & board.kings & us
+}
 
-    attacks |= ortho.fill_north(empty).shift_north();
-    attacks |= ortho.fill_south(empty).shift_south();
-    attacks |= ortho.fill_east(empty).shift_east();
-    attacks |= ortho.fill_west(empty).shift_west();
+/// Moves of lane 0 that don't leave the mover's own king attacked, with the
+/// castling, en passant and underpromotions search leaves out
+pub fn legal_move_list(board: &Board) -> MoveList {
+    let white = board.white_to_move(0);
+    let mut moves = generate_move_list(board, 0);
+    add_special_moves(board, &mut moves);
+    let mut legal = MoveList::new();
+    for &mv in moves.iter() {
+        let mut next = *board;
+        next.play(mv);
+        if !king_attacked(&next, white) {
+            legal.push(mv);
+        }
+    }
+    legal
+}
 
-    attacks |= kings.king_attacks();
+fn add_special_moves(board: &Board, moves: &mut MoveList) {
+    let white = board.white_to_move(0);
+    let (us, them) = if white { (board.white, board.black) } else { (board.black, board.white) };
+    let (us, occupied) = (us.extract(0), board.occupied().extract(0));
+
+    for i in 0..moves.len() {
+        let mv = moves[i];
+        if mv.promotion_piece().is_some() {
+            for piece in 1..4 {
+                moves.push(Move::promotion(mv.from_square(), mv.to_square(), piece));
+            }
+        }
+    }
 
-    attacks
+    // The pawn taken sits just past the target square; the capturers are
+    // where a pawn of theirs on the target would attack
+    if let Some(ep) = board.ep_square(0).filter(|&sq| sq / 8 == if white { 5 } else { 2 }) {
+        let victim = if white { ep - 8 } else { ep + 8 };
+        let square = Lane::from_single(1 << ep);
+        let beside = if white { square.shift_south_east() | square.shift_south_west() } else { square.shift_north_east() | square.shift_north_west() };
+        if (board.pawns & them).extract(0) & 1 << victim != 0 && occupied & 1 << ep == 0 {
+            let mut p = beside.extract(0) & board.pawns.extract(0) & us;
+            while p != 0 {
+                moves.push(Move::with_kind(p.trailing_zeros(), ep, Move::EN_PASSANT));
+                p &= p - 1;
+            }
+        }
+    }
+
+    // The king may not castle out of or through check; the legality filter
+    // covers the square it lands on
+    let base = if white { 0 } else { 56 };
+    let rights = board.castling_rights(0) >> if white { 0 } else { 2 };
+    let rooks = board.rooks().extract(0) & us;
+    if board.kings.extract(0) & us & 1 << (base + 4) == 0 || rights & 3 == 0 {
+        return;
+    }
+    let attacked = get_attacks(board, them, !white).all().extract(0);
+    let sides = [(1, base + 7, 0x60u64 << base, base + 5, base + 6), (2, base, 0x0eu64 << base, base + 3, base + 2)];
+    for (right, rook, between, crossed, to) in sides {
+        if rights & right != 0 && rooks & 1 << rook != 0 && occupied & between == 0 && attacked & (1 << (base + 4) | 1 << crossed) == 0 {
+            moves.push(Move::with_kind(base + 4, to, Move::CASTLING));
+        }
+    }
+}
+
+#[inline(always)]
+fn diagonal_attacks(diag: Lane, empty: Lane) -> Lane {
+    diag.fill_north_east(empty).shift_north_east()
+        | diag.fill_north_west(empty).shift_north_west()
+        | diag.fill_south_east(empty).shift_south_east()
+        | diag.fill_south_west(empty).shift_south_west()
+}
+
+#[inline(always)]
+fn orthogonal_attacks(ortho: Lane, empty: Lane) -> Lane {
+    ortho.fill_north(empty).shift_north()
+        | ortho.fill_south(empty).shift_south()
+        | ortho.fill_east(empty).shift_east()
+        | ortho.fill_west(empty).shift_west()
 }
 
+/// Pseudo-legal moves of one lane; moves into check are included
 pub fn generate_moves_for_lane(board: &Board, lane_idx: usize) -> Vec<MoveField> {
-    let mut move_fields = Vec::new();
+    generate_move_list(board, lane_idx).iter().map(|mv| mv.to_field()).collect()
+}
+
+/// generate_moves_for_lane as a MoveList
+pub fn generate_move_list(board: &Board, lane_idx: usize) -> MoveList {
+    #[cfg(target_arch = "x86_64")]
+    match crate::cpu::path() {
+        crate::cpu::Path::Avx512 => return unsafe { generate_moves_avx512(board, lane_idx) },
+        crate::cpu::Path::Avx2 => return unsafe { generate_moves_avx2(board, lane_idx) },
+        _ => {}
+    }
+    generate_moves(board, lane_idx)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f,avx512bw,avx512vl,avx2,popcnt,bmi1")]
+unsafe fn generate_moves_avx512(board: &Board, lane_idx: usize) -> MoveList {
+    generate_moves(board, lane_idx)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,popcnt,bmi1")]
+unsafe fn generate_moves_avx2(board: &Board, lane_idx: usize) -> MoveList {
+    generate_moves(board, lane_idx)
+}
+
+// Inlined into each path so it is compiled once per instruction set
+#[inline(always)]
+fn generate_moves(board: &Board, lane_idx: usize) -> MoveList {
+    let mut moves = MoveList::new();
     let occupied = board.occupied().extract(lane_idx);
     let empty = !occupied;
 
@@ -64,7 +343,7 @@ pub fn generate_moves_for_lane(board: &Board, lane_idx: usize) -> Vec<MoveField>
     while p != 0 {
         let from_bit = 1 << p.trailing_zeros();
         let targets = if white_turn { (from_bit << 8) & empty } else { (from_bit >> 8) & empty };
-        add_moves(&mut move_fields, from_bit, targets);
+        add_pawn_moves(&mut moves, from_bit, targets);
 
         if white_turn {
             if (from_bit & 0x000000000000ff00) != 0 {
@@ -72,7 +351,7 @@ pub fn generate_moves_for_lane(board: &Board, lane_idx: usize) -> Vec<MoveField>
                 if (push1 & empty) != 0 {
                     let push2 = push1 << 8;
                     if (push2 & empty) != 0 {
-                        add_moves(&mut move_fields, from_bit, push2);
+                        add_moves(&mut moves, from_bit, push2);
                     }
                 }
             }
@@ -82,7 +361,7 @@ pub fn generate_moves_for_lane(board: &Board, lane_idx: usize) -> Vec<MoveField>
                 if (push1 & empty) != 0 {
                     let push2 = push1 >> 8;
                     if (push2 & empty) != 0 {
-                        add_moves(&mut move_fields, from_bit, push2);
+                        add_moves(&mut moves, from_bit, push2);
                     }
                 }
             }
@@ -93,7 +372,7 @@ pub fn generate_moves_for_lane(board: &Board, lane_idx: usize) -> Vec<MoveField>
         } else {
             ((from_bit >> 7) & !0x0101010101010101u64 & them) | ((from_bit >> 9) & !0x8080808080808080u64 & them)
         };
-        add_moves(&mut move_fields, from_bit, caps);
+        add_pawn_moves(&mut moves, from_bit, caps);
         p &= p - 1;
     }
 
@@ -101,7 +380,7 @@ pub fn generate_moves_for_lane(board: &Board, lane_idx: usize) -> Vec<MoveField>
     while l != 0 {
         let from_bit = 1 << l.trailing_zeros();
         let targets = Lane::from_single(from_bit).knight_attacks().extract(0) & !us;
-        add_moves(&mut move_fields, from_bit, targets);
+        add_moves(&mut moves, from_bit, targets);
         l &= l - 1;
     }
 
@@ -127,7 +406,7 @@ pub fn generate_moves_for_lane(board: &Board, lane_idx: usize) -> Vec<MoveField>
                         l_from.fill_east(l_empty).shift_east() |
                         l_from.fill_west(l_empty).shift_west()).extract(0);
         }
-        add_moves(&mut move_fields, from_bit, targets & !us);
+        add_moves(&mut moves, from_bit, targets & !us);
         s &= s - 1;
     }
 
@@ -135,38 +414,233 @@ pub fn generate_moves_for_lane(board: &Board, lane_idx: usize) -> Vec<MoveField>
     while k != 0 {
         let from_bit = 1 << k.trailing_zeros();
         let targets = Lane::from_single(from_bit).king_attacks().extract(0) & !us;
-        add_moves(&mut move_fields, from_bit, targets);
+        add_moves(&mut moves, from_bit, targets);
         k &= k - 1;
     }
 
-    move_fields
+    moves
 }
 
-fn add_moves(moves: &mut Vec<MoveField>, from_bit: u64, targets: u64) {
+// Pawn moves onto the last rank are queen promotions, as apply_move plays them
+#[inline(always)]
+fn add_pawn_moves(moves: &mut MoveList, from_bit: u64, targets: u64) {
+    let from = from_bit.trailing_zeros();
     let mut t = targets;
     while t != 0 {
-        let to_bit = 1 << t.trailing_zeros();
-        moves.push(MoveField {
-            from: Lane::from_single(from_bit),
-            to: Lane::from_single(to_bit),
-        });
+        let to = t.trailing_zeros();
+        moves.push(if (1u64 << to) & board::LAST_RANKS != 0 { Move::promotion(from, to, 4) } else { Move::new(from, to) });
         t &= t - 1;
     }
 }
 
-pub fn pack_move_fields(moves: &[MoveField]) -> Vec<MoveField> {
-    if moves.is_empty() { return Vec::new(); }
-    let mut packed = Vec::new();
-    for i in (0..moves.len()).step_by(4) {
-        let m1 = moves[i];
-        let m2 = moves.get(i+1).unwrap_or(&m1);
-        let m3 = moves.get(i+2).unwrap_or(&m1);
-        let m4 = moves.get(i+3).unwrap_or(&m1);
+#[inline(always)]
+fn add_moves(moves: &mut MoveList, from_bit: u64, targets: u64) {
+    let from = from_bit.trailing_zeros();
+    let mut t = targets;
+    while t != 0 {
+        moves.push(Move::new(from, t.trailing_zeros()));
+        t &= t - 1;
+    }
+}
 
-        packed.push(MoveField {
-            from: Lane::new(m1.from.extract(0), m2.from.extract(0), m3.from.extract(0), m4.from.extract(0)),
-            to: Lane::new(m1.to.extract(0), m2.to.extract(0), m3.to.extract(0), m4.to.extract(0)),
-        });
+/// Moves packed LANES to a MoveField, without allocating. Batch `b` holds
+/// the `b`-th move of each lane; a lane with fewer moves is empty there.
+pub struct MoveBatches {
+    from: [[u64; LANES]; MAX_MOVES],
+    to: [[u64; LANES]; MAX_MOVES],
+    counts: [usize; LANES],
+}
+
+impl MoveBatches {
+    #[inline(always)]
+    pub(crate) fn new() -> Self {
+        Self { from: [[0; LANES]; MAX_MOVES], to: [[0; LANES]; MAX_MOVES], counts: [0; LANES] }
+    }
+
+    // Empties the batches in use, so a buffer can be filled again
+    #[inline(always)]
+    fn clear(&mut self) {
+        for b in 0..self.len() {
+            self.from[b] = [0; LANES];
+            self.to[b] = [0; LANES];
+        }
+        self.counts = [0; LANES];
+    }
+
+    // Like MoveList::push, drops moves once the lane is full
+    #[inline(always)]
+    fn push(&mut self, lane: usize, from: u64, to: u64) {
+        let b = self.counts[lane];
+        if b < MAX_MOVES {
+            self.from[b][lane] = from;
+            self.to[b][lane] = to;
+            self.counts[lane] += 1;
+        }
+    }
+
+    // The next move of a single position, filling each batch's lanes in turn
+    #[inline(always)]
+    fn push_spread(&mut self, from: u64, to: u64) {
+        let n: usize = self.counts.iter().sum();
+        self.push(n % LANES, from, to);
+    }
+
+    // One move per lane, skipping lanes whose `to` is empty
+    #[inline(always)]
+    fn push_lanes(&mut self, from: Lane, to: Lane) {
+        let (from, to) = (from.to_array(), to.to_array());
+        for lane in (0..LANES).filter(|&lane| to[lane] != 0) {
+            self.push(lane, from[lane], to[lane]);
+        }
+    }
+
+    /// Number of batches
+    pub fn len(&self) -> usize {
+        self.counts.iter().copied().max().unwrap_or(0)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Lanes of batch `b` that hold a move
+    pub fn moves_in(&self, b: usize) -> usize {
+        self.counts.iter().filter(|&&count| count > b).count()
+    }
+
+    pub fn get(&self, b: usize) -> MoveField {
+        MoveField { from: Lane::from_array(self.from[b]), to: Lane::from_array(self.to[b]) }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = MoveField> + '_ {
+        (0..self.len()).map(|b| self.get(b))
+    }
+}
+
+/// Moves of one position, LANES to a batch
+pub fn pack_move_fields(moves: &[Move]) -> MoveBatches {
+    let mut batches = MoveBatches::new();
+    for mv in moves {
+        batches.push_spread(1 << mv.from_square(), 1 << mv.to_square());
+    }
+    batches
+}
+
+// Calls `f` with the lowest square of `set` in each lane until all are used
+#[inline(always)]
+fn for_each_square(set: Lane, mut f: impl FnMut(Lane)) {
+    let mut rest = set;
+    while !rest.is_empty() {
+        let square = rest & (Lane::EMPTY - rest);
+        f(square);
+        rest ^= square;
+    }
+}
+
+// The side to move's pieces within `mine`, in every lane at once:
+// `emit(from, targets)` gets one piece per lane, with an empty `from` in lanes
+// that have run out. The order is generate_moves_for_lane's, so a pawn's
+// single push, double push and captures come separately.
+#[inline(always)]
+fn for_each_piece(board: &Board, mine: Lane, mut emit: impl FnMut(Lane, Lane)) {
+    let black = (board.metadata & Lane::from_single(1 << crate::board::META_TURN)).is_not_zero_mask();
+    let us = black.select(board.black, board.white);
+    let them = black.select(board.white, board.black);
+    let empty = !board.occupied();
+    let forward = |l: Lane| black.select(l.shift_south(), l.shift_north());
+    let start_rank = black.select(Lane::from_single(0x00ff000000000000), Lane::from_single(0x000000000000ff00));
+
+    for_each_square(board.pawns & us & mine, |from| {
+        emit(from, forward(from) & empty);
+        emit(from, forward(forward(from & start_rank) & empty) & empty);
+        let captures = black.select(from.shift_south_east() | from.shift_south_west(), from.shift_north_east() | from.shift_north_west());
+        emit(from, captures & them);
+    });
+    for_each_square(board.leapers & us & mine, |from| emit(from, from.knight_attacks() & !us));
+    for_each_square(board.sliders & us & mine, |from| {
+        let targets = diagonal_attacks(from & board.diagonal, empty) | orthogonal_attacks(from & board.orthogonal, empty);
+        emit(from, targets & !us);
+    });
+    for_each_square(board.kings & us & mine, |from| emit(from, from.king_attacks() & !us));
+}
+
+/// Pseudo-legal moves of every lane's own position, generated for all lanes
+/// at once; lane `i` of batch `b` is the `b`-th move of lane `i`
+pub fn generate_batches(board: &Board) -> MoveBatches {
+    let mut batches = MoveBatches::new();
+    for_each_piece(board, Lane::from_single(!0), |from, targets| {
+        for_each_square(targets, |to| batches.push_lanes(from, to));
+    });
+    batches
+}
+
+/// Pseudo-legal moves of a position broadcast to every lane, captures first,
+/// LANES to a batch: the children of a VPTS frontier node
+pub fn generate_packed(board: &Board) -> MoveBatches {
+    let mut batches = MoveBatches::new();
+    generate_packed_into(board, &mut batches, &mut Vec::with_capacity(MAX_MOVES));
+    batches
+}
+
+/// [`generate_packed`] into buffers the caller reuses; `quiet` holds the quiet
+/// moves until the captures are in
+pub fn generate_packed_into(board: &Board, batches: &mut MoveBatches, quiet: &mut Vec<(u64, u64)>) {
+    batches.clear();
+    quiet.clear();
+    #[cfg(target_arch = "x86_64")]
+    match crate::cpu::path() {
+        crate::cpu::Path::Avx512 => return unsafe { generate_packed_avx512(board, batches, quiet) },
+        crate::cpu::Path::Avx2 => return unsafe { generate_packed_avx2(board, batches, quiet) },
+        _ => {}
+    }
+    packed(board, batches, quiet)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx512f,avx512bw,avx512vl,avx2,popcnt,bmi1")]
+unsafe fn generate_packed_avx512(board: &Board, batches: &mut MoveBatches, quiet: &mut Vec<(u64, u64)>) {
+    packed(board, batches, quiet)
+}
+
+#[cfg(target_arch = "x86_64")]
+#[target_feature(enable = "avx2,popcnt,bmi1")]
+unsafe fn generate_packed_avx2(board: &Board, batches: &mut MoveBatches, quiet: &mut Vec<(u64, u64)>) {
+    packed(board, batches, quiet)
+}
+
+// The side to move's pieces are dealt out across the lanes, so every lane
+// generates the moves of its own share, as in generate_batches
+#[inline(always)]
+fn packed(board: &Board, batches: &mut MoveBatches, quiet: &mut Vec<(u64, u64)>) {
+    let occupied = board.occupied().extract(0);
+
+    // Lane i gets the i-th piece, the (i + LANES)-th and so on
+    let mut shares = [0u64; LANES];
+    let mut rest = if board.white_to_move(0) { board.white } else { board.black }.extract(0);
+    let mut i = 0;
+    while rest != 0 {
+        let square = rest & rest.wrapping_neg();
+        shares[i % LANES] |= square;
+        rest ^= square;
+        i += 1;
+    }
+
+    for_each_piece(board, Lane::from_array(shares), |from, targets| {
+        let (from, targets) = (from.to_array(), targets.to_array());
+        for lane in 0..LANES {
+            let mut t = targets[lane];
+            while t != 0 {
+                let to = t & t.wrapping_neg();
+                if to & occupied != 0 {
+                    batches.push_spread(from[lane], to);
+                } else if quiet.len() < MAX_MOVES {
+                    quiet.push((from[lane], to));
+                }
+                t ^= to;
+            }
+        }
+    });
+    for &(from, to) in quiet.iter() {
+        batches.push_spread(from, to);
     }
-    packed
 }
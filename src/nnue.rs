@@ -0,0 +1,304 @@
+#[cfg(target_arch = "x86_64")]
+use std::arch::x86_64::*;
+use std::fs;
+use std::sync::{Arc, RwLock};
+
+use crate::board::{Board, META_TURN};
+
+// File layout, all little-endian:
+//   magic "VSPRNNUE", u32 version, u32 hidden size N (a multiple of 16),
+//   i16 feature weights [768][N], i16 feature biases [N],
+//   i16 output weights [2N] (side to move half first), i16 output bias
+const MAGIC: &[u8; 8] = b"VSPRNNUE";
+const VERSION: u32 = 1;
+
+pub const INPUTS: usize = 768;
+const QA: i32 = 255;
+const QB: i32 = 64;
+const SCALE: i32 = 400;
+
+pub struct Network {
+    hidden: usize,
+    feature_weights: Vec<i16>,
+    feature_bias: Vec<i16>,
+    output_weights: Vec<i16>,
+    output_bias: i16,
+}
+
+// Hidden layer pre-activations from White's and Black's point of view
+#[derive(Clone)]
+pub struct Accumulator {
+    values: [Vec<i16>; 2],
+}
+
+static NETWORK: RwLock<Option<Arc<Network>>> = RwLock::new(None);
+
+// The network `EvalFile` points at, if any
+pub fn network() -> Option<Arc<Network>> {
+    NETWORK.read().unwrap().clone()
+}
+
+pub fn set_network(net: Option<Arc<Network>>) {
+    *NETWORK.write().unwrap() = net;
+}
+
+// Input index of a piece as seen from `perspective`: our pieces come first,
+// and Black sees the board rank-flipped
+fn feature_index(perspective: usize, color: usize, piece: usize, sq: usize) -> usize {
+    let relative = (color != perspective) as usize;
+    let sq = if perspective == 0 { sq } else { sq ^ 56 };
+    (relative * 6 + piece) * 64 + sq
+}
+
+impl Network {
+    pub fn hidden_size(&self) -> usize {
+        self.hidden
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|e| e.to_string())?;
+        Self::from_bytes(&bytes)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < 16 || &bytes[..8] != MAGIC {
+            return Err("not a Vesper network file".to_string());
+        }
+        let read_u32 = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        if read_u32(8) != VERSION {
+            return Err(format!("unsupported network version {}", read_u32(8)));
+        }
+        let hidden = read_u32(12) as usize;
+        if hidden == 0 || !hidden.is_multiple_of(16) {
+            return Err(format!("hidden size {} is not a positive multiple of 16", hidden));
+        }
+
+        let count = INPUTS * hidden + hidden + 2 * hidden + 1;
+        let body = &bytes[16..];
+        if body.len() != count * 2 {
+            return Err(format!("expected {} bytes of weights, found {}", count * 2, body.len()));
+        }
+        let values: Vec<i16> = body.chunks_exact(2).map(|c| i16::from_le_bytes([c[0], c[1]])).collect();
+
+        let (feature_weights, rest) = values.split_at(INPUTS * hidden);
+        let (feature_bias, rest) = rest.split_at(hidden);
+        let (output_weights, rest) = rest.split_at(2 * hidden);
+
+        Ok(Self {
+            hidden,
+            feature_weights: feature_weights.to_vec(),
+            feature_bias: feature_bias.to_vec(),
+            output_weights: output_weights.to_vec(),
+            output_bias: rest[0],
+        })
+    }
+
+    #[cfg(test)]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.hidden as u32).to_le_bytes());
+        let values = self.feature_weights.iter()
+            .chain(self.feature_bias.iter())
+            .chain(self.output_weights.iter())
+            .chain(std::iter::once(&self.output_bias));
+        for v in values {
+            bytes.extend_from_slice(&v.to_le_bytes());
+        }
+        bytes
+    }
+
+    // A 16-neuron network that counts material from the side to move, used to
+    // check loading, incremental updates and both inference paths
+    #[cfg(test)]
+    pub fn material_test_net() -> Self {
+        const HIDDEN: usize = 16;
+        const COUNT_WEIGHT: i16 = 16;
+        let values = [100, 320, 330, 500, 900];
+
+        let mut feature_weights = vec![0i16; INPUTS * HIDDEN];
+        let mut output_weights = vec![0i16; 2 * HIDDEN];
+        for (piece, value) in values.iter().enumerate() {
+            for relative in 0..2 {
+                let neuron = relative * 6 + piece;
+                for sq in 0..64 {
+                    feature_weights[((relative * 6 + piece) * 64 + sq) * HIDDEN + neuron] = COUNT_WEIGHT;
+                }
+                let weight = (value * QA * QB) as f64 / (COUNT_WEIGHT as i32 * SCALE) as f64;
+                let weight = weight.round() as i16;
+                output_weights[neuron] = if relative == 0 { weight } else { -weight };
+            }
+        }
+
+        Self {
+            hidden: HIDDEN,
+            feature_weights,
+            feature_bias: vec![0; HIDDEN],
+            output_weights,
+            output_bias: 0,
+        }
+    }
+
+    pub fn new_accumulator(&self) -> Accumulator {
+        Accumulator { values: [vec![0; self.hidden], vec![0; self.hidden]] }
+    }
+
+    fn weights(&self, index: usize) -> &[i16] {
+        &self.feature_weights[index * self.hidden..(index + 1) * self.hidden]
+    }
+
+    // Full rebuild of `acc` from lane `lane` of `board`
+    pub fn refresh(&self, acc: &mut Accumulator, board: &Board, lane: usize) {
+        let pieces = board.pieces(lane);
+        for perspective in 0..2 {
+            let values = &mut acc.values[perspective];
+            values.copy_from_slice(&self.feature_bias);
+            for (color, sets) in pieces.iter().enumerate() {
+                for (piece, set) in sets.iter().enumerate() {
+                    let mut bb = *set;
+                    while bb != 0 {
+                        let sq = bb.trailing_zeros() as usize;
+                        add_weights(values, self.weights(feature_index(perspective, color, piece, sq)));
+                        bb &= bb - 1;
+                    }
+                }
+            }
+        }
+    }
+
+    // Incremental update: `child` is `parent` after a move. Diffing the piece
+    // bitboards covers captures, castling, promotions and en passant alike.
+    pub fn update(&self, acc: &mut Accumulator, parent_acc: &Accumulator, parent: &Board, child: &Board, lane: usize) {
+        let before = parent.pieces(lane);
+        let after = child.pieces(lane);
+
+        for perspective in 0..2 {
+            let values = &mut acc.values[perspective];
+            values.copy_from_slice(&parent_acc.values[perspective]);
+            for color in 0..2 {
+                for piece in 0..6 {
+                    let mut removed = before[color][piece] & !after[color][piece];
+                    while removed != 0 {
+                        let sq = removed.trailing_zeros() as usize;
+                        sub_weights(values, self.weights(feature_index(perspective, color, piece, sq)));
+                        removed &= removed - 1;
+                    }
+                    let mut added = after[color][piece] & !before[color][piece];
+                    while added != 0 {
+                        let sq = added.trailing_zeros() as usize;
+                        add_weights(values, self.weights(feature_index(perspective, color, piece, sq)));
+                        added &= added - 1;
+                    }
+                }
+            }
+        }
+    }
+
+    // Lane 0 of `board` evaluated from scratch
+    pub fn evaluate_board(&self, board: &Board) -> i32 {
+        let mut acc = self.new_accumulator();
+        self.refresh(&mut acc, board, 0);
+        self.evaluate(&acc, board, 0)
+    }
+
+    // Score relative to the side to move of lane `lane`
+    pub fn evaluate(&self, acc: &Accumulator, board: &Board, lane: usize) -> i32 {
+        let stm = ((board.metadata.extract(lane) >> META_TURN) & 1) as usize;
+        let (ours, theirs) = self.output_weights.split_at(self.hidden);
+        let sum = output_dot(&acc.values[stm], ours) + output_dot(&acc.values[1 - stm], theirs);
+        (sum + self.output_bias as i32) * SCALE / (QA * QB)
+    }
+}
+
+fn add_weights(values: &mut [i16], weights: &[i16]) {
+    #[cfg(target_arch = "x86_64")]
+    if matches!(crate::cpu::path(), crate::cpu::Path::Avx512 | crate::cpu::Path::Avx2) {
+        return unsafe { add_weights_avx2(values, weights) };
+    }
+    add_weights_scalar(values, weights)
+}
+
+fn sub_weights(values: &mut [i16], weights: &[i16]) {
+    #[cfg(target_arch = "x86_64")]
+    if matches!(crate::cpu::path(), crate::cpu::Path::Avx512 | crate::cpu::Path::Avx2) {
+        return unsafe { sub_weights_avx2(values, weights) };
+    }
+    sub_weights_scalar(values, weights)
+}
+
+fn output_dot(values: &[i16], weights: &[i16]) -> i32 {
+    #[cfg(target_arch = "x86_64")]
+    if matches!(crate::cpu::path(), crate::cpu::Path::Avx512 | crate::cpu::Path::Avx2) {
+        return unsafe { output_dot_avx2(values, weights) };
+    }
+    output_dot_scalar(values, weights)
+}
+
+pub fn add_weights_scalar(values: &mut [i16], weights: &[i16]) {
+    for (v, w) in values.iter_mut().zip(weights) {
+        *v = v.wrapping_add(*w);
+    }
+}
+
+pub fn sub_weights_scalar(values: &mut [i16], weights: &[i16]) {
+    for (v, w) in values.iter_mut().zip(weights) {
+        *v = v.wrapping_sub(*w);
+    }
+}
+
+// Clipped ReLU on the accumulator, dotted with the output weights
+pub fn output_dot_scalar(values: &[i16], weights: &[i16]) -> i32 {
+    values.iter().zip(weights).map(|(&v, &w)| (v as i32).clamp(0, QA) * w as i32).sum()
+}
+
+#[cfg(target_arch = "x86_64")]
+/// # Safety
+/// The CPU must support AVX2.
+#[target_feature(enable = "avx2")]
+pub unsafe fn add_weights_avx2(values: &mut [i16], weights: &[i16]) {
+    for (v, w) in values.chunks_exact_mut(16).zip(weights.chunks_exact(16)) {
+        unsafe {
+            let a = _mm256_loadu_si256(v.as_ptr() as *const __m256i);
+            let b = _mm256_loadu_si256(w.as_ptr() as *const __m256i);
+            _mm256_storeu_si256(v.as_mut_ptr() as *mut __m256i, _mm256_add_epi16(a, b));
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+/// # Safety
+/// The CPU must support AVX2.
+#[target_feature(enable = "avx2")]
+pub unsafe fn sub_weights_avx2(values: &mut [i16], weights: &[i16]) {
+    for (v, w) in values.chunks_exact_mut(16).zip(weights.chunks_exact(16)) {
+        unsafe {
+            let a = _mm256_loadu_si256(v.as_ptr() as *const __m256i);
+            let b = _mm256_loadu_si256(w.as_ptr() as *const __m256i);
+            _mm256_storeu_si256(v.as_mut_ptr() as *mut __m256i, _mm256_sub_epi16(a, b));
+        }
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+/// # Safety
+/// The CPU must support AVX2.
+#[target_feature(enable = "avx2")]
+pub unsafe fn output_dot_avx2(values: &[i16], weights: &[i16]) -> i32 {
+    let zero = _mm256_setzero_si256();
+    let ceiling = _mm256_set1_epi16(QA as i16);
+    let mut sum = _mm256_setzero_si256();
+
+    for (v, w) in values.chunks_exact(16).zip(weights.chunks_exact(16)) {
+        unsafe {
+            let a = _mm256_loadu_si256(v.as_ptr() as *const __m256i);
+            let b = _mm256_loadu_si256(w.as_ptr() as *const __m256i);
+            let clipped = _mm256_min_epi16(_mm256_max_epi16(a, zero), ceiling);
+            sum = _mm256_add_epi32(sum, _mm256_madd_epi16(clipped, b));
+        }
+    }
+
+    let mut lanes = [0i32; 8];
+    unsafe { _mm256_storeu_si256(lanes.as_mut_ptr() as *mut __m256i, sum) };
+    lanes.iter().sum()
+}
@@ -0,0 +1,430 @@
+use std::fmt::Write;
+
+use crate::eval::Score;
+
+pub const PIECE_NAMES: [&str; 6] = ["pawn", "knight", "bishop", "rook", "queen", "king"];
+
+// Offsets of each parameter group in the flat vector
+pub const MATERIAL: usize = 0;
+pub const PST_MG: usize = MATERIAL + 6;
+pub const PST_EG: usize = PST_MG + 6 * 64;
+pub const MOBILITY: usize = PST_EG + 6 * 64;
+pub const TENSION: usize = MOBILITY + 1;
+pub const TEMPO: usize = TENSION + 1;
+pub const DOUBLED: usize = TEMPO + 1;
+pub const ISOLATED: usize = DOUBLED + 2;
+pub const BACKWARD: usize = ISOLATED + 2;
+pub const CONNECTED: usize = BACKWARD + 2;
+pub const PHALANX: usize = CONNECTED + 2;
+pub const PASSED: usize = PHALANX + 2;
+pub const PASSED_ENEMY_KING: usize = PASSED + 2 * 8;
+pub const PASSED_OWN_KING: usize = PASSED_ENEMY_KING + 1;
+pub const ATTACK_UNITS: usize = PASSED_OWN_KING + 1;
+pub const SHIELD_NEAR: usize = ATTACK_UNITS + 4;
+pub const SHIELD_FAR: usize = SHIELD_NEAR + 1;
+pub const STORM: usize = SHIELD_FAR + 1;
+pub const SEMI_OPEN_FILE: usize = STORM + 1;
+pub const OPEN_FILE: usize = SEMI_OPEN_FILE + 1;
+pub const SAFETY: usize = OPEN_FILE + 1;
+pub const COUNT: usize = SAFETY + 100;
+
+// Every evaluation weight. PSTs are laid out as seen from White, rank 8
+// first; king safety attack units are per attacked zone square for
+// knight, bishop, rook and queen, and `safety` maps units to a penalty.
+#[derive(Clone, Debug)]
+pub struct Params {
+    pub pawn: i32,
+    pub knight: i32,
+    pub bishop: i32,
+    pub rook: i32,
+    pub queen: i32,
+    pub king: i32,
+    pub pst_mg: [[i32; 64]; 6],
+    pub pst_eg: [[i32; 64]; 6],
+    pub mobility: i32,
+    pub tension: i32,
+    pub tempo: i32,
+    pub doubled: Score,
+    pub isolated: Score,
+    pub backward: Score,
+    pub connected: Score,
+    pub phalanx: Score,
+    pub passed: [Score; 8],
+    pub passed_enemy_king: i32,
+    pub passed_own_king: i32,
+    pub attack_units: [i32; 4],
+    pub shield_near: i32,
+    pub shield_far: i32,
+    pub storm: i32,
+    pub semi_open_file: i32,
+    pub open_file: i32,
+    pub safety: [i32; 100],
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        DEFAULT
+    }
+}
+
+fn square_name(index: usize) -> String {
+    let file = (index % 8) as u8 + b'a';
+    let rank = 8 - index / 8;
+    format!("{}{}", file as char, rank)
+}
+
+impl Params {
+    // Named mutable views of every parameter, in vector order
+    fn entries(&mut self) -> Vec<(String, &mut i32)> {
+        let mut out: Vec<(String, &mut i32)> = Vec::with_capacity(COUNT);
+
+        let material = [
+            &mut self.pawn, &mut self.knight, &mut self.bishop,
+            &mut self.rook, &mut self.queen, &mut self.king,
+        ];
+        for (name, v) in PIECE_NAMES.iter().zip(material) {
+            out.push((name.to_string(), v));
+        }
+
+        for (phase, tables) in [("pst_mg", &mut self.pst_mg), ("pst_eg", &mut self.pst_eg)] {
+            for (p, table) in tables.iter_mut().enumerate() {
+                for (sq, v) in table.iter_mut().enumerate() {
+                    out.push((format!("{}.{}.{}", phase, PIECE_NAMES[p], square_name(sq)), v));
+                }
+            }
+        }
+
+        out.push(("mobility".to_string(), &mut self.mobility));
+        out.push(("tension".to_string(), &mut self.tension));
+        out.push(("tempo".to_string(), &mut self.tempo));
+
+        let pawn_terms = [
+            ("doubled", &mut self.doubled),
+            ("isolated", &mut self.isolated),
+            ("backward", &mut self.backward),
+            ("connected", &mut self.connected),
+            ("phalanx", &mut self.phalanx),
+        ];
+        for (name, s) in pawn_terms {
+            out.push((format!("{}.mg", name), &mut s.mg));
+            out.push((format!("{}.eg", name), &mut s.eg));
+        }
+        for (rank, s) in self.passed.iter_mut().enumerate() {
+            out.push((format!("passed.{}.mg", rank + 1), &mut s.mg));
+            out.push((format!("passed.{}.eg", rank + 1), &mut s.eg));
+        }
+
+        out.push(("passed_enemy_king".to_string(), &mut self.passed_enemy_king));
+        out.push(("passed_own_king".to_string(), &mut self.passed_own_king));
+        for (name, v) in ["knight", "bishop", "rook", "queen"].iter().zip(self.attack_units.iter_mut()) {
+            out.push((format!("attack_units.{}", name), v));
+        }
+        out.push(("shield_near".to_string(), &mut self.shield_near));
+        out.push(("shield_far".to_string(), &mut self.shield_far));
+        out.push(("storm".to_string(), &mut self.storm));
+        out.push(("semi_open_file".to_string(), &mut self.semi_open_file));
+        out.push(("open_file".to_string(), &mut self.open_file));
+        for (units, v) in self.safety.iter_mut().enumerate() {
+            out.push((format!("safety.{}", units), v));
+        }
+
+        out
+    }
+
+    pub fn names() -> Vec<String> {
+        let mut params = DEFAULT;
+        params.entries().into_iter().map(|(name, _)| name).collect()
+    }
+
+    pub fn to_vec(&self) -> Vec<i32> {
+        let mut params = self.clone();
+        params.entries().into_iter().map(|(_, v)| *v).collect()
+    }
+
+    pub fn from_slice(values: &[i32]) -> Self {
+        let mut params = DEFAULT;
+        for ((_, v), x) in params.entries().into_iter().zip(values) {
+            *v = *x;
+        }
+        params
+    }
+
+    // Parameters the tuner leaves alone: the king is never traded, and attack
+    // units pick the safety table entry rather than scaling linearly
+    pub fn is_tunable(index: usize) -> bool {
+        index != MATERIAL + 5 && !(ATTACK_UNITS..ATTACK_UNITS + 4).contains(&index)
+    }
+
+    // One `name value` pair per line
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        for (name, v) in Params::names().iter().zip(self.to_vec()) {
+            writeln!(out, "{} {}", name, v).unwrap();
+        }
+        out
+    }
+
+    // Starts from the defaults, so a partial file only overrides what it names
+    pub fn from_text(text: &str) -> Result<Self, String> {
+        let mut params = DEFAULT;
+        let mut entries = params.entries();
+        for line in text.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.split_whitespace();
+            let (Some(name), Some(value)) = (parts.next(), parts.next()) else {
+                return Err(format!("malformed line: {}", line));
+            };
+            let value: i32 = value.parse().map_err(|_| format!("bad value for {}: {}", name, value))?;
+            match entries.iter_mut().find(|(n, _)| n == name) {
+                Some((_, v)) => **v = value,
+                None => return Err(format!("unknown parameter: {}", name)),
+            }
+        }
+        drop(entries);
+        Ok(params)
+    }
+
+    // The `DEFAULT` definition below, as Rust source
+    pub fn to_rust(&self) -> String {
+        let mut out = String::new();
+        let score = |s: &Score| format!("Score::new({}, {})", s.mg, s.eg);
+
+        out.push_str(GENERATED_MARKER);
+        out.push_str("\n#[rustfmt::skip]\npub const DEFAULT: Params = Params {\n");
+        for (name, v) in PIECE_NAMES.iter().zip([self.pawn, self.knight, self.bishop, self.rook, self.queen, self.king]) {
+            writeln!(out, "    {}: {},", name, v).unwrap();
+        }
+        for (phase, tables) in [("pst_mg", &self.pst_mg), ("pst_eg", &self.pst_eg)] {
+            writeln!(out, "    {}: [", phase).unwrap();
+            for (p, table) in tables.iter().enumerate() {
+                let name = PIECE_NAMES[p];
+                writeln!(out, "        // {}{}\n        [", name[..1].to_uppercase(), &name[1..]).unwrap();
+                for row in table.chunks(8) {
+                    let cells: Vec<String> = row.iter().map(|v| format!("{:>4},", v)).collect();
+                    writeln!(out, "            {}", cells.join(" ")).unwrap();
+                }
+                out.push_str("        ],\n");
+            }
+            out.push_str("    ],\n");
+        }
+        writeln!(out, "    mobility: {},", self.mobility).unwrap();
+        writeln!(out, "    tension: {},", self.tension).unwrap();
+        writeln!(out, "    tempo: {},", self.tempo).unwrap();
+        writeln!(out, "    doubled: {},", score(&self.doubled)).unwrap();
+        writeln!(out, "    isolated: {},", score(&self.isolated)).unwrap();
+        writeln!(out, "    backward: {},", score(&self.backward)).unwrap();
+        writeln!(out, "    connected: {},", score(&self.connected)).unwrap();
+        writeln!(out, "    phalanx: {},", score(&self.phalanx)).unwrap();
+        out.push_str("    passed: [\n");
+        for s in self.passed.iter() {
+            writeln!(out, "        {},", score(s)).unwrap();
+        }
+        out.push_str("    ],\n");
+        writeln!(out, "    passed_enemy_king: {},", self.passed_enemy_king).unwrap();
+        writeln!(out, "    passed_own_king: {},", self.passed_own_king).unwrap();
+        let units: Vec<String> = self.attack_units.iter().map(|v| v.to_string()).collect();
+        writeln!(out, "    attack_units: [{}],", units.join(", ")).unwrap();
+        writeln!(out, "    shield_near: {},", self.shield_near).unwrap();
+        writeln!(out, "    shield_far: {},", self.shield_far).unwrap();
+        writeln!(out, "    storm: {},", self.storm).unwrap();
+        writeln!(out, "    semi_open_file: {},", self.semi_open_file).unwrap();
+        writeln!(out, "    open_file: {},", self.open_file).unwrap();
+        out.push_str("    safety: [\n");
+        for row in self.safety.chunks(10) {
+            let cells: Vec<String> = row.iter().map(|v| format!("{},", v)).collect();
+            writeln!(out, "        {}", cells.join(" ")).unwrap();
+        }
+        out.push_str("    ],\n};\n");
+        out
+    }
+}
+
+pub const GENERATED_MARKER: &str = "// Generated by `vesper tune`: everything from here to the end of the file is rewritten in place";
+
+// Generated by `vesper tune`: everything from here to the end of the file is rewritten in place
+#[rustfmt::skip]
+pub const DEFAULT: Params = Params {
+    pawn: 100,
+    knight: 320,
+    bishop: 330,
+    rook: 500,
+    queen: 900,
+    king: 10000,
+    pst_mg: [
+        // Pawn
+        [
+               0,    0,    0,    0,    0,    0,    0,    0,
+              50,   50,   50,   50,   50,   50,   50,   50,
+              10,   10,   20,   30,   30,   20,   10,   10,
+               5,    5,   10,   25,   25,   10,    5,    5,
+               0,    0,    0,   20,   20,    0,    0,    0,
+               5,   -5,  -10,    0,    0,  -10,   -5,    5,
+               5,   10,   10,  -20,  -20,   10,   10,    5,
+               0,    0,    0,    0,    0,    0,    0,    0,
+        ],
+        // Knight
+        [
+             -50,  -40,  -30,  -30,  -30,  -30,  -40,  -50,
+             -40,  -20,    0,    0,    0,    0,  -20,  -40,
+             -30,    0,   10,   15,   15,   10,    0,  -30,
+             -30,    5,   15,   20,   20,   15,    5,  -30,
+             -30,    0,   15,   20,   20,   15,    0,  -30,
+             -30,    5,   10,   15,   15,   10,    5,  -30,
+             -40,  -20,    0,    5,    5,    0,  -20,  -40,
+             -50,  -40,  -30,  -30,  -30,  -30,  -40,  -50,
+        ],
+        // Bishop
+        [
+             -20,  -10,  -10,  -10,  -10,  -10,  -10,  -20,
+             -10,    0,    0,    0,    0,    0,    0,  -10,
+             -10,    0,    5,   10,   10,    5,    0,  -10,
+             -10,    5,    5,   10,   10,    5,    5,  -10,
+             -10,    0,   10,   10,   10,   10,    0,  -10,
+             -10,   10,   10,   10,   10,   10,   10,  -10,
+             -10,    5,    0,    0,    0,    0,    5,  -10,
+             -20,  -10,  -10,  -10,  -10,  -10,  -10,  -20,
+        ],
+        // Rook
+        [
+               0,    0,    0,    0,    0,    0,    0,    0,
+               5,   10,   10,   10,   10,   10,   10,    5,
+              -5,    0,    0,    0,    0,    0,    0,   -5,
+              -5,    0,    0,    0,    0,    0,    0,   -5,
+              -5,    0,    0,    0,    0,    0,    0,   -5,
+              -5,    0,    0,    0,    0,    0,    0,   -5,
+              -5,    0,    0,    0,    0,    0,    0,   -5,
+               0,    0,    0,    5,    5,    0,    0,    0,
+        ],
+        // Queen
+        [
+             -20,  -10,  -10,   -5,   -5,  -10,  -10,  -20,
+             -10,    0,    0,    0,    0,    0,    0,  -10,
+             -10,    0,    5,    5,    5,    5,    0,  -10,
+              -5,    0,    5,    5,    5,    5,    0,   -5,
+               0,    0,    5,    5,    5,    5,    0,   -5,
+             -10,    5,    5,    5,    5,    5,    0,  -10,
+             -10,    0,    5,    0,    0,    0,    0,  -10,
+             -20,  -10,  -10,   -5,   -5,  -10,  -10,  -20,
+        ],
+        // King
+        [
+             -30,  -40,  -40,  -50,  -50,  -40,  -40,  -30,
+             -30,  -40,  -40,  -50,  -50,  -40,  -40,  -30,
+             -30,  -40,  -40,  -50,  -50,  -40,  -40,  -30,
+             -30,  -40,  -40,  -50,  -50,  -40,  -40,  -30,
+             -20,  -30,  -30,  -40,  -40,  -30,  -30,  -20,
+             -10,  -20,  -20,  -20,  -20,  -20,  -20,  -10,
+              20,   20,    0,    0,    0,    0,   20,   20,
+              20,   30,   10,    0,    0,   10,   30,   20,
+        ],
+    ],
+    pst_eg: [
+        // Pawn
+        [
+               0,    0,    0,    0,    0,    0,    0,    0,
+              80,   80,   80,   80,   80,   80,   80,   80,
+              50,   50,   50,   50,   50,   50,   50,   50,
+              30,   30,   30,   30,   30,   30,   30,   30,
+              20,   20,   20,   20,   20,   20,   20,   20,
+              10,   10,   10,   10,   10,   10,   10,   10,
+               0,    0,    0,    0,    0,    0,    0,    0,
+               0,    0,    0,    0,    0,    0,    0,    0,
+        ],
+        // Knight
+        [
+             -50,  -40,  -30,  -30,  -30,  -30,  -40,  -50,
+             -40,  -20,    0,    0,    0,    0,  -20,  -40,
+             -30,    0,   10,   15,   15,   10,    0,  -30,
+             -30,    5,   15,   20,   20,   15,    5,  -30,
+             -30,    5,   15,   20,   20,   15,    5,  -30,
+             -30,    0,   10,   15,   15,   10,    0,  -30,
+             -40,  -20,    0,    0,    0,    0,  -20,  -40,
+             -50,  -40,  -30,  -30,  -30,  -30,  -40,  -50,
+        ],
+        // Bishop
+        [
+             -20,  -10,  -10,  -10,  -10,  -10,  -10,  -20,
+             -10,    0,    0,    0,    0,    0,    0,  -10,
+             -10,    0,    5,   10,   10,    5,    0,  -10,
+             -10,    0,   10,   15,   15,   10,    0,  -10,
+             -10,    0,   10,   15,   15,   10,    0,  -10,
+             -10,    0,    5,   10,   10,    5,    0,  -10,
+             -10,    0,    0,    0,    0,    0,    0,  -10,
+             -20,  -10,  -10,  -10,  -10,  -10,  -10,  -20,
+        ],
+        // Rook
+        [
+               5,    5,    5,    5,    5,    5,    5,    5,
+              10,   10,   10,   10,   10,   10,   10,   10,
+               0,    0,    0,    0,    0,    0,    0,    0,
+               0,    0,    0,    0,    0,    0,    0,    0,
+               0,    0,    0,    0,    0,    0,    0,    0,
+               0,    0,    0,    0,    0,    0,    0,    0,
+               0,    0,    0,    0,    0,    0,    0,    0,
+               0,    0,    0,    0,    0,    0,    0,    0,
+        ],
+        // Queen
+        [
+             -20,  -10,  -10,   -5,   -5,  -10,  -10,  -20,
+             -10,    0,    5,    5,    5,    5,    0,  -10,
+             -10,    5,   10,   10,   10,   10,    5,  -10,
+              -5,    5,   10,   15,   15,   10,    5,   -5,
+              -5,    5,   10,   15,   15,   10,    5,   -5,
+             -10,    5,   10,   10,   10,   10,    5,  -10,
+             -10,    0,    5,    5,    5,    5,    0,  -10,
+             -20,  -10,  -10,   -5,   -5,  -10,  -10,  -20,
+        ],
+        // King
+        [
+             -50,  -40,  -30,  -20,  -20,  -30,  -40,  -50,
+             -30,  -20,  -10,    0,    0,  -10,  -20,  -30,
+             -30,  -10,   20,   30,   30,   20,  -10,  -30,
+             -30,  -10,   30,   40,   40,   30,  -10,  -30,
+             -30,  -10,   30,   40,   40,   30,  -10,  -30,
+             -30,  -10,   20,   30,   30,   20,  -10,  -30,
+             -30,  -30,    0,    0,    0,    0,  -30,  -30,
+             -50,  -30,  -30,  -30,  -30,  -30,  -30,  -50,
+        ],
+    ],
+    mobility: 5,
+    tension: 2,
+    tempo: 10,
+    doubled: Score::new(-10, -20),
+    isolated: Score::new(-10, -15),
+    backward: Score::new(-8, -10),
+    connected: Score::new(8, 6),
+    phalanx: Score::new(5, 4),
+    passed: [
+        Score::new(0, 0),
+        Score::new(5, 10),
+        Score::new(10, 15),
+        Score::new(15, 25),
+        Score::new(30, 45),
+        Score::new(50, 75),
+        Score::new(80, 120),
+        Score::new(0, 0),
+    ],
+    passed_enemy_king: 5,
+    passed_own_king: 2,
+    attack_units: [2, 2, 3, 5],
+    shield_near: 12,
+    shield_far: 6,
+    storm: -8,
+    semi_open_file: -15,
+    open_file: -25,
+    safety: [
+        0, 0, 1, 3, 6, 9, 13, 18, 24, 30,
+        37, 45, 54, 63, 73, 84, 96, 108, 121, 135,
+        150, 165, 181, 198, 216, 234, 253, 273, 294, 315,
+        337, 360, 384, 408, 433, 459, 486, 500, 500, 500,
+        500, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+        500, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+        500, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+        500, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+        500, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+        500, 500, 500, 500, 500, 500, 500, 500, 500, 500,
+    ],
+};
@@ -0,0 +1,488 @@
+use std::fs;
+
+use crate::board::Board;
+use crate::movegen::Move;
+use crate::san;
+
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+const LINE_WIDTH: usize = 80;
+
+// A move and what follows it. The first child continues the line it is on;
+// later children are variations replacing that first child.
+#[derive(Clone, Default)]
+pub struct Node {
+    pub mv: Option<Move>,
+    pub san: String,
+    pub nags: Vec<u8>,
+    // Comments before the move (opening a variation) and after it
+    pub comment_before: Option<String>,
+    pub comment: Option<String>,
+    pub children: Vec<Node>,
+}
+
+#[derive(Clone)]
+pub struct Game {
+    pub tags: Vec<(String, String)>,
+    pub start: Board,
+    // Plies played before `start`, from the FEN's move number
+    pub start_ply: usize,
+    // Holds the moves; its comment is the one before the first move
+    pub root: Node,
+    pub result: String,
+    // A bad FEN tag (no moves are kept) or the first move that couldn't be
+    // played (the rest of its line is dropped)
+    pub error: Option<String>,
+}
+
+#[derive(Clone, PartialEq, Debug)]
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Nag(u8),
+    Open,
+    Close,
+    Result(String),
+    Move(String),
+}
+
+impl Game {
+    fn new() -> Self {
+        Self {
+            tags: Vec::new(),
+            start: Board::from_fen(START_FEN),
+            start_ply: 0,
+            root: Node::default(),
+            result: "*".to_string(),
+            error: None,
+        }
+    }
+
+    // A main line of `moves` from `fen`, with FEN and SetUp tags unless it is
+    // the standard start
+    pub fn from_moves(fen: &str, moves: &[Move], result: &str) -> Self {
+        let mut game = Self::new();
+        if fen.split_whitespace().take(4).ne(START_FEN.split_whitespace().take(4)) {
+            if let Err(e) = game.set_start(fen) {
+                game.error = Some(format!("FEN tag: {}", e));
+            }
+            game.tags.push(("SetUp".to_string(), "1".to_string()));
+            game.tags.push(("FEN".to_string(), fen.to_string()));
+        }
+
+        let mut board = game.start;
+        let mut node = &mut game.root;
+        // Moves from a start that couldn't be read would be written wrong
+        let moves = if game.error.is_some() { &[] } else { moves };
+        for mv in moves {
+            node.children.push(Node { mv: Some(*mv), san: san::to_san(&board, *mv), ..Node::default() });
+            node = &mut node.children[0];
+            board.play(*mv);
+        }
+        game.result = result.to_string();
+        game
+    }
+
+    fn set_start(&mut self, fen: &str) -> Result<(), String> {
+        Board::check_fen(fen)?;
+        self.start = Board::from_fen(fen);
+        let fullmove: usize = fen.split_whitespace().nth(5).and_then(|n| n.parse().ok()).unwrap_or(1);
+        self.start_ply = 2 * fullmove.saturating_sub(1) + !self.start.white_to_move(0) as usize;
+        Ok(())
+    }
+
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    // Moves of the main line, from `start`
+    pub fn mainline(&self) -> Vec<Move> {
+        let mut moves = Vec::new();
+        let mut node = &self.root;
+        while let Some(child) = node.children.first() {
+            moves.extend(child.mv);
+            node = child;
+        }
+        moves
+    }
+
+    // 1.0, 0.5 or 0.0 from White's side, None if unfinished
+    pub fn result_value(&self) -> Option<f64> {
+        match self.result.as_str() {
+            "1-0" => Some(1.0),
+            "0-1" => Some(0.0),
+            "1/2-1/2" => Some(0.5),
+            _ => None,
+        }
+    }
+}
+
+fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = text.chars().collect();
+    let mut i = 0;
+    let mut line_start = true;
+
+    while i < chars.len() {
+        let c = chars[i];
+        // Escape lines start with %
+        if line_start && c == '%' {
+            while i < chars.len() && chars[i] != '\n' {
+                i += 1;
+            }
+            continue;
+        }
+        line_start = c == '\n';
+
+        match c {
+            _ if c.is_whitespace() => i += 1,
+            '[' => {
+                let end = (i..chars.len()).find(|&j| chars[j] == ']' && !in_string(&chars[i..j])).unwrap_or(chars.len());
+                let inner: String = chars[i + 1..end].iter().collect();
+                let inner = inner.trim();
+                let (name, value) = inner.split_once(char::is_whitespace).unwrap_or((inner, ""));
+                tokens.push(Token::Tag(name.to_string(), unquote(value.trim())));
+                i = end + 1;
+            }
+            '{' => {
+                let end = (i..chars.len()).find(|&j| chars[j] == '}').unwrap_or(chars.len());
+                let comment: String = chars[i + 1..end].iter().collect();
+                tokens.push(Token::Comment(comment.split_whitespace().collect::<Vec<_>>().join(" ")));
+                i = end + 1;
+            }
+            ';' => {
+                let end = (i..chars.len()).find(|&j| chars[j] == '\n').unwrap_or(chars.len());
+                let comment: String = chars[i + 1..end].iter().collect();
+                tokens.push(Token::Comment(comment.trim().to_string()));
+                i = end;
+            }
+            '(' => {
+                tokens.push(Token::Open);
+                i += 1;
+            }
+            ')' => {
+                tokens.push(Token::Close);
+                i += 1;
+            }
+            _ => {
+                let end = (i..chars.len())
+                    .find(|&j| chars[j].is_whitespace() || "[]{}();".contains(chars[j]))
+                    .unwrap_or(chars.len());
+                let word: String = chars[i..end].iter().collect();
+                word_tokens(&word, &mut tokens);
+                i = end;
+            }
+        }
+    }
+    tokens
+}
+
+// Whether a quote is still open in `s`, honouring \" escapes
+fn in_string(s: &[char]) -> bool {
+    let mut open = false;
+    let mut escaped = false;
+    for &c in s {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => open = !open,
+            _ => {}
+        }
+    }
+    open
+}
+
+fn unquote(value: &str) -> String {
+    let inner = value.strip_prefix('"').map_or(value, |v| v.strip_suffix('"').unwrap_or(v));
+    let mut out = String::new();
+    let mut escaped = false;
+    for c in inner.chars() {
+        if c == '\\' && !escaped {
+            escaped = true;
+        } else {
+            out.push(c);
+            escaped = false;
+        }
+    }
+    out
+}
+
+// Splits a word into move numbers, results, NAGs and a move with its
+// suffix annotation
+fn word_tokens(word: &str, tokens: &mut Vec<Token>) {
+    if matches!(word, "1-0" | "0-1" | "1/2-1/2" | "*") {
+        tokens.push(Token::Result(word.to_string()));
+        return;
+    }
+    if let Some(n) = word.strip_prefix('$') {
+        tokens.extend(n.parse().ok().map(Token::Nag));
+        return;
+    }
+    // "12." "12..." and "12.e4" all carry a move number
+    let word = match word.rfind('.') {
+        Some(i) if word[..i].trim_end_matches('.').chars().all(|c| c.is_ascii_digit()) => &word[i + 1..],
+        _ => word,
+    };
+    if word.is_empty() || word.chars().all(|c| c.is_ascii_digit()) {
+        return;
+    }
+
+    let mv = word.trim_end_matches(['!', '?']);
+    let nag = match &word[mv.len()..] {
+        "!" => Some(1),
+        "?" => Some(2),
+        "!!" => Some(3),
+        "??" => Some(4),
+        "!?" => Some(5),
+        "?!" => Some(6),
+        _ => None,
+    };
+    if !mv.is_empty() {
+        tokens.push(Token::Move(mv.to_string()));
+    }
+    tokens.extend(nag.map(Token::Nag));
+}
+
+// Where the parser is in the tree: the path of child indices to the current
+// node, the board there, and the same for the node before it so that a
+// variation can branch off
+#[derive(Clone)]
+struct Frame {
+    path: Vec<usize>,
+    board: Board,
+    previous: Option<(Vec<usize>, Board)>,
+    comment_before: Option<String>,
+    // Skipping to the end of this line after a bad move
+    dead: bool,
+}
+
+fn node_at<'a>(root: &'a mut Node, path: &[usize]) -> &'a mut Node {
+    path.iter().fold(root, |node, &i| &mut node.children[i])
+}
+
+// Every game in `text`. Unplayable moves end their line with `error` set
+// rather than losing the game.
+pub fn parse(text: &str) -> Vec<Game> {
+    let mut games = Vec::new();
+    let mut game = Game::new();
+    let mut frames: Vec<Frame> = Vec::new();
+    let mut in_movetext = false;
+
+    let start_frame = |game: &Game| Frame { path: Vec::new(), board: game.start, previous: None, comment_before: None, dead: game.error.is_some() };
+
+    for token in tokenize(text) {
+        if let Token::Tag(name, value) = &token {
+            if in_movetext {
+                finish(&mut game, &mut games);
+                frames.clear();
+                in_movetext = false;
+            }
+            if name == "FEN"
+                && let Err(e) = game.set_start(value)
+            {
+                game.error = Some(format!("FEN tag: {}", e));
+            }
+            game.tags.push((name.clone(), value.clone()));
+            continue;
+        }
+        if !in_movetext {
+            in_movetext = true;
+            frames = vec![start_frame(&game)];
+        }
+
+        match token {
+            Token::Result(result) => {
+                game.result = result;
+                finish(&mut game, &mut games);
+                frames.clear();
+                in_movetext = false;
+            }
+            Token::Open => {
+                let frame = frames.last().unwrap();
+                match (&frame.previous, frame.dead) {
+                    (Some((path, board)), false) => {
+                        let (path, board) = (path.clone(), *board);
+                        frames.push(Frame { path, board, previous: None, comment_before: None, dead: false });
+                    }
+                    // Nothing to branch from; swallow the variation
+                    _ => frames.push(Frame { dead: true, ..frame.clone() }),
+                }
+            }
+            Token::Close => {
+                if frames.len() > 1 {
+                    frames.pop();
+                }
+            }
+            _ if frames.last().unwrap().dead => {}
+            Token::Comment(comment) => {
+                let in_variation = frames.len() > 1;
+                let frame = frames.last_mut().unwrap();
+                if !in_variation && frame.path.is_empty() {
+                    append(&mut game.root.comment, comment);
+                } else if in_variation && frame.previous.is_none() {
+                    append(&mut frame.comment_before, comment);
+                } else {
+                    append(&mut node_at(&mut game.root, &frame.path).comment, comment);
+                }
+            }
+            Token::Nag(nag) => {
+                let frame = frames.last().unwrap();
+                if !frame.path.is_empty() {
+                    node_at(&mut game.root, &frame.path).nags.push(nag);
+                }
+            }
+            Token::Move(text) => {
+                let frame = frames.last_mut().unwrap();
+                match san::parse_san(&frame.board, &text) {
+                    Ok(mv) => {
+                        let parent = node_at(&mut game.root, &frame.path);
+                        parent.children.push(Node {
+                            mv: Some(mv),
+                            san: san::to_san(&frame.board, mv),
+                            comment_before: frame.comment_before.take(),
+                            ..Node::default()
+                        });
+                        let index = parent.children.len() - 1;
+                        frame.previous = Some((frame.path.clone(), frame.board));
+                        frame.path.push(index);
+                        frame.board.play(mv);
+                    }
+                    Err(e) => {
+                        let ply = game.start_ply + frame.path.len();
+                        game.error.get_or_insert(format!("move {}{} {}", ply / 2 + 1, if ply.is_multiple_of(2) { "." } else { "..." }, e));
+                        frame.dead = true;
+                    }
+                }
+            }
+            Token::Tag(..) => unreachable!(),
+        }
+    }
+
+    if in_movetext || !game.tags.is_empty() {
+        finish(&mut game, &mut games);
+    }
+    games
+}
+
+// Movetext cut off before its result falls back on the Result tag
+fn finish(game: &mut Game, games: &mut Vec<Game>) {
+    let mut game = std::mem::replace(game, Game::new());
+    if game.result == "*"
+        && let Some(result) = game.tag("Result")
+    {
+        game.result = result.to_string();
+    }
+    games.push(game);
+}
+
+fn append(slot: &mut Option<String>, comment: String) {
+    match slot {
+        Some(existing) => {
+            existing.push(' ');
+            existing.push_str(&comment);
+        }
+        None => *slot = Some(comment),
+    }
+}
+
+// Export format: tags, a blank line, then movetext wrapped under 80 columns
+pub fn write(game: &Game) -> String {
+    let mut out = String::new();
+    for (name, value) in game.tags.iter() {
+        out.push_str(&format!("[{} \"{}\"]\n", name, value.replace('\\', "\\\\").replace('"', "\\\"")));
+    }
+    out.push('\n');
+
+    let mut words = Vec::new();
+    if let Some(comment) = &game.root.comment {
+        words.push(format!("{{{}}}", comment));
+    }
+    write_line(&game.root, game.start_ply, true, &mut words);
+    words.push(game.result.clone());
+
+    let mut line = String::new();
+    for word in words {
+        if !line.is_empty() && line.len() + 1 + word.len() >= LINE_WIDTH {
+            out.push_str(&line);
+            out.push('\n');
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&word);
+    }
+    out.push_str(&line);
+    out.push('\n');
+    out
+}
+
+// The line continuing from `parent`, whose first child is played at `ply`
+fn write_line(parent: &Node, ply: usize, mut numbered: bool, words: &mut Vec<String>) {
+    let Some(main) = parent.children.first() else { return };
+    write_move(main, ply, numbered, words);
+    numbered = main.comment.is_some();
+
+    for variation in parent.children.iter().skip(1) {
+        let first = words.len();
+        write_move(variation, ply, true, words);
+        write_line(variation, ply + 1, variation.comment.is_some(), words);
+        words[first].insert(0, '(');
+        words.last_mut().unwrap().push(')');
+        numbered = true;
+    }
+    write_line(main, ply + 1, numbered, words);
+}
+
+fn write_move(node: &Node, ply: usize, numbered: bool, words: &mut Vec<String>) {
+    if let Some(comment) = &node.comment_before {
+        words.push(format!("{{{}}}", comment));
+    }
+    let number = if ply.is_multiple_of(2) {
+        format!("{}.", ply / 2 + 1)
+    } else if numbered || node.comment_before.is_some() {
+        format!("{}...", ply / 2 + 1)
+    } else {
+        String::new()
+    };
+    // Move numbers stay attached to their move
+    words.push(if number.is_empty() { node.san.clone() } else { format!("{} {}", number, node.san) });
+    for nag in node.nags.iter() {
+        words.push(format!("${}", nag));
+    }
+    if let Some(comment) = &node.comment {
+        words.push(format!("{{{}}}", comment));
+    }
+}
+
+// `vesper pgn <in.pgn> [out.pgn]`: reads every game, reports those with moves
+// that can't be played, and writes the rest back out in export format
+pub fn run(args: &[String]) {
+    let Some(path) = args.first() else {
+        eprintln!("usage: vesper pgn <in.pgn> [out.pgn]");
+        return;
+    };
+    let text = match fs::read_to_string(path) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("cannot read {}: {}", path, e);
+            return;
+        }
+    };
+
+    let games = parse(&text);
+    let mut out = String::new();
+    for (i, game) in games.iter().enumerate() {
+        if let Some(e) = &game.error {
+            println!("game {}: {}", i + 1, e);
+        }
+        out.push_str(&write(game));
+        out.push('\n');
+    }
+    println!("{} games, {} with errors", games.len(), games.iter().filter(|g| g.error.is_some()).count());
+
+    if let Some(out_path) = args.get(1)
+        && let Err(e) = fs::write(out_path, out)
+    {
+        eprintln!("cannot write {}: {}", out_path, e);
+    }
+}
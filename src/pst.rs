@@ -0,0 +1,101 @@
+use crate::board::Board;
+use crate::lane::{Lane, LANES};
+
+const PST_BITS: usize = 8;
+
+// A table split into bit planes, so that the sum over a piece set is
+// sum(popcnt(pieces & planes[k]) << k) + popcnt(pieces) * base
+#[derive(Clone, Copy)]
+struct SlicedPst {
+    base: i32,
+    planes: [u64; PST_BITS],
+}
+
+const fn slice(table: &[i32; 64], black: bool) -> SlicedPst {
+    let mut base = table[0];
+    let mut i = 1;
+    while i < 64 {
+        if table[i] < base {
+            base = table[i];
+        }
+        i += 1;
+    }
+
+    let mut planes = [0u64; PST_BITS];
+    let mut sq = 0;
+    while sq < 64 {
+        let index = if black { sq } else { sq ^ 56 };
+        let offset = table[index] - base;
+        assert!(offset < (1 << PST_BITS), "PST range too wide for bit slicing");
+        let mut k = 0;
+        while k < PST_BITS {
+            if (offset >> k) & 1 != 0 {
+                planes[k] |= 1 << sq;
+            }
+            k += 1;
+        }
+        sq += 1;
+    }
+
+    SlicedPst { base, planes }
+}
+
+const fn slice_all(tables: &[[i32; 64]; 6], black: bool) -> [SlicedPst; 6] {
+    let mut sliced = [SlicedPst { base: 0, planes: [0; PST_BITS] }; 6];
+    let mut p = 0;
+    while p < 6 {
+        sliced[p] = slice(&tables[p], black);
+        p += 1;
+    }
+    sliced
+}
+
+// Bit-sliced form of a set of middlegame and endgame tables, as laid out in `Params`
+pub struct PstTables {
+    white_mg: [SlicedPst; 6],
+    white_eg: [SlicedPst; 6],
+    black_mg: [SlicedPst; 6],
+    black_eg: [SlicedPst; 6],
+}
+
+impl PstTables {
+    pub const fn new(mg: &[[i32; 64]; 6], eg: &[[i32; 64]; 6]) -> Self {
+        Self {
+            white_mg: slice_all(mg, false),
+            white_eg: slice_all(eg, false),
+            black_mg: slice_all(mg, true),
+            black_eg: slice_all(eg, true),
+        }
+    }
+}
+
+// Horner's rule over the planes, highest first, so each step only doubles
+#[inline(always)]
+fn sum(pieces: Lane, pst: &SlicedPst) -> Lane {
+    let mut total = Lane::EMPTY;
+    for plane in pst.planes.iter().rev() {
+        total = total + total + (pieces & Lane::from_single(*plane)).popcount();
+    }
+    total + pieces.popcount().mul_const(pst.base as i64)
+}
+
+// Middlegame and endgame PST scores of one side for all lanes
+#[inline(always)]
+pub fn evaluate(board: &Board, white: bool, tables: &PstTables) -> ([i32; LANES], [i32; LANES]) {
+    let mut mg = Lane::EMPTY;
+    let mut eg = Lane::EMPTY;
+
+    let (side, tables_mg, tables_eg) = if white {
+        (board.white, &tables.white_mg, &tables.white_eg)
+    } else {
+        (board.black, &tables.black_mg, &tables.black_eg)
+    };
+
+    for (p, set) in board.piece_sets().iter().enumerate() {
+        let pieces = *set & side;
+        mg += sum(pieces, &tables_mg[p]);
+        eg += sum(pieces, &tables_eg[p]);
+    }
+
+    (mg.to_i32s(), eg.to_i32s())
+}
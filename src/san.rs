@@ -0,0 +1,132 @@
+use crate::board::{self, Board};
+use crate::movegen::{self, Move};
+
+const PIECE_LETTERS: &str = "PNBRQK";
+
+// Standard algebraic notation for a legal move of lane 0, with the least
+// disambiguation needed and a + or # suffix
+pub fn to_san(board: &Board, mv: Move) -> String {
+    let from = mv.from_square();
+    let to = mv.to_square();
+    let piece = piece_on(board, from);
+    let capture = board.occupied().extract(0) & (1 << to) != 0 || mv.kind() == Move::EN_PASSANT;
+
+    let mut san = String::new();
+    if mv.kind() == Move::CASTLING {
+        san.push_str(if to > from { "O-O" } else { "O-O-O" });
+    } else if piece == 0 {
+        if capture {
+            san.push(file_char(from));
+        }
+    } else {
+        san.push(PIECE_LETTERS.as_bytes()[piece] as char);
+        // Other pieces of the same kind that can reach the square
+        let rivals: Vec<u32> = movegen::legal_move_list(board)
+            .iter()
+            .filter(|m| m.to_square() == to && m.kind() != Move::CASTLING)
+            .map(|m| m.from_square())
+            .filter(|&sq| sq != from && piece_on(board, sq) == piece)
+            .collect();
+        if !rivals.is_empty() {
+            if rivals.iter().all(|sq| sq % 8 != from % 8) {
+                san.push(file_char(from));
+            } else if rivals.iter().all(|sq| sq / 8 != from / 8) {
+                san.push(rank_char(from));
+            } else {
+                san.push(file_char(from));
+                san.push(rank_char(from));
+            }
+        }
+    }
+    if mv.kind() != Move::CASTLING {
+        if capture {
+            san.push('x');
+        }
+        san.push(file_char(to));
+        san.push(rank_char(to));
+    }
+    if let Some(promoted) = mv.promotion_piece() {
+        san.push('=');
+        san.push(PIECE_LETTERS.as_bytes()[promoted] as char);
+    }
+
+    let mut next = *board;
+    next.play(mv);
+    if movegen::in_check(&next) {
+        san.push(if movegen::legal_move_list(&next).is_empty() { '#' } else { '+' });
+    }
+    san
+}
+
+// The legal move `san` names. Accepts 0-0 and 0-0-0 for castling, e8Q for
+// e8=Q, a missing or wrong check suffix, annotations like !? and long forms
+// like Ng1-f3 or Ng1f3. A promotion must name its piece.
+pub fn parse_san(board: &Board, san: &str) -> Result<Move, String> {
+    // Everything below slices by byte
+    if !san.is_ascii() {
+        return Err(format!("{}: not a move", san));
+    }
+    let text = san.trim().trim_end_matches(['+', '#', '!', '?']);
+    let legal = movegen::legal_move_list(board);
+    let castle = text.replace('0', "O");
+    if castle == "O-O" || castle == "O-O-O" {
+        let kingside = castle == "O-O";
+        return legal
+            .iter()
+            .find(|mv| mv.kind() == Move::CASTLING && (mv.to_square() > mv.from_square()) == kingside)
+            .copied()
+            .ok_or_else(|| format!("{}: castling is not legal here", san));
+    }
+
+    let mut text = text.to_string();
+    text.retain(|c| c != 'x' && c != '-' && c != ':' && c != '=');
+    // A trailing piece letter after the last rank names the promotion
+    let mut promoted = None;
+    if text.len() > 2 && text.ends_with(['N', 'B', 'R', 'Q']) && text[..text.len() - 1].ends_with(['1', '8']) {
+        promoted = PIECE_LETTERS.find(text.pop().unwrap());
+    }
+
+    let (piece, rest) = match text.chars().next() {
+        Some(c) if PIECE_LETTERS.contains(c) => (PIECE_LETTERS.find(c).unwrap(), &text[1..]),
+        _ => (0, text.as_str()),
+    };
+    if rest.len() < 2 {
+        return Err(format!("{}: not a move", san));
+    }
+    let to = board::parse_square(&rest[rest.len() - 2..]).ok_or_else(|| format!("{}: not a move", san))?;
+    let hint = &rest[..rest.len() - 2];
+    if !hint.chars().all(|c| matches!(c, 'a'..='h' | '1'..='8')) || hint.len() > 2 {
+        return Err(format!("{}: not a move", san));
+    }
+
+    let found: Vec<Move> = legal
+        .iter()
+        .copied()
+        .filter(|mv| {
+            let from = mv.from_square();
+            piece_on(board, from) == piece
+                && mv.to_square() == to
+                && mv.kind() != Move::CASTLING
+                && mv.promotion_piece() == promoted
+                && hint.chars().all(|c| c == file_char(from) || c == rank_char(from))
+        })
+        .collect();
+    match found.len() {
+        1 => Ok(found[0]),
+        0 => Err(format!("{}: no legal move matches", san)),
+        _ => Err(format!("{}: ambiguous", san)),
+    }
+}
+
+// Piece type on `sq` of lane 0, in `piece_sets` order
+fn piece_on(board: &Board, sq: u32) -> usize {
+    board.piece_sets().iter().position(|set| set.extract(0) & (1 << sq) != 0).unwrap_or(6)
+}
+
+fn file_char(sq: u32) -> char {
+    (b'a' + (sq % 8) as u8) as char
+}
+
+fn rank_char(sq: u32) -> char {
+    (b'1' + (sq / 8) as u8) as char
+}
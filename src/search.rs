@@ -1,75 +1,434 @@
-use crate::board::{Board, META_TURN};
-use crate::movegen::{self, MoveField};
-use crate::eval;
-use crate::lane::Lane;
-
-pub fn search(board: Board, depth: i32) -> MoveField {
-    let mut moves = movegen::generate_moves_for_lane(&board, 0);
-    if moves.is_empty() {
-        return MoveField { from: Lane::EMPTY, to: Lane::EMPTY };
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+use crate::board::Board;
+use crate::movegen::{self, Move, MoveBatches, MoveList};
+use crate::eval::{self, Weights};
+use crate::lane::LANES;
+use crate::nnue::{self, Accumulator, Network};
+use crate::syzygy::{self, Tablebases};
+use crate::uci;
+
+pub const INF: i32 = 1_000_000;
+pub const MAX_DEPTH: i32 = 64;
+const ASPIRATION_WINDOW: i32 = 25;
+// Milliseconds kept back for communication
+const MOVE_OVERHEAD: u64 = 10;
+
+struct Searcher<'a> {
+    nodes: u64,
+    start: Instant,
+    // Loaded network and its accumulators, one per ply
+    net: Option<Arc<Network>>,
+    accumulators: Vec<Accumulator>,
+    // Children of frontier nodes, one buffer per ply, and the quiet moves
+    // packing holds back until the captures are in
+    frontier: Vec<MoveBatches>,
+    quiet: Vec<(u64, u64)>,
+    // Classical weights loaded with `ParamsFile`, used without a network
+    weights: Option<Arc<Weights>>,
+    // Syzygy tables, probed at or above `tb_depth` with at most `tb_limit` pieces
+    tb: Option<Arc<Tablebases>>,
+    tb_depth: i32,
+    tb_limit: usize,
+    tb_hits: u64,
+    // Set once the node or time budget runs out; the interrupted iteration is discarded
+    node_limit: Option<u64>,
+    deadline: Option<Instant>,
+    // Node count at which the clock is next read
+    clock_check: u64,
+    stopped: bool,
+    on_info: &'a mut dyn FnMut(&Info),
+}
+
+/// When to stop searching. The first iteration always completes, whatever
+/// the node or time budget.
+pub struct Limits {
+    pub depth: i32,
+    pub nodes: Option<u64>,
+    /// Milliseconds
+    pub movetime: Option<u64>,
+    /// Suppresses the info lines [`search_with`] prints, for self-play and
+    /// other offline tools
+    pub silent: bool,
+}
+
+pub struct SearchResult {
+    pub best_move: Move,
+    /// From the side to move, as of the last completed iteration
+    pub score: i32,
+    pub nodes: u64,
+    /// Best move and elapsed milliseconds after each completed iteration
+    pub history: Vec<(Move, u64)>,
+}
+
+/// Progress of a search: one per completed iteration, and one for each
+/// aspiration window it failed out of
+pub struct Info<'a> {
+    pub depth: i32,
+    /// From the side to move
+    pub score: i32,
+    /// "", "lowerbound" or "upperbound", as UCI has it
+    pub bound: &'static str,
+    pub nodes: u64,
+    pub tb_hits: u64,
+    /// Milliseconds since the search started
+    pub time: u64,
+    pub pv: &'a [Move],
+}
+
+/// The evaluation and tablebases a search uses. [`Settings::current`] has the
+/// ones the UCI options loaded; `match` keeps one per internal engine.
+#[derive(Clone)]
+pub struct Settings {
+    pub net: Option<Arc<Network>>,
+    /// Classical weights, used without a network
+    pub weights: Option<Arc<Weights>>,
+    pub tb: Option<Arc<Tablebases>>,
+    pub tb_depth: i32,
+    pub tb_limit: usize,
+}
+
+impl Settings {
+    pub fn current() -> Self {
+        Self {
+            net: nnue::network(),
+            weights: eval::weights(),
+            tb: syzygy::tablebases(),
+            tb_depth: syzygy::PROBE_DEPTH.load(Ordering::Relaxed),
+            tb_limit: syzygy::PROBE_LIMIT.load(Ordering::Relaxed),
+        }
     }
 
-    moves.sort_by_key(|m| {
-        let to_sq = m.to.extract(0);
-        let captured = (board.occupied().extract(0) & to_sq) != 0;
-        if captured { -1 } else { 0 }
-    });
+    /// Sets one of the options above by its UCI name; an empty value or
+    /// `<empty>` unloads a file
+    pub fn set(&mut self, name: &str, value: &str) -> Result<(), String> {
+        let file = (!value.is_empty() && value != "<empty>").then_some(value);
+        let number = |max: i64| value.parse::<i64>().map(|n| n.clamp(0, max)).map_err(|_| format!("invalid {} {}", name, value));
+        let cannot_load = |e: String| format!("cannot load {}: {}", value, e);
+        match name.to_ascii_lowercase().as_str() {
+            "evalfile" => self.net = file.map(Network::load).transpose().map_err(cannot_load)?.map(Arc::new),
+            "paramsfile" => self.weights = file.map(Weights::load).transpose().map_err(cannot_load)?.map(Arc::new),
+            "syzygypath" => self.tb = file.map(Tablebases::open).transpose().map_err(cannot_load)?.map(Arc::new),
+            "syzygyprobedepth" => self.tb_depth = number(100)?.max(1) as i32,
+            "syzygyprobelimit" => self.tb_limit = number(7)? as usize,
+            _ => return Err(format!("unknown option {}", name)),
+        }
+        Ok(())
+    }
 
-    let packed = movegen::pack_move_fields(&moves);
-    let mut best_move = moves[0];
-    let mut best_score = -2000000;
+    /// Makes these the settings [`Settings::current`] returns
+    pub fn publish(&self) {
+        nnue::set_network(self.net.clone());
+        eval::set_weights(self.weights.clone());
+        syzygy::set_tablebases(self.tb.clone());
+        syzygy::PROBE_DEPTH.store(self.tb_depth, Ordering::Relaxed);
+        syzygy::PROBE_LIMIT.store(self.tb_limit, Ordering::Relaxed);
+    }
+}
 
-    let turn_white = (board.metadata.extract(0) & (1 << META_TURN)) == 0;
+/// Milliseconds to spend on a move with `remaining` on the clock: an even share
+/// of the moves to go (30 if unknown) plus most of the increment, never more
+/// than half the clock
+pub fn time_for_move(remaining: u64, increment: u64, moves_to_go: Option<u64>) -> u64 {
+    let share = remaining / moves_to_go.unwrap_or(30).max(1) + increment * 3 / 4;
+    share.min(remaining / 2).saturating_sub(MOVE_OVERHEAD).max(1)
+}
 
-    for pm in packed {
-        let mut next_board = board;
-        next_board.apply_move(&pm);
+/// Searches lane 0, printing UCI info lines unless `limits.silent`
+pub fn search_with(board: Board, limits: &Limits) -> SearchResult {
+    search_with_info(board, limits, &mut |info| {
+        if !limits.silent {
+            uci::print_info(info);
+        }
+    })
+}
 
-        let scores = vpts_recurse(&next_board, depth - 1);
+/// Searches lane 0, passing each iteration's progress to `on_info`
+pub fn search_with_info(board: Board, limits: &Limits, on_info: &mut dyn FnMut(&Info)) -> SearchResult {
+    search_with_settings(board, limits, &Settings::current(), on_info)
+}
 
-        for i in 0..4 {
-            let score = if turn_white { scores[i] } else { -scores[i] };
-            if score > best_score {
-                best_score = score;
-                best_move = MoveField {
-                    from: Lane::from_single(pm.from.extract(i)),
-                    to: Lane::from_single(pm.to.extract(i)),
-                };
+/// [`search_with_info`] with its own network, weights and tablebases
+pub fn search_with_settings(board: Board, limits: &Limits, settings: &Settings, on_info: &mut dyn FnMut(&Info)) -> SearchResult {
+    if movegen::generate_move_list(&board, 0).is_empty() {
+        return SearchResult { best_move: Move::NONE, score: 0, nodes: 0, history: Vec::new() };
+    }
+
+    let net = settings.net.clone();
+    let mut accumulators = Vec::new();
+    if let Some(net) = &net {
+        let mut root = net.new_accumulator();
+        net.refresh(&mut root, &board, 0);
+        accumulators.push(root);
+    }
+    // With few enough pieces the DTZ table picks the move outright
+    let tb = settings.tb.clone();
+    let tb_limit = tb.as_ref().map_or(0, |tb| tb.max_pieces().min(settings.tb_limit));
+    let root_probe = tb.as_ref().filter(|_| board.occupied().extract(0).count_ones() as usize <= tb_limit)
+        .and_then(|tb| tb.probe_root(&board));
+
+    let mut searcher = Searcher {
+        nodes: 0,
+        start: Instant::now(),
+        net,
+        accumulators,
+        frontier: Vec::new(),
+        quiet: Vec::with_capacity(movegen::MAX_MOVES),
+        weights: settings.weights.clone(),
+        tb,
+        tb_depth: settings.tb_depth,
+        tb_limit,
+        tb_hits: 0,
+        node_limit: None,
+        deadline: None,
+        clock_check: 0,
+        stopped: false,
+        on_info,
+    };
+    if let Some((best_move, dtz)) = root_probe {
+        // Cursed wins and blessed losses are drawn under the 50-move rule
+        let score = match dtz {
+            1..=100 => syzygy::TB_WIN - dtz,
+            -100..=-1 => -syzygy::TB_WIN - dtz,
+            _ => 0,
+        };
+        searcher.tb_hits += 1;
+        searcher.report(1, score, "", &[best_move]);
+        return SearchResult { best_move, score, nodes: searcher.nodes, history: vec![(best_move, 0)] };
+    }
+
+    let mut best_move = Move::NONE;
+    let mut score = 0;
+    let mut history = Vec::new();
+    let deadline = limits.movetime.map(|ms| searcher.start + Duration::from_millis(ms));
+
+    for d in 1..=limits.depth.clamp(1, MAX_DEPTH) {
+        // The first iteration always completes so there is a move to play
+        searcher.node_limit = if d > 1 { limits.nodes } else { None };
+        searcher.deadline = if d > 1 { deadline } else { None };
+        // Aspiration window around the previous iteration's score
+        let mut delta = ASPIRATION_WINDOW;
+        let (mut alpha, mut beta) = if d > 1 {
+            ((score - delta).max(-INF), (score + delta).min(INF))
+        } else {
+            (-INF, INF)
+        };
+
+        loop {
+            let mut pv = MoveList::new();
+            let s = searcher.pvs(&board, d, 0, alpha, beta, &mut pv);
+
+            if searcher.stopped {
+                break;
+            } else if s <= alpha {
+                searcher.report(d, s, "upperbound", &pv);
+                alpha = (alpha - delta).max(-INF);
+                delta *= 2;
+            } else if s >= beta {
+                searcher.report(d, s, "lowerbound", &pv);
+                beta = (beta + delta).min(INF);
+                delta *= 2;
+            } else {
+                score = s;
+                best_move = pv[0];
+                searcher.report(d, s, "", &pv);
+                break;
             }
         }
+        if searcher.stopped {
+            break;
+        }
+        history.push((best_move, searcher.start.elapsed().as_millis() as u64));
     }
 
-    best_move
+    SearchResult { best_move, score, nodes: searcher.nodes, history }
 }
 
-fn vpts_recurse(board: &Board, depth: i32) -> [i32; 4] {
-    if depth <= 0 {
-        return eval::evaluate(board);
+impl Searcher<'_> {
+    fn report(&mut self, depth: i32, score: i32, bound: &'static str, pv: &[Move]) {
+        let time = self.start.elapsed().as_millis() as u64;
+        (self.on_info)(&Info { depth, score, bound, nodes: self.nodes, tb_hits: self.tb_hits, time, pv });
     }
 
-    let mut froms = [0u64; 4];
-    let mut tos = [0u64; 4];
-    let mut any_move = false;
+    // Negamax: both evaluations already score from the side to move
+    fn evaluate(&self, board: &Board, ply: usize) -> i32 {
+        match &self.net {
+            Some(net) => net.evaluate(&self.accumulators[ply], board, 0),
+            None => self.classical(board)[0],
+        }
+    }
+
+    fn classical(&self, board: &Board) -> [i32; LANES] {
+        self.weights.as_deref().unwrap_or(&eval::DEFAULT_WEIGHTS).evaluate(board)
+    }
 
-    for i in 0..4 {
-        let moves = movegen::generate_moves_for_lane(board, i);
-        if let Some(m) = moves.first() {
-            froms[i] = m.from.extract(0);
-            tos[i] = m.to.extract(0);
-            any_move = true;
+    // Copy-make for the accumulator stack: the child's slot is rebuilt from
+    // the parent's, so unmaking is just returning to the lower ply
+    fn push_accumulator(&mut self, parent: &Board, child: &Board, ply: usize) {
+        let Some(net) = &self.net else { return };
+        if self.accumulators.len() <= ply + 1 {
+            self.accumulators.push(net.new_accumulator());
         }
+        let (below, above) = self.accumulators.split_at_mut(ply + 1);
+        net.update(&mut above[0], &below[ply], parent, child, 0);
     }
 
-    if !any_move {
-        return eval::evaluate(board);
+    fn pvs(&mut self, board: &Board, depth: i32, ply: usize, mut alpha: i32, beta: i32, pv: &mut MoveList) -> i32 {
+        pv.clear();
+        if self.node_limit.is_some_and(|limit| self.nodes >= limit) {
+            self.stopped = true;
+        }
+        // The clock is only read every 1024 nodes; leaf batches count many at once
+        if let Some(deadline) = self.deadline
+            && self.nodes >= self.clock_check
+        {
+            self.clock_check = self.nodes + 1024;
+            if Instant::now() >= deadline {
+                self.stopped = true;
+            }
+        }
+        if self.stopped {
+            return 0;
+        }
+        self.nodes += 1;
+
+        if depth <= 0 {
+            return self.evaluate(board, ply);
+        }
+
+        if ply > 0 && depth >= self.tb_depth && board.occupied().extract(0).count_ones() as usize <= self.tb_limit {
+            let wdl = self.tb.as_ref().and_then(|tb| tb.probe_wdl(board));
+            if let Some(wdl) = wdl {
+                self.tb_hits += 1;
+                return match wdl {
+                    syzygy::WDL_WIN => syzygy::TB_WIN - ply as i32,
+                    syzygy::WDL_LOSS => -syzygy::TB_WIN + ply as i32,
+                    _ => 0,
+                };
+            }
+        }
+
+        // The frontier's children come straight from the packed generator
+        if depth == 1 {
+            while self.frontier.len() <= ply {
+                self.frontier.push(MoveBatches::new());
+            }
+            movegen::generate_packed_into(board, &mut self.frontier[ply], &mut self.quiet);
+            if self.frontier[ply].is_empty() {
+                return self.evaluate(board, ply);
+            }
+            return self.vpts_leaves(board, ply, alpha, beta, pv);
+        }
+
+        let mut moves = movegen::generate_move_list(board, 0);
+        if moves.is_empty() {
+            return self.evaluate(board, ply);
+        }
+        // Deeper down, a move into check loses the king; at the root a shallow
+        // search might not see that, so only legal moves are played when there are any
+        if ply == 0 {
+            let legal = movegen::legal_move_list(board);
+            if !legal.is_empty() {
+                moves = legal;
+            }
+        }
+        order_moves(board, &mut moves);
+
+        let mut best_score = -INF;
+        let mut child_pv = MoveList::new();
+
+        for (i, mv) in moves.iter().enumerate() {
+            let mut next_board = *board;
+            next_board.play(*mv);
+            self.push_accumulator(board, &next_board, ply);
+
+            let score = if i == 0 {
+                -self.pvs(&next_board, depth - 1, ply + 1, -beta, -alpha, &mut child_pv)
+            } else {
+                // Null window for non-PV moves, re-searched on fail-high
+                let s = -self.pvs(&next_board, depth - 1, ply + 1, -alpha - 1, -alpha, &mut child_pv);
+                if s > alpha && s < beta {
+                    -self.pvs(&next_board, depth - 1, ply + 1, -beta, -alpha, &mut child_pv)
+                } else {
+                    s
+                }
+            };
+            if self.stopped {
+                return 0;
+            }
+
+            if score > best_score {
+                best_score = score;
+            }
+            if score > alpha {
+                alpha = score;
+                pv.clear();
+                pv.push(*mv);
+                pv.extend_from_slice(&child_pv);
+            }
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        best_score
     }
 
-    let pm = MoveField {
-        from: Lane::new(froms[0], froms[1], froms[2], froms[3]),
-        to: Lane::new(tos[0], tos[1], tos[2], tos[3]),
-    };
+    // Frontier nodes: children, packed into `frontier[ply]`, are evaluated
+    // LANES at a time, one per lane
+    fn vpts_leaves(&mut self, board: &Board, ply: usize, mut alpha: i32, beta: i32, pv: &mut MoveList) -> i32 {
+        let mut best_score = -INF;
+        let mut child = self.net.as_ref().map(|net| net.new_accumulator());
+        // As in pvs, the root plays only legal moves when there are any
+        let white = board.white_to_move(0);
+        let legal_only = ply == 0 && !movegen::legal_move_list(board).is_empty();
+
+        for b in 0..self.frontier[ply].len() {
+            let pm = self.frontier[ply].get(b);
+            let mut next_board = *board;
+            next_board.apply_move(&pm);
+            let count = self.frontier[ply].moves_in(b);
+
+            // The parent is broadcast, so lane i of `board` is the parent of lane i
+            let scores = match (&self.net, &mut child) {
+                (Some(net), Some(acc)) => std::array::from_fn(|i| {
+                    if i >= count {
+                        return 0;
+                    }
+                    net.update(acc, &self.accumulators[ply], board, &next_board, i);
+                    net.evaluate(acc, &next_board, i)
+                }),
+                _ => self.classical(&next_board),
+            };
+            self.nodes += count as u64;
+            let illegal = if legal_only { movegen::attacked_kings(&next_board, white).to_array() } else { [0; LANES] };
 
-    let mut next_board = *board;
-    next_board.apply_move(&pm);
-    vpts_recurse(&next_board, depth - 1)
+            for (i, s) in scores.iter().enumerate().take(count).filter(|&(i, _)| illegal[i] == 0) {
+                let score = -s;
+                if score > best_score {
+                    best_score = score;
+                }
+                if score > alpha {
+                    alpha = score;
+                    pv.clear();
+                    pv.push(Move::on_board(board, &pm, i));
+                }
+            }
+            if alpha >= beta {
+                break;
+            }
+        }
+
+        best_score
+    }
+}
+
+fn order_moves(board: &Board, moves: &mut [Move]) {
+    let occupied = board.occupied().extract(0);
+    moves.sort_by_key(|m| {
+        let captured = occupied & (1 << m.to_square()) != 0;
+        if captured { -1 } else { 0 }
+    });
 }
@@ -0,0 +1,1082 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicI32, AtomicUsize};
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
+use std::time::SystemTime;
+
+use crate::board::{self, Board};
+use crate::eval::material_key;
+use crate::movegen::{self, Move};
+
+// Syzygy WDL/DTZ probing, following the layout of the reference probing code.
+// Each table file is read whole into memory on its first probe, so only sets
+// whose probed tables fit in RAM (up to 5 pieces, about 1 GB in all) are
+// practical; std has no memory mapping to page in 6- and 7-piece tables.
+// Positions with castling rights are not in the tables and aren't probed. The
+// tables assume no en passant capture, so one is searched like any other
+// capture before the table is trusted.
+
+const TB_PIECES: usize = 7;
+const WDL_MAGIC: [u8; 4] = [0x71, 0xe8, 0x23, 0x5d];
+const DTZ_MAGIC: [u8; 4] = [0xd7, 0x66, 0x0c, 0xa5];
+
+// Per-table flags
+const FLAG_STM: u8 = 1;
+const FLAG_MAPPED: u8 = 2;
+const FLAG_WIN_PLIES: u8 = 4;
+const FLAG_LOSS_PLIES: u8 = 8;
+const FLAG_WIDE: u8 = 16;
+const FLAG_SINGLE_VALUE: u8 = 128;
+
+pub const WDL_LOSS: i32 = -2;
+pub const WDL_BLESSED_LOSS: i32 = -1;
+pub const WDL_DRAW: i32 = 0;
+pub const WDL_CURSED_WIN: i32 = 1;
+pub const WDL_WIN: i32 = 2;
+
+// Search score of a tablebase win, above anything material can reach but
+// below a king capture
+pub const TB_WIN: i32 = 8000;
+
+// `SyzygyProbeDepth` and `SyzygyProbeLimit`
+pub static PROBE_DEPTH: AtomicI32 = AtomicI32::new(1);
+pub static PROBE_LIMIT: AtomicUsize = AtomicUsize::new(TB_PIECES);
+
+static TABLEBASES: RwLock<Option<Arc<Tablebases>>> = RwLock::new(None);
+
+// The tables `SyzygyPath` points at, if any
+pub fn tablebases() -> Option<Arc<Tablebases>> {
+    TABLEBASES.read().unwrap().clone()
+}
+
+pub fn set_tablebases(tb: Option<Arc<Tablebases>>) {
+    *TABLEBASES.write().unwrap() = tb;
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum State {
+    Ok,
+    Fail,
+    // DTZ tables store one side to move; the other needs a 1-ply search
+    ChangeStm,
+    // The best move zeroes the 50-move counter, so DTZ can't be trusted
+    ZeroingBestMove,
+}
+
+// Encoding tables shared by every table file
+struct Maps {
+    b1h1h7: [u64; 64],
+    a1d1d4: [u64; 64],
+    kk: [[u64; 64]; 10],
+    binomial: [[u64; 64]; 6],
+    pawns: [usize; 64],
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+static MAPS: OnceLock<Box<Maps>> = OnceLock::new();
+
+// Rank minus file: negative below the a1-h8 diagonal, positive above it
+fn off_diagonal(sq: usize) -> i32 {
+    (sq / 8) as i32 - (sq % 8) as i32
+}
+
+fn maps() -> &'static Maps {
+    MAPS.get_or_init(|| {
+        let mut m = Box::new(Maps {
+            b1h1h7: [0; 64],
+            a1d1d4: [0; 64],
+            kk: [[0; 64]; 10],
+            binomial: [[0; 64]; 6],
+            pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; 6],
+            lead_pawns_size: [[0; 4]; 6],
+        });
+
+        let mut code = 0;
+        for sq in 0..64 {
+            if off_diagonal(sq) < 0 {
+                m.b1h1h7[sq] = code;
+                code += 1;
+            }
+        }
+
+        // The a1-d1-d4 triangle, with the diagonal squares numbered last
+        let mut diagonal = Vec::new();
+        code = 0;
+        for sq in 0..28 {
+            if off_diagonal(sq) < 0 && sq % 8 <= 3 {
+                m.a1d1d4[sq] = code;
+                code += 1;
+            } else if off_diagonal(sq) == 0 && sq % 8 <= 3 {
+                diagonal.push(sq);
+            }
+        }
+        for sq in diagonal {
+            m.a1d1d4[sq] = code;
+            code += 1;
+        }
+
+        // The 462 legal placements of two kings with the first in the triangle
+        let king_attacks = |sq: usize| crate::lane::Lane::from_single(1 << sq).king_attacks().extract(0) | 1 << sq;
+        let mut both_on_diagonal = Vec::new();
+        code = 0;
+        for idx in 0..10 {
+            for s1 in 0..28 {
+                if m.a1d1d4[s1] != idx as u64 || (idx == 0 && s1 != 1) {
+                    continue;
+                }
+                for s2 in 0..64 {
+                    if king_attacks(s1) & (1 << s2) != 0 || (off_diagonal(s1) == 0 && off_diagonal(s2) > 0) {
+                        continue;
+                    }
+                    if off_diagonal(s1) == 0 && off_diagonal(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        m.kk[idx][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            m.kk[idx][s2] = code;
+            code += 1;
+        }
+
+        m.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..6.min(n + 1) {
+                m.binomial[k][n] = if k > 0 { m.binomial[k - 1][n - 1] } else { 0 }
+                    + if k < n { m.binomial[k][n - 1] } else { 0 };
+            }
+        }
+
+        // Pawn squares a2-h7 numbered so the leading pawn, nearest the edge
+        // and then lowest, has the highest value
+        let mut available = 48;
+        for lead_count in 1..=5 {
+            for file in 0..4 {
+                let mut idx = 0;
+                for rank in 1..7 {
+                    let sq = rank * 8 + file;
+                    if lead_count == 1 {
+                        m.pawns[sq] = available - 1;
+                        m.pawns[sq ^ 7] = available - 2;
+                        available -= 2;
+                    }
+                    m.lead_pawn_idx[lead_count][sq] = idx;
+                    idx += m.binomial[lead_count - 1][m.pawns[sq]];
+                }
+                m.lead_pawns_size[lead_count][file] = idx;
+            }
+        }
+
+        m
+    })
+}
+
+fn read_u16(data: &[u8], at: usize) -> u16 {
+    data.get(at..at + 2).map_or(0, |b| u16::from_le_bytes([b[0], b[1]]))
+}
+
+fn read_u32(data: &[u8], at: usize) -> u32 {
+    data.get(at..at + 4).map_or(0, |b| u32::from_le_bytes(b.try_into().unwrap()))
+}
+
+// Huffman data is big-endian; reads past the end are zero padding
+fn read_u32_be(data: &[u8], at: usize) -> u32 {
+    let mut bytes = [0u8; 4];
+    for (i, b) in bytes.iter_mut().enumerate() {
+        *b = data.get(at + i).copied().unwrap_or(0);
+    }
+    u32::from_be_bytes(bytes)
+}
+
+// One compressed sub-table: a side to move and, with pawns, a leading file.
+// Positions are offsets into the table file.
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    block_size: usize,
+    span: u64,
+    num_blocks: usize,
+    min_sym_len: u8,
+    lowest_sym: usize,
+    btree: usize,
+    block_length: usize,
+    block_length_size: usize,
+    sparse_index: usize,
+    sparse_index_size: usize,
+    data: usize,
+    base64: Vec<u64>,
+    symlen: Vec<u8>,
+    pieces: [u8; TB_PIECES],
+    group_idx: [u64; TB_PIECES + 1],
+    group_len: [usize; TB_PIECES + 1],
+    map_idx: [usize; 4],
+}
+
+impl PairsData {
+    // Each btree node holds two 12-bit symbols; a leaf stores its value on the left
+    fn left(&self, data: &[u8], sym: usize) -> usize {
+        let at = self.btree + 3 * sym;
+        ((data[at + 1] as usize & 0xf) << 8) | data[at] as usize
+    }
+
+    fn right(&self, data: &[u8], sym: usize) -> usize {
+        let at = self.btree + 3 * sym;
+        ((data[at + 2] as usize) << 4) | (data[at + 1] as usize >> 4)
+    }
+
+    // Pairs must name symbols of the table, so `decompress` can follow them unchecked
+    fn set_symlen(&mut self, data: &[u8], sym: usize, visited: &mut [bool]) -> Result<u8, String> {
+        visited[sym] = true;
+        let right = self.right(data, sym);
+        if right == 0xfff {
+            return Ok(0);
+        }
+        let left = self.left(data, sym);
+        if left >= visited.len() || right >= visited.len() {
+            return Err("bad symbol tree".to_string());
+        }
+        if !visited[left] {
+            self.symlen[left] = self.set_symlen(data, left, visited)?;
+        }
+        if !visited[right] {
+            self.symlen[right] = self.set_symlen(data, right, visited)?;
+        }
+        self.symlen[left].checked_add(self.symlen[right]).and_then(|n| n.checked_add(1)).ok_or_else(|| "bad symbol tree".to_string())
+    }
+
+    // Reads the block sizes and Huffman tables, returning the next position
+    fn set_sizes(&mut self, data: &[u8], mut pos: usize) -> Result<usize, String> {
+        self.flags = *data.get(pos).ok_or("truncated table")?;
+        pos += 1;
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            // The single value is kept in `min_sym_len`
+            self.min_sym_len = *data.get(pos).ok_or("truncated table")?;
+            return Ok(pos + 1);
+        }
+        let header = data.get(pos..pos + 9).ok_or("truncated table")?;
+        // Shift counts for block_size and span, and for the Huffman bases
+        if header[0] > 32 || header[1] > 63 || header[7] > 64 {
+            return Err("bad block or symbol sizes".to_string());
+        }
+
+        let groups = self.group_len.iter().position(|&l| l == 0).unwrap();
+        let tb_size = self.group_idx[groups];
+
+        self.block_size = 1 << data[pos];
+        self.span = 1 << data[pos + 1];
+        self.sparse_index_size = tb_size.div_ceil(self.span) as usize;
+        let padding = data[pos + 2] as usize;
+        self.num_blocks = read_u32(data, pos + 3) as usize;
+        self.block_length_size = self.num_blocks + padding;
+        let max_sym_len = data[pos + 7];
+        self.min_sym_len = data[pos + 8];
+        pos += 9;
+        if max_sym_len < self.min_sym_len || self.min_sym_len == 0 {
+            return Err("bad symbol lengths".to_string());
+        }
+        self.lowest_sym = pos;
+
+        // Canonical Huffman: base64[i] is the smallest left-aligned code of
+        // length min_sym_len + i
+        let lengths = (max_sym_len - self.min_sym_len) as usize + 1;
+        let lowest = |i: usize| read_u16(data, self.lowest_sym + 2 * i) as u64;
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            self.base64[i] = (self.base64[i + 1].wrapping_add(lowest(i)).wrapping_sub(lowest(i + 1))) / 2;
+        }
+        for (i, base) in self.base64.iter_mut().enumerate() {
+            *base = base.checked_shl(64 - i as u32 - self.min_sym_len as u32).unwrap_or(0);
+        }
+        pos += lengths * 2;
+
+        let symbols = read_u16(data, pos) as usize;
+        pos += 2;
+        self.btree = pos;
+        if self.btree + 3 * symbols > data.len() {
+            return Err("truncated table".to_string());
+        }
+
+        // Recursive pairing: each symbol expands to symlen + 1 values
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for sym in 0..symbols {
+            if !visited[sym] {
+                self.symlen[sym] = self.set_symlen(data, sym, &mut visited)?;
+            }
+        }
+
+        Ok(pos + symbols * 3 + (symbols & 1))
+    }
+
+    // `parse` checked the regions this reads; None is a block or symbol
+    // pointer that leads outside them, from a corrupt file
+    fn decompress(&self, data: &[u8], idx: u64) -> Option<i32> {
+        if self.flags & FLAG_SINGLE_VALUE != 0 {
+            return Some(self.min_sym_len as i32);
+        }
+
+        // The sparse index points at the block holding k * span + span / 2
+        let k = (idx / self.span) as usize;
+        let entry = self.sparse_index + 6 * k;
+        let mut block = read_u32(data, entry) as usize;
+        let mut offset = read_u16(data, entry + 4) as i64 + (idx % self.span) as i64 - (self.span / 2) as i64;
+
+        let block_length = |b: usize| read_u16(data, self.block_length + 2 * b) as i64;
+        while offset < 0 {
+            block = block.checked_sub(1)?;
+            offset += block_length(block) + 1;
+        }
+        while offset > block_length(block) {
+            offset -= block_length(block) + 1;
+            block += 1;
+        }
+        if block >= self.num_blocks {
+            return None;
+        }
+
+        let mut ptr = self.data + block * self.block_size;
+        let mut buf = ((read_u32_be(data, ptr) as u64) << 32) | read_u32_be(data, ptr + 4) as u64;
+        ptr += 8;
+        let mut buf_size: usize = 64;
+        let min = self.min_sym_len as usize;
+
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while buf < *self.base64.get(len)? {
+                len += 1;
+            }
+            sym = ((buf - self.base64[len]) >> (64 - len - min)) as usize;
+            sym += read_u16(data, self.lowest_sym + 2 * len) as usize;
+            if sym >= self.symlen.len() {
+                return None;
+            }
+
+            if offset < self.symlen[sym] as i64 + 1 {
+                break;
+            }
+            offset -= self.symlen[sym] as i64 + 1;
+            len += min;
+            buf = buf.checked_shl(len as u32).unwrap_or(0);
+            buf_size = buf_size.checked_sub(len)?;
+            if buf_size <= 32 {
+                buf_size += 32;
+                buf |= (read_u32_be(data, ptr) as u64) << (64 - buf_size);
+                ptr += 4;
+            }
+        }
+
+        // Walk down the pair tree to the value at our offset
+        while self.symlen[sym] != 0 {
+            let left = self.left(data, sym);
+            if offset < self.symlen[left] as i64 + 1 {
+                sym = left;
+            } else {
+                offset -= self.symlen[left] as i64 + 1;
+                sym = self.right(data, sym);
+            }
+        }
+        Some(self.left(data, sym) as i32)
+    }
+}
+
+// Lane 0 of a board as the probing code sees it
+struct TbPosition {
+    pieces: [[u64; 6]; 2],
+    occupied: u64,
+    stm: usize,
+    key: u64,
+    piece_count: usize,
+}
+
+impl TbPosition {
+    fn new(board: &Board) -> Self {
+        let pieces = board.pieces(0);
+        let counts = pieces.map(|side| [0, 1, 2, 3, 4].map(|p| side[p].count_ones()));
+        let occupied = board.occupied().extract(0);
+        Self {
+            pieces,
+            occupied,
+            stm: !board.white_to_move(0) as usize,
+            key: material_key(&counts),
+            piece_count: occupied.count_ones() as usize,
+        }
+    }
+
+    // Table piece code: type 1-6 from pawn to king, plus 8 for black
+    fn piece_on(&self, sq: usize) -> u8 {
+        for (color, side) in self.pieces.iter().enumerate() {
+            if let Some(p) = side.iter().position(|bb| bb & (1 << sq) != 0) {
+                return (p as u8 + 1) | (color as u8 * 8);
+            }
+        }
+        0
+    }
+}
+
+struct Loaded {
+    data: Vec<u8>,
+    items: [[PairsData; 4]; 2],
+    map: usize,
+}
+
+// One table file, opened on first probe
+struct Table {
+    dtz: bool,
+    key: u64,
+    key2: u64,
+    piece_count: usize,
+    has_pawns: bool,
+    has_unique_pieces: bool,
+    // Leading color's pawns first
+    pawn_count: [usize; 2],
+    path: PathBuf,
+    loaded: OnceLock<Loaded>,
+    // Size and modification time of the file when it last failed to load:
+    // it is read again once it changes, not on every probe
+    failed: Mutex<Option<(u64, Option<SystemTime>)>>,
+}
+
+impl Table {
+    // `name` is the material of the stronger side first, like "KRPvKR"
+    fn new(name: &str, path: PathBuf, dtz: bool) -> Option<Self> {
+        let (a, b) = name.split_once('v')?;
+        let mut counts = [[0u32; 5]; 2];
+        for (color, side) in [a, b].iter().enumerate() {
+            if !side.starts_with('K') || side[1..].contains('K') {
+                return None;
+            }
+            for c in side[1..].chars() {
+                counts[color]["PNBRQ".find(c)?] += 1;
+            }
+        }
+
+        let pawns = [counts[0][0] as usize, counts[1][0] as usize];
+        let leading_white = pawns[1] == 0 || (pawns[0] > 0 && pawns[1] >= pawns[0]);
+        Some(Self {
+            dtz,
+            key: material_key(&counts),
+            key2: material_key(&[counts[1], counts[0]]),
+            piece_count: 2 + counts.iter().flatten().sum::<u32>() as usize,
+            has_pawns: pawns[0] + pawns[1] > 0,
+            has_unique_pieces: counts.iter().any(|side| side.contains(&1)),
+            pawn_count: if leading_white { pawns } else { [pawns[1], pawns[0]] },
+            path,
+            loaded: OnceLock::new(),
+            failed: Mutex::new(None),
+        })
+    }
+
+    fn sides(&self) -> usize {
+        if !self.dtz && self.key != self.key2 { 2 } else { 1 }
+    }
+
+    fn loaded(&self) -> Option<&Loaded> {
+        if let Some(loaded) = self.loaded.get() {
+            return Some(loaded);
+        }
+        let stamp = fs::metadata(&self.path).ok().map(|m| (m.len(), m.modified().ok()));
+        let mut failed = self.failed.lock().unwrap();
+        if stamp.is_none() || *failed == stamp {
+            return None;
+        }
+        match fs::read(&self.path).map_err(|e| e.to_string()).and_then(|data| self.parse(data)) {
+            Ok(loaded) => Some(self.loaded.get_or_init(|| loaded)),
+            Err(_) => {
+                *failed = stamp;
+                None
+            }
+        }
+    }
+
+    fn get<'a>(&self, loaded: &'a Loaded, stm: usize, file: usize) -> &'a PairsData {
+        &loaded.items[stm % self.sides()][if self.has_pawns { file } else { 0 }]
+    }
+
+    fn parse(&self, data: Vec<u8>) -> Result<Loaded, String> {
+        let magic = if self.dtz { DTZ_MAGIC } else { WDL_MAGIC };
+        if data.len() < 6 || data[..4] != magic {
+            return Err("bad magic".to_string());
+        }
+        let flags = data[4];
+        if (flags & 2 != 0) != self.has_pawns || (flags & 1 != 0) != (self.key != self.key2) {
+            return Err("table does not match its name".to_string());
+        }
+
+        let sides = self.sides();
+        let max_file = if self.has_pawns { 3 } else { 0 };
+        let both_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut items: [[PairsData; 4]; 2] = Default::default();
+        let mut pos = 5;
+
+        // Piece codes the name gives, sorted: type 1-6 from pawn to king, plus 8 for black
+        let mut expected = vec![6, 14];
+        for color in 0..2 {
+            for p in 0..5 {
+                let count = (self.key >> (color * 20 + p * 4)) & 15;
+                expected.extend((0..count).map(|_| (p + 1) as u8 | (color as u8 * 8)));
+            }
+        }
+        expected.sort();
+
+        for file in 0..=max_file {
+            let byte = |at: usize| data.get(at).copied().ok_or("truncated table");
+            let first = byte(pos)?;
+            let second = if both_pawns { byte(pos + 1)? } else { 0xff };
+            let order = [[first & 0xf, second & 0xf], [first >> 4, second >> 4]];
+            pos += 1 + both_pawns as usize;
+
+            for k in 0..self.piece_count {
+                let b = byte(pos)?;
+                for (i, side) in items.iter_mut().enumerate().take(sides) {
+                    side[file].pieces[k] = if i == 1 { b >> 4 } else { b & 0xf };
+                }
+                pos += 1;
+            }
+            for (i, side) in items.iter_mut().enumerate().take(sides) {
+                // `probe` looks the position's pieces up in this order
+                let mut found = side[file].pieces[..self.piece_count].to_vec();
+                found.sort();
+                if found != expected {
+                    return Err("pieces do not match the table's name".to_string());
+                }
+                self.set_groups(&mut side[file], order[i], file)?;
+            }
+        }
+        pos += pos & 1;
+
+        for file in 0..=max_file {
+            for side in items.iter_mut().take(sides) {
+                pos = side[file].set_sizes(&data, pos)?;
+            }
+        }
+
+        let map = pos;
+        if self.dtz {
+            for item in items[0].iter_mut().take(max_file + 1) {
+                if item.flags & FLAG_MAPPED == 0 {
+                    continue;
+                }
+                // Four value maps, for loss/blessed loss/win/cursed win
+                if item.flags & FLAG_WIDE != 0 {
+                    pos += pos & 1;
+                    for i in 0..4 {
+                        item.map_idx[i] = (pos - map) / 2 + 1;
+                        pos += 2 * read_u16(&data, pos) as usize + 2;
+                    }
+                } else {
+                    for i in 0..4 {
+                        item.map_idx[i] = pos - map + 1;
+                        pos += *data.get(pos).ok_or("truncated table")? as usize + 1;
+                    }
+                }
+            }
+            pos += pos & 1;
+        }
+
+        for file in 0..=max_file {
+            for side in items.iter_mut().take(sides) {
+                side[file].sparse_index = pos;
+                pos += side[file].sparse_index_size * 6;
+            }
+        }
+        for file in 0..=max_file {
+            for side in items.iter_mut().take(sides) {
+                side[file].block_length = pos;
+                pos += side[file].block_length_size * 2;
+            }
+        }
+        for file in 0..=max_file {
+            for side in items.iter_mut().take(sides) {
+                pos = (pos + 0x3f) & !0x3f;
+                side[file].data = pos;
+                pos += side[file].num_blocks * side[file].block_size;
+            }
+        }
+        if pos > data.len() {
+            return Err("truncated table".to_string());
+        }
+
+        Ok(Loaded { data, items, map })
+    }
+
+    // Splits the pieces into groups of equal pieces and works out each
+    // group's multiplier in the index, in the per-table order
+    fn set_groups(&self, d: &mut PairsData, order: [u8; 2], file: usize) -> Result<(), String> {
+        let m = maps();
+        let mut n = 0;
+        let mut first_len: i32 = if self.has_pawns { 0 } else if self.has_unique_pieces { 3 } else { 2 };
+        d.group_len[0] = 1;
+        for i in 1..self.piece_count {
+            first_len -= 1;
+            if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+                d.group_len[n] += 1;
+            } else {
+                n += 1;
+                d.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        d.group_len[n] = 0;
+        // The index tables go up to five of a kind
+        if d.group_len.iter().any(|&len| len > 5) {
+            return Err("bad piece groups".to_string());
+        }
+
+        let both_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = if both_pawns { 2 } else { 1 };
+        let mut free_squares = 64 - d.group_len[0] - if both_pawns { d.group_len[1] } else { 0 };
+        let mut idx = 1u64;
+        let mut k = 0u8;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                d.group_idx[0] = idx;
+                idx *= if self.has_pawns {
+                    m.lead_pawns_size[d.group_len[0]][file]
+                } else if self.has_unique_pieces {
+                    31332
+                } else {
+                    462
+                };
+            } else if k == order[1] {
+                d.group_idx[1] = idx;
+                idx *= m.binomial[d.group_len[1]][48 - d.group_len[0]];
+            } else {
+                if next >= n {
+                    return Err("bad group order".to_string());
+                }
+                d.group_idx[next] = idx;
+                idx *= m.binomial[d.group_len[next]][free_squares];
+                free_squares -= d.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        d.group_idx[n] = idx;
+        Ok(())
+    }
+
+    fn probe(&self, loaded: &Loaded, pos: &TbPosition, wdl: i32, state: &mut State) -> i32 {
+        let m = maps();
+        let mut squares = [0usize; TB_PIECES];
+        let mut pieces = [0u8; TB_PIECES];
+        let mut size = 0;
+        let mut lead_count = 0;
+        let mut lead_pawns = 0u64;
+        let mut tb_file = 0;
+
+        // Tables are stored with the stronger side as White, and symmetric
+        // ones only with White to move, so flip colors when needed
+        let symmetric_black_to_move = self.key == self.key2 && pos.stm == 1;
+        let flip = symmetric_black_to_move || pos.key != self.key;
+        let flip_color = if flip { 8 } else { 0 };
+        let flip_squares = if flip { 56 } else { 0 };
+        let stm = flip as usize ^ pos.stm;
+
+        // Pawn tables are split by the file of the leading pawn
+        if self.has_pawns {
+            let pc = self.get(loaded, 0, 0).pieces[0] ^ flip_color;
+            lead_pawns = pos.pieces[(pc >> 3) as usize][0];
+            let mut b = lead_pawns;
+            while b != 0 {
+                squares[size] = b.trailing_zeros() as usize ^ flip_squares;
+                size += 1;
+                b &= b - 1;
+            }
+            lead_count = size;
+            let lead = (0..lead_count).max_by_key(|&i| m.pawns[squares[i]]).unwrap();
+            squares.swap(0, lead);
+            tb_file = (squares[0] % 8).min(7 - squares[0] % 8);
+        }
+
+        let d = self.get(loaded, stm, tb_file);
+        if self.dtz && (d.flags & FLAG_STM) as usize != stm && (self.key != self.key2 || self.has_pawns) {
+            *state = State::ChangeStm;
+            return 0;
+        }
+
+        let mut b = pos.occupied ^ lead_pawns;
+        while b != 0 {
+            let sq = b.trailing_zeros() as usize;
+            squares[size] = sq ^ flip_squares;
+            pieces[size] = pos.piece_on(sq) ^ flip_color;
+            size += 1;
+            b &= b - 1;
+        }
+
+        // Put the pieces in the table's order
+        for i in lead_count..size - 1 {
+            if let Some(j) = (i + 1..size).find(|&j| pieces[j] == d.pieces[i]) {
+                pieces.swap(i, j);
+                squares.swap(i, j);
+            }
+        }
+
+        if squares[0] % 8 > 3 {
+            squares[..size].iter_mut().for_each(|s| *s ^= 7);
+        }
+
+        let mut idx;
+        if self.has_pawns {
+            idx = m.lead_pawn_idx[lead_count][squares[0]];
+            squares[1..lead_count].sort_by_key(|&s| m.pawns[s]);
+            for (i, sq) in squares.iter().enumerate().take(lead_count).skip(1) {
+                idx += m.binomial[i][m.pawns[*sq]];
+            }
+        } else {
+            if squares[0] / 8 > 3 {
+                squares[..size].iter_mut().for_each(|s| *s ^= 56);
+            }
+            // The first leading piece off the diagonal goes below it
+            for i in 0..d.group_len[0] {
+                let off = off_diagonal(squares[i]);
+                if off == 0 {
+                    continue;
+                }
+                if off > 0 {
+                    squares[i..size].iter_mut().for_each(|s| *s = ((*s >> 3) | (*s << 3)) & 63);
+                }
+                break;
+            }
+
+            if self.has_unique_pieces {
+                let [s0, s1, s2] = [squares[0], squares[1], squares[2]].map(|s| s as u64);
+                let adjust1 = (s1 > s0) as u64;
+                let adjust2 = (s2 > s0) as u64 + (s2 > s1) as u64;
+                let rank = |s: u64| s / 8;
+                idx = if off_diagonal(squares[0]) != 0 {
+                    (m.a1d1d4[squares[0]] * 63 + (s1 - adjust1)) * 62 + s2 - adjust2
+                } else if off_diagonal(squares[1]) != 0 {
+                    (6 * 63 + rank(s0) * 28 + m.b1h1h7[squares[1]]) * 62 + s2 - adjust2
+                } else if off_diagonal(squares[2]) != 0 {
+                    6 * 63 * 62 + 4 * 28 * 62 + rank(s0) * 7 * 28 + (rank(s1) - adjust1) * 28 + m.b1h1h7[squares[2]]
+                } else {
+                    6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank(s0) * 7 * 6 + (rank(s1) - adjust1) * 6 + (rank(s2) - adjust2)
+                };
+            } else {
+                idx = m.kk[m.a1d1d4[squares[0]] as usize][squares[1]];
+            }
+        }
+
+        // The remaining groups, each as a combination of the free squares
+        idx *= d.group_idx[0];
+        let mut start = d.group_len[0];
+        let mut remaining_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        let mut next = 1;
+        while d.group_len[next] != 0 {
+            let len = d.group_len[next];
+            squares[start..start + len].sort();
+            let mut n = 0;
+            for i in 0..len {
+                let sq = squares[start + i];
+                let adjust = squares[..start].iter().filter(|&&s| sq > s).count();
+                // Other pawns count from rank 2; probeable keeps them off the last ranks
+                let Some(index) = (sq - adjust).checked_sub(if remaining_pawns { 8 } else { 0 }) else {
+                    *state = State::Fail;
+                    return 0;
+                };
+                n += m.binomial[i + 1][index];
+            }
+            remaining_pawns = false;
+            idx += n * d.group_idx[next];
+            start += len;
+            next += 1;
+        }
+
+        let Some(value) = d.decompress(&loaded.data, idx) else {
+            *state = State::Fail;
+            return 0;
+        };
+        if !self.dtz {
+            return value - 2;
+        }
+        self.map_dtz(loaded, tb_file, value, wdl).unwrap_or_else(|| {
+            *state = State::Fail;
+            0
+        })
+    }
+
+    // DTZ values may be remapped and stored in moves rather than plies
+    fn map_dtz(&self, loaded: &Loaded, file: usize, mut value: i32, wdl: i32) -> Option<i32> {
+        const WDL_MAP: [usize; 5] = [1, 3, 0, 2, 0];
+        let d = self.get(loaded, 0, file);
+        if d.flags & FLAG_MAPPED != 0 {
+            let i = d.map_idx[WDL_MAP[(wdl + 2) as usize]] + value as usize;
+            value = if d.flags & FLAG_WIDE != 0 {
+                read_u16(&loaded.data, loaded.map + 2 * i) as i32
+            } else {
+                *loaded.data.get(loaded.map + i)? as i32
+            };
+        }
+        if (wdl == WDL_WIN && d.flags & FLAG_WIN_PLIES == 0)
+            || (wdl == WDL_LOSS && d.flags & FLAG_LOSS_PLIES == 0)
+            || wdl == WDL_CURSED_WIN
+            || wdl == WDL_BLESSED_LOSS
+        {
+            value *= 2;
+        }
+        Some(value + 1)
+    }
+}
+
+pub struct Tablebases {
+    wdl: HashMap<u64, Arc<Table>>,
+    dtz: HashMap<u64, Arc<Table>>,
+    max_pieces: usize,
+    count: usize,
+}
+
+fn is_capture(board: &Board, mv: Move) -> bool {
+    board.occupied().extract(0) & 1 << mv.to_square() != 0 || mv.kind() == Move::EN_PASSANT
+}
+
+fn is_pawn_move(board: &Board, mv: Move) -> bool {
+    board.pawns.extract(0) & 1 << mv.from_square() != 0
+}
+
+fn dtz_before_zeroing(wdl: i32) -> i32 {
+    match wdl {
+        WDL_WIN => 1,
+        WDL_CURSED_WIN => 101,
+        WDL_BLESSED_LOSS => -101,
+        WDL_LOSS => -1,
+        _ => 0,
+    }
+}
+
+impl Tablebases {
+    // Scans `paths`, separated like PATH, for .rtbw files and their .rtbz partners
+    pub fn open(paths: &str) -> Result<Self, String> {
+        let mut tb = Self { wdl: HashMap::new(), dtz: HashMap::new(), max_pieces: 0, count: 0 };
+        let dirs: Vec<PathBuf> = env::split_paths(paths).collect();
+
+        for dir in dirs.iter() {
+            let entries = fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+            for entry in entries.flatten() {
+                let path = entry.path();
+                let (Some(name), Some("rtbw")) = (path.file_stem().and_then(|s| s.to_str()), path.extension().and_then(|s| s.to_str())) else {
+                    continue;
+                };
+                let Some(table) = Table::new(name, path.clone(), false) else {
+                    continue;
+                };
+                if table.piece_count > TB_PIECES {
+                    continue;
+                }
+                tb.max_pieces = tb.max_pieces.max(table.piece_count);
+                tb.count += 1;
+
+                let dtz_path = dirs.iter().map(|d| d.join(format!("{}.rtbz", name))).find(|p| p.exists());
+                if let Some(dtz) = dtz_path.and_then(|p| Table::new(name, p, true)) {
+                    let dtz = Arc::new(dtz);
+                    tb.dtz.insert(dtz.key, dtz.clone());
+                    tb.dtz.insert(dtz.key2, dtz);
+                }
+                let table = Arc::new(table);
+                tb.wdl.insert(table.key, table.clone());
+                tb.wdl.insert(table.key2, table);
+            }
+        }
+
+        if tb.wdl.is_empty() {
+            return Err(format!("no Syzygy tables in {}", paths));
+        }
+        Ok(tb)
+    }
+
+    pub fn max_pieces(&self) -> usize {
+        self.max_pieces
+    }
+
+    // Distinct WDL tables found
+    pub fn table_count(&self) -> usize {
+        self.count
+    }
+
+    fn probe_table(&self, board: &Board, dtz: bool, wdl: i32, state: &mut State) -> i32 {
+        let pos = TbPosition::new(board);
+        if pos.piece_count == 2 {
+            return WDL_DRAW;
+        }
+        let table = if dtz { self.dtz.get(&pos.key) } else { self.wdl.get(&pos.key) };
+        match table.and_then(|t| t.loaded().map(|l| (t, l))) {
+            Some((table, loaded)) => table.probe(loaded, &pos, wdl, state),
+            None => {
+                *state = State::Fail;
+                0
+            }
+        }
+    }
+
+    // Resolves captures (and with `zeroing`, pawn moves) before trusting the
+    // table, since tables don't account for them being the best move
+    fn search(&self, board: &Board, state: &mut State, zeroing: bool) -> i32 {
+        let moves = movegen::legal_move_list(board);
+        let mut best = WDL_LOSS;
+        let mut searched = 0;
+
+        for mv in moves.iter() {
+            if !(is_capture(board, *mv) || zeroing && is_pawn_move(board, *mv)) {
+                continue;
+            }
+            searched += 1;
+            let mut next = *board;
+            next.play(*mv);
+            let value = -self.search(&next, state, false);
+            if *state == State::Fail {
+                return WDL_DRAW;
+            }
+            if value > best {
+                best = value;
+                if value >= WDL_WIN {
+                    *state = State::ZeroingBestMove;
+                    return value;
+                }
+            }
+        }
+
+        let no_more_moves = searched > 0 && searched == moves.len();
+        let value = if no_more_moves {
+            best
+        } else {
+            let v = self.probe_table(board, false, WDL_DRAW, state);
+            if *state == State::Fail {
+                return WDL_DRAW;
+            }
+            v
+        };
+
+        if best >= value {
+            *state = if best > WDL_DRAW || no_more_moves { State::ZeroingBestMove } else { State::Ok };
+            return best;
+        }
+        *state = State::Ok;
+        value
+    }
+
+    // Tables only hold positions with both kings where the side that just
+    // moved isn't in check, which pseudo-legal search can reach, with no
+    // castling rights and no pawns on the first or last rank, which a made-up
+    // FEN can have. `search` takes a position whose every legal move is a
+    // capture it searched as settled, so it relies on legal_move_list having
+    // them all, en passant and underpromotions included.
+    fn probeable(&self, board: &Board) -> bool {
+        let kings = board.kings.extract(0);
+        (kings & board.white.extract(0)).count_ones() == 1
+            && (kings & board.black.extract(0)).count_ones() == 1
+            && board.occupied().extract(0).count_ones() as usize <= self.max_pieces
+            && board.pawns.extract(0) & board::LAST_RANKS == 0
+            && board.castling_rights(0) == 0
+            && !movegen::king_attacked(board, !board.white_to_move(0))
+    }
+
+    // Win/draw/loss from the side to move, -2 to 2
+    pub fn probe_wdl(&self, board: &Board) -> Option<i32> {
+        if !self.probeable(board) {
+            return None;
+        }
+        let mut state = State::Ok;
+        let wdl = self.search(board, &mut state, false);
+        (state != State::Fail).then_some(wdl)
+    }
+
+    // Plies to the next capture or pawn move in a won line, signed by the
+    // result, and 0 for draws
+    pub fn probe_dtz(&self, board: &Board) -> Option<i32> {
+        if !self.probeable(board) {
+            return None;
+        }
+        let mut state = State::Ok;
+        let dtz = self.dtz(board, &mut state);
+        (state != State::Fail).then_some(dtz)
+    }
+
+    fn dtz(&self, board: &Board, state: &mut State) -> i32 {
+        *state = State::Ok;
+        let wdl = self.search(board, state, true);
+        if *state == State::Fail || wdl == WDL_DRAW {
+            return 0;
+        }
+        if *state == State::ZeroingBestMove {
+            return dtz_before_zeroing(wdl);
+        }
+
+        let dtz = self.probe_table(board, true, wdl, state);
+        if *state == State::Fail {
+            return 0;
+        }
+        if *state != State::ChangeStm {
+            let cursed = wdl == WDL_BLESSED_LOSS || wdl == WDL_CURSED_WIN;
+            return (dtz + if cursed { 100 } else { 0 }) * wdl.signum();
+        }
+
+        // The table has the other side to move: take the best DTZ a move away
+        let mut min_dtz = i32::MAX;
+        for &mv in movegen::legal_move_list(board).iter() {
+            let zeroing = is_capture(board, mv) || is_pawn_move(board, mv);
+            let mut next = *board;
+            next.play(mv);
+
+            let mut dtz = if zeroing {
+                -dtz_before_zeroing(self.search(&next, state, false))
+            } else {
+                -self.dtz(&next, state)
+            };
+            if dtz == 1 && movegen::in_check(&next) && movegen::legal_move_list(&next).is_empty() {
+                min_dtz = 1;
+            }
+            if !zeroing {
+                dtz += dtz.signum();
+            }
+            if dtz < min_dtz && dtz.signum() == wdl.signum() {
+                min_dtz = dtz;
+            }
+            if *state == State::Fail {
+                return 0;
+            }
+        }
+        if min_dtz == i32::MAX { -1 } else { min_dtz }
+    }
+
+    // The root move that keeps the best result: the quickest conversion when
+    // winning, the longest resistance when losing. Returns it with its DTZ.
+    pub fn probe_root(&self, board: &Board) -> Option<(Move, i32)> {
+        if !self.probeable(board) {
+            return None;
+        }
+        let mut best: Option<(Move, i32, i32)> = None;
+
+        for &mv in movegen::legal_move_list(board).iter() {
+            let mut next = *board;
+            next.play(mv);
+
+            let mut dtz = if is_capture(board, mv) || is_pawn_move(board, mv) {
+                dtz_before_zeroing(-self.probe_wdl(&next)?)
+            } else {
+                let d = -self.probe_dtz(&next)?;
+                d + d.signum()
+            };
+            if dtz == 2 && movegen::in_check(&next) && movegen::legal_move_list(&next).is_empty() {
+                dtz = 1;
+            }
+
+            let rank = match dtz.signum() {
+                1 => 10_000 - dtz,
+                -1 => -10_000 - dtz,
+                _ => 0,
+            };
+            if best.is_none_or(|(_, _, r)| rank > r) {
+                best = Some((mv, dtz, rank));
+            }
+        }
+
+        best.map(|(mv, dtz, _)| (mv, dtz))
+    }
+}
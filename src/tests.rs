@@ -1,8 +1,26 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
+    use crate::arena;
+    use crate::bench;
+    use crate::cpu;
     use crate::board::Board;
-    use crate::movegen;
+    use crate::book::{self, Book};
+    use crate::datagen;
+    use crate::eval;
+    use crate::lane::{Backend, LaneOf, LANES};
+    use crate::lane_scalar::Scalar;
+    use crate::movegen::{self, Move, MoveField};
+    use crate::nnue::{self, Network};
+    use crate::pst;
+    use crate::params::{self, Params};
+    use crate::tune;
+    use crate::pgn;
+    use crate::san;
     use crate::search;
+    use crate::syzygy::{self, Tablebases};
+    use crate::testsuite;
+    use crate::uci;
 
     #[test]
     fn test_starting_position_moves() {
@@ -11,11 +29,1004 @@ mod tests {
         assert!(!moves.is_empty());
     }
 
+    #[test]
+    fn test_move_batches() {
+        let squares = |mv: &MoveField, lane: usize| (mv.from.extract(lane), mv.to.extract(lane));
+        // A reused buffer, left full by a position with many moves
+        let mut reused = movegen::generate_packed(&Board::from_fen("QQQQQQQQ/Q6Q/Q6Q/Q6Q/Q6Q/Q6Q/QQQQQQQQ/k6K w - - 0 1"));
+        let mut scratch = Vec::new();
+        for fen in SYMMETRY_FENS {
+            let board = Board::from_fen(fen);
+            let moves = movegen::generate_moves_for_lane(&board, 0);
+
+            // Packed: the same moves, captures first, every batch but the last full
+            let packed = movegen::generate_packed(&board);
+            let flat: Vec<_> = packed.iter().enumerate()
+                .flat_map(|(b, pm)| (0..packed.moves_in(b)).map(move |i| squares(&pm, i)))
+                .collect();
+            let occupied = board.occupied().extract(0);
+            let (mut captures, mut quiet): (Vec<_>, Vec<_>) = moves.iter().map(|mv| squares(mv, 0)).partition(|&(_, to)| to & occupied != 0);
+            assert_eq!(flat.len(), moves.len(), "{}", fen);
+            let mut packed_captures = flat[..captures.len()].to_vec();
+            let mut packed_quiet = flat[captures.len()..].to_vec();
+            captures.sort();
+            quiet.sort();
+            packed_captures.sort();
+            packed_quiet.sort();
+            assert_eq!((packed_captures, packed_quiet), (captures, quiet), "{}", fen);
+            assert!((0..packed.len().saturating_sub(1)).all(|b| packed.moves_in(b) == LANES), "{}", fen);
+            movegen::generate_packed_into(&board, &mut reused, &mut scratch);
+            let fields = |batches: &movegen::MoveBatches| -> Vec<_> { batches.iter().map(|mv| (mv.from.to_array(), mv.to.to_array())).collect() };
+            assert_eq!(fields(&reused), fields(&packed), "{}", fen);
+
+            // A different child in each lane, each generating its own moves
+            let mut children = board;
+            children.apply_move(&packed.get(0));
+            let batches = movegen::generate_batches(&children);
+            for lane in 0..LANES {
+                let own: Vec<_> = batches.iter().map(|mv| squares(&mv, lane)).filter(|&(_, to)| to != 0).collect();
+                let expected: Vec<_> = movegen::generate_moves_for_lane(&children, lane).iter().map(|mv| squares(mv, 0)).collect();
+                assert_eq!(own, expected, "{} lane {}", fen, lane);
+            }
+        }
+    }
+
+    #[test]
+    fn test_compact_moves() {
+        let mv = Move::new(12, 28);
+        assert_eq!((mv.from_square(), mv.to_square(), mv.kind()), (12, 28, Move::NORMAL));
+        assert_eq!(mv.to_string(), "e2e4");
+        assert_eq!(Move::from_field(&mv.to_field(), 0), mv);
+        assert_eq!(Move::from_field(&Move::NONE.to_field(), 0), Move::NONE);
+        assert_eq!(Move::NONE.to_string(), "0000");
+
+        let promotion = Move::promotion(52, 61, 4);
+        assert_eq!((promotion.kind(), promotion.promotion_piece()), (Move::PROMOTION, Some(4)));
+        assert_eq!(promotion.to_string(), "e7f8q");
+        assert_eq!(Move::promotion(52, 60, 1).to_string(), "e7e8n");
+        assert_eq!(Move::with_kind(4, 6, Move::CASTLING).kind(), Move::CASTLING);
+        assert_eq!(std::mem::size_of::<Move>(), 2);
+
+        // The list and the MoveField API agree, in the same order
+        for fen in SYMMETRY_FENS {
+            let board = Board::from_fen(fen);
+            let list: Vec<Move> = movegen::generate_move_list(&board, 0).iter().map(|mv| Move::from_field(&mv.to_field(), 0)).collect();
+            let fields: Vec<Move> = movegen::generate_moves_for_lane(&board, 0).iter().map(|mv| Move::from_field(mv, 0)).collect();
+            assert_eq!(list, fields, "{}", fen);
+        }
+        // A push to the last rank is a queen promotion, and packed moves read back as one
+        let board = Board::from_fen("4k3/1P6/8/8/8/8/8/4K3 w - - 0 1");
+        assert!(movegen::generate_move_list(&board, 0).contains(&Move::promotion(49, 57, 4)));
+        let packed = movegen::generate_packed(&board);
+        let promoted = (0..packed.len()).flat_map(|b| (0..packed.moves_in(b)).map(move |i| (b, i))).map(|(b, i)| Move::on_board(&board, &packed.get(b), i));
+        assert_eq!(promoted.filter(|mv| mv.promotion_piece() == Some(4)).count(), 1);
+
+        // More moves than fit: the list keeps the first MAX_MOVES
+        let board = Board::from_fen("QQQQQQQQ/Q6Q/Q6Q/Q6Q/Q6Q/Q6Q/QQQQQQQQ/k6K w - - 0 1");
+        assert_eq!(movegen::generate_move_list(&board, 0).len(), movegen::MAX_MOVES);
+        let packed = movegen::generate_packed(&board);
+        assert!((0..packed.len()).map(|b| packed.moves_in(b)).sum::<usize>() >= movegen::MAX_MOVES);
+    }
+
+    // Leaf count of the legal move tree, the standard check on a generator
+    fn perft(board: &Board, depth: u32) -> u64 {
+        let moves = movegen::legal_move_list(board);
+        if depth == 1 {
+            return moves.len() as u64;
+        }
+        moves.iter().map(|&mv| {
+            let mut next = *board;
+            next.play(mv);
+            perft(&next, depth - 1)
+        }).sum()
+    }
+
+    #[test]
+    fn test_perft() {
+        // Castling through and out of check, en passant pins, every promotion
+        let cases = [
+            ("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1", 3, 8902),
+            ("r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1", 2, 2039),
+            ("8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1", 3, 2812),
+            ("r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1", 3, 9467),
+            ("rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8", 2, 1486),
+        ];
+        for (fen, depth, nodes) in cases {
+            assert_eq!(perft(&Board::from_fen(fen), depth), nodes, "{}", fen);
+        }
+
+        // Rights and the en passant square follow the moves that change them
+        let mut board = Board::from_fen("r3k2r/8/8/8/1p6/8/P7/R3K2R w KQkq - 0 1");
+        for (mv, fen) in [
+            ("a2a4", "r3k2r/8/8/8/Pp6/8/8/R3K2R b KQkq a3 0 1"),
+            ("b4a3", "r3k2r/8/8/8/8/p7/8/R3K2R w KQkq - 0 1"),
+            ("e1g1", "r3k2r/8/8/8/8/p7/8/R4RK1 b kq - 0 1"),
+            ("a8a4", "4k2r/8/8/8/r7/p7/8/R4RK1 w k - 0 1"),
+        ] {
+            let found = movegen::legal_move_list(&board).iter().copied().find(|m| m.to_string() == mv).unwrap();
+            board.play(found);
+            assert_eq!(board.fen(0), fen);
+        }
+        assert_eq!(Board::from_fen("4k3/8/8/8/8/8/8/4K3 w Kq e3 0 1").fen(0), "4k3/8/8/8/8/8/8/4K3 w Kq e3 0 1");
+        assert!(Board::check_fen("4k3/8/8/8/8/8/8/4K3 w KX - 0 1").is_err());
+        assert!(Board::check_fen("4k3/8/8/8/8/8/8/4K3 w - e4 0 1").is_err());
+    }
+
     #[test]
     fn test_search() {
         let board = Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
-        let best_move = search::search(board, 2);
-        assert!(best_move.from.extract(0) != 0);
-        assert!(best_move.to.extract(0) != 0);
+        let best_move = search_depth(board, 2);
+        assert!(!best_move.is_none());
+    }
+
+    #[test]
+    fn test_slider_material() {
+        let queen_vs_bishop = Board::from_fen("4k3/8/8/8/8/8/8/3QK1b1 w - - 0 1");
+        let rook_vs_bishop = Board::from_fen("4k3/8/8/8/8/8/8/3RK1b1 w - - 0 1");
+        let q = eval::evaluate(&queen_vs_bishop)[0];
+        let r = eval::evaluate(&rook_vs_bishop)[0];
+        assert!(q > r);
+        assert!(q > params::DEFAULT.queen - params::DEFAULT.bishop - 100);
+        assert_eq!(eval::evaluate_with(&queen_vs_bishop, &params::DEFAULT), eval::evaluate(&queen_vs_bishop));
+
+        // Other piece values reach the score, directly and through loaded weights
+        let values = Params { queen: 1500, rook: 300, ..params::DEFAULT };
+        let q = eval::evaluate_with(&queen_vs_bishop, &values)[0];
+        let r = eval::evaluate_with(&rook_vs_bishop, &values)[0];
+        assert!(q - r > 1000, "{} {}", q, r);
+        assert!(q > values.queen - values.bishop - 100);
+        let weights = eval::Weights::new(values);
+        assert_eq!(weights.evaluate(&queen_vs_bishop), eval::evaluate_with(&queen_vs_bishop, &weights.params));
+        assert_eq!(weights.trace(&rook_vs_bishop).score(), r);
+    }
+
+    #[test]
+    fn test_pst_bit_slicing() {
+        let board = Board::from_fen("r1bqk2r/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP3PPP/R1BQKB1R w KQkq - 0 1");
+        let tables = pst::PstTables::new(&params::DEFAULT.pst_mg, &params::DEFAULT.pst_eg);
+        let (w_mg, w_eg) = pst::evaluate(&board, true, &tables);
+        let (b_mg, b_eg) = pst::evaluate(&board, false, &tables);
+
+        let sets = [board.pawns, board.leapers, board.bishops(), board.rooks(), board.queens(), board.kings];
+        let mut expected_mg = 0;
+        let mut expected_eg = 0;
+        for (p, set) in sets.iter().enumerate() {
+            for sq in 0..64 {
+                let bit = 1u64 << sq;
+                if set.extract(0) & bit == 0 {
+                    continue;
+                }
+                if board.white.extract(0) & bit != 0 {
+                    expected_mg += params::DEFAULT.pst_mg[p][sq ^ 56];
+                    expected_eg += params::DEFAULT.pst_eg[p][sq ^ 56];
+                } else {
+                    expected_mg -= params::DEFAULT.pst_mg[p][sq];
+                    expected_eg -= params::DEFAULT.pst_eg[p][sq];
+                }
+            }
+        }
+
+        for i in 0..LANES {
+            assert_eq!(w_mg[i] - b_mg[i], expected_mg);
+            assert_eq!(w_eg[i] - b_eg[i], expected_eg);
+        }
+    }
+
+    #[test]
+    fn test_pawn_structure() {
+        let advanced = Board::from_fen("4k3/8/3P4/8/8/8/8/4K3 w - - 0 1");
+        let home = Board::from_fen("4k3/8/8/8/8/3P4/8/4K3 w - - 0 1");
+        let advanced_eg = eval::pawn_structure(&advanced, &params::DEFAULT).0[0][0].eg;
+        assert!(advanced_eg > eval::pawn_structure(&home, &params::DEFAULT).0[0][0].eg);
+
+        let doubled = Board::from_fen("4k3/8/8/8/3P4/3P4/8/4K3 w - - 0 1");
+        let connected = Board::from_fen("4k3/8/8/8/3P4/4P3/8/4K3 w - - 0 1");
+        assert!(eval::pawn_structure(&doubled, &params::DEFAULT).0[0][0].mg < eval::pawn_structure(&connected, &params::DEFAULT).0[0][0].mg);
+
+        // The pawn hash table gives what a fresh computation does, on both the
+        // probe that fills an entry and the one that hits it
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1",
+            "r1bqkb1r/pp3ppp/2n1pn2/2pp4/3P4/2P1PN2/PP1N1PPP/R1BQKB1R w - - 0 1",
+            "4k3/1p6/p1p5/P1P3p1/1P4P1/8/5K2/8 b - - 0 1",
+            "8/5k2/8/2P5/8/1p6/6K1/8 w - - 0 1",
+            "4k3/8/8/8/3P4/3P4/8/4K3 w - - 0 1",
+        ];
+        let hits = eval::pawn_table_hits();
+        for fen in fens {
+            let board = Board::from_fen(fen);
+            let fresh = eval::pawn_structure(&board, &params::DEFAULT);
+            assert_eq!(eval::DEFAULT_WEIGHTS.pawn_structure(&board), fresh, "{}", fen);
+            assert_eq!(eval::DEFAULT_WEIGHTS.pawn_structure(&board), fresh, "{}", fen);
+        }
+        // Every lane of every second probe hits
+        assert!(eval::pawn_table_hits() - hits >= (fens.len() * LANES) as u64);
+    }
+
+    #[test]
+    fn test_king_safety() {
+        let sheltered = Board::from_fen("r5k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1");
+        let exposed = Board::from_fen("r5k1/5ppp/8/8/8/8/PPP5/R5K1 w - - 0 1");
+
+        let enemy = movegen::get_attacks(&sheltered, sheltered.black, false);
+        let safe = eval::king_safety(&sheltered, &enemy, true, &params::DEFAULT)[0];
+        let enemy = movegen::get_attacks(&exposed, exposed.black, false);
+        let unsafe_king = eval::king_safety(&exposed, &enemy, true, &params::DEFAULT)[0];
+
+        assert_eq!(safe.shield, 3 * 12);
+        assert_eq!(unsafe_king.shield, 0);
+        assert!(unsafe_king.open_files < 0);
+        assert!(safe.shield + safe.open_files > unsafe_king.shield + unsafe_king.open_files);
+    }
+
+    // Best move of a silent fixed-depth search
+    fn search_depth(board: Board, depth: i32) -> Move {
+        search::search_with(board, &search::Limits { depth, nodes: None, movetime: None, silent: true }).best_move
+    }
+
+    // Swap colors and ranks, so the same position is seen from the other side
+    fn flip_colors(fen: &str) -> String {
+        let parts: Vec<&str> = fen.split_whitespace().collect();
+        let rows: Vec<String> = parts[0].split('/').rev().map(|row| {
+            row.chars().map(|c| if c.is_ascii_uppercase() { c.to_ascii_lowercase() } else { c.to_ascii_uppercase() }).collect()
+        }).collect();
+        let turn = if parts[1] == "w" { "b" } else { "w" };
+        let castling: String = parts.get(2).unwrap_or(&"-").chars().map(|c| if c.is_ascii_uppercase() { c.to_ascii_lowercase() } else { c.to_ascii_uppercase() }).collect();
+        let ep = parts.get(3).and_then(|ep| crate::board::parse_square(ep)).map_or("-".to_string(), |sq| crate::board::square_name(sq ^ 56));
+        format!("{} {} {} {} 0 1", rows.join("/"), turn, castling, ep)
+    }
+
+    fn mirror_files(fen: &str) -> String {
+        let parts: Vec<&str> = fen.split_whitespace().collect();
+        let rows: Vec<String> = parts[0].split('/').map(|row| row.chars().rev().collect()).collect();
+        format!("{} {} - - 0 1", rows.join("/"), parts[1])
+    }
+
+    const SYMMETRY_FENS: [&str; 8] = [
+        "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1",
+        "r1bqk2r/pp2bppp/2n1pn2/3p4/2PP4/2N1PN2/PP3PPP/R1BQKB1R w - - 0 1",
+        "r1bq1rk1/ppp2ppp/2np1n2/2b1p3/2B1P3/2NP1N2/PPP2PPP/R1BQ1RK1 b - - 0 1",
+        "2kr3r/ppp2ppp/2n5/3q4/3P4/5N2/PP3PPP/R2QR1K1 w - - 0 1",
+        "8/5pk1/6p1/3P4/1P6/6K1/8/8 b - - 0 1",
+        "8/8/4k3/8/2B5/8/3PK3/8 w - - 0 1",
+        "r4rk1/1b3ppp/p3p3/1p1nP3/3N4/P1R5/1P3PPP/5RK1 b - - 0 1",
+        "6k1/pp3pp1/2p4p/8/2P5/1P4P1/P4PKP/8 w - - 0 1",
+    ];
+
+    #[test]
+    fn test_eval_color_symmetry() {
+        for fen in SYMMETRY_FENS {
+            let original = eval::evaluate(&Board::from_fen(fen))[0];
+            let flipped = eval::evaluate(&Board::from_fen(&flip_colors(fen)))[0];
+            assert_eq!(original, flipped, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_eval_mirror_symmetry() {
+        // The queen's middlegame table favours the queenside, so the PSTs are
+        // made symmetric first: everything else has no sense of a or h
+        let mut params = Params::default();
+        for table in params.pst_mg.iter_mut().chain(params.pst_eg.iter_mut()) {
+            for sq in (0..64).filter(|sq| sq % 8 >= 4) {
+                table[sq] = table[sq ^ 7];
+            }
+        }
+        for fen in SYMMETRY_FENS {
+            let original = eval::evaluate_with(&Board::from_fen(fen), &params)[0];
+            let mirrored = eval::evaluate_with(&Board::from_fen(&mirror_files(fen)), &params)[0];
+            let both = eval::evaluate_with(&Board::from_fen(&flip_colors(&mirror_files(fen))), &params)[0];
+            assert_eq!(original, mirrored, "{}", fen);
+            assert_eq!(original, both, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_search_color_symmetry() {
+        for fen in &SYMMETRY_FENS[..4] {
+            let best = search_depth(Board::from_fen(fen), 2);
+            let flipped = search_depth(Board::from_fen(&flip_colors(fen)), 2);
+            assert_eq!(best.from_square() ^ 56, flipped.from_square(), "{}", fen);
+            assert_eq!(best.to_square() ^ 56, flipped.to_square(), "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_eval_trace_matches_evaluate() {
+        for fen in SYMMETRY_FENS {
+            let board = Board::from_fen(fen);
+            assert_eq!(eval::trace(&board).score(), eval::evaluate(&board)[0], "{}", fen);
+        }
+        // The printed tapered score is the scaled one the final score adds to
+        let trace = eval::trace(&Board::from_fen("4k3/5p2/4b3/8/8/2B5/4PP2/4K3 w - - 0 1"));
+        assert_eq!(trace.scale, eval::SCALE_NORMAL / 4);
+        let tapered = trace.score() - trace.tempo - trace.tension;
+        assert!(trace.to_string().contains(&format!("Tapered (white): {}\n", tapered)));
+    }
+
+    #[test]
+    fn test_params_round_trip() {
+        let values = params::DEFAULT.to_vec();
+        assert_eq!(values.len(), params::COUNT);
+        assert_eq!(Params::from_slice(&values).to_vec(), values);
+
+        let names = Params::names();
+        assert_eq!(names[params::PST_EG], "pst_eg.pawn.a8");
+        assert_eq!(names[params::SAFETY], "safety.0");
+
+        let text = params::DEFAULT.to_text();
+        assert_eq!(Params::from_text(&text).unwrap().to_vec(), values);
+
+        // The checked-in defaults are exactly what the tuner would write
+        let source = include_str!("params.rs");
+        let marker = source.rfind(params::GENERATED_MARKER).unwrap();
+        assert_eq!(&source[marker..], params::DEFAULT.to_rust());
+    }
+
+    #[test]
+    fn test_tuner_features_match_eval() {
+        let values: Vec<i32> = params::DEFAULT.to_vec();
+        for fen in SYMMETRY_FENS {
+            let board = Board::from_fen(fen);
+            let (features, phase) = eval::features(&board, &params::DEFAULT);
+            let trace = eval::trace(&board);
+
+            let mg: i32 = features.iter().map(|f| f.mg as i32 * values[f.index as usize]).sum();
+            let eg: i32 = features.iter().map(|f| f.eg as i32 * values[f.index as usize]).sum();
+            let stm = if trace.white_turn { 1 } else { -1 };
+            let stm_terms = stm * (trace.tempo + trace.tension);
+
+            assert_eq!(phase, trace.phase, "{}", fen);
+            assert_eq!(mg, trace.total().mg + stm_terms, "{}", fen);
+            assert_eq!(eg, trace.total().eg + stm_terms, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_parse_result() {
+        assert_eq!(tune::parse_result("8/8/8/8/8/8/8/K6k w - - 0 1 [0.5]"), Some(0.5));
+        assert_eq!(tune::parse_result("8/8/8/8/8/8/8/K6k w - - c9 \"1-0\";"), Some(1.0));
+        assert_eq!(tune::parse_result("8/8/8/8/8/8/8/K6k b - - 0 1; 0-1"), Some(0.0));
+        assert_eq!(tune::parse_result("8/8/8/8/8/8/8/K6k w - - 0 1"), None);
+        assert_eq!(tune::parse_result("8/8/8/8/8/8/8/K6k w - - 0 1 | -35 | 0.0"), Some(0.0));
+    }
+
+    #[test]
+    fn test_nnue_test_net() {
+        let net = Network::material_test_net();
+        assert_eq!(include_bytes!("../nets/material-16.nnue").as_slice(), net.to_bytes().as_slice());
+        let loaded = Network::from_bytes(&net.to_bytes()).unwrap();
+        assert!(Network::from_bytes(b"VSPRNNUE").is_err());
+
+        // The test net scores plain material from the side to move
+        let values = [100, 320, 330, 500, 900];
+        for fen in SYMMETRY_FENS {
+            let board = Board::from_fen(fen);
+            let white_turn = fen.split_whitespace().nth(1) == Some("w");
+            let (us, them) = if white_turn { (board.white, board.black) } else { (board.black, board.white) };
+            let material: i32 = board.piece_sets().iter().zip(values).map(|(set, v)| {
+                let count = |side| (*set & side).extract(0).count_ones() as i32;
+                v * (count(us) - count(them))
+            }).sum();
+            assert!((loaded.evaluate_board(&board) - material).abs() <= 2, "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_nnue_incremental_matches_refresh() {
+        let net = Network::material_test_net();
+        for fen in SYMMETRY_FENS {
+            let board = Board::from_fen(fen);
+            let mut parent = net.new_accumulator();
+            net.refresh(&mut parent, &board, 0);
+
+            let moves = movegen::generate_move_list(&board, 0);
+            for pm in movegen::pack_move_fields(&moves).iter() {
+                let mut child = board;
+                child.apply_move(&pm);
+                for lane in 0..LANES {
+                    let mut updated = net.new_accumulator();
+                    let mut fresh = net.new_accumulator();
+                    net.update(&mut updated, &parent, &board, &child, lane);
+                    net.refresh(&mut fresh, &child, lane);
+                    assert_eq!(net.evaluate(&updated, &child, lane), net.evaluate(&fresh, &child, lane), "{}", fen);
+                }
+            }
+        }
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_nnue_simd_matches_scalar() {
+        if !is_x86_feature_detected!("avx2") {
+            return;
+        }
+        let mut seed = 0x9e37_79b9_7f4a_7c15u64;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            (seed % 1024) as i16 - 512
+        };
+        let values: Vec<i16> = (0..64).map(|_| next()).collect();
+        let weights: Vec<i16> = (0..64).map(|_| next()).collect();
+
+        let (mut simd, mut scalar) = (values.clone(), values.clone());
+        unsafe { nnue::add_weights_avx2(&mut simd, &weights) };
+        nnue::add_weights_scalar(&mut scalar, &weights);
+        assert_eq!(simd, scalar);
+
+        unsafe { nnue::sub_weights_avx2(&mut simd, &weights) };
+        nnue::sub_weights_scalar(&mut scalar, &weights);
+        assert_eq!(simd, values);
+        assert_eq!(scalar, values);
+
+        let simd_dot = unsafe { nnue::output_dot_avx2(&values, &weights) };
+        assert_eq!(simd_dot, nnue::output_dot_scalar(&values, &weights));
+    }
+
+    #[test]
+    fn test_datagen_records() {
+        for (i, fen) in SYMMETRY_FENS.iter().enumerate() {
+            let board = Board::from_fen(fen);
+            assert_eq!(board.fen(0), *fen);
+
+            let score = i as i32 * 37 - 100;
+            let text = datagen::text_record(&board, score, 0.5);
+            assert_eq!(text, format!("{} | {} | 0.5", fen, score));
+            assert_eq!(tune::parse_result(&text), Some(0.5));
+
+            let record = datagen::binary_record(&board, score, 1.0);
+            let (decoded, decoded_score, result) = datagen::read_binary_record(&record).unwrap();
+            assert_eq!(decoded.fen(0), *fen);
+            assert_eq!((decoded_score, result), (score, 1.0));
+        }
+
+        // Corrupt records are errors: too many pieces, piece codes 6 and 7,
+        // a bad result and a short read
+        let mut record = datagen::binary_record(&Board::from_fen(SYMMETRY_FENS[0]), 0, 0.5);
+        assert!(datagen::read_binary_record(&[0xff; datagen::RECORD_BYTES]).is_err());
+        record[8] = 0x66;
+        assert!(datagen::read_binary_record(&record).is_err());
+        record[8] = 0x0f;
+        assert!(datagen::read_binary_record(&record).is_err());
+        let mut record = datagen::binary_record(&Board::from_fen(SYMMETRY_FENS[0]), 0, 0.5);
+        record[26] = 3;
+        assert!(datagen::read_binary_record(&record).is_err());
+        assert!(datagen::read_binary_record(&record[..10]).is_err());
+
+        assert!(movegen::in_check(&Board::from_fen("4k3/8/8/8/8/8/4r3/4K3 w - - 0 1")));
+        assert!(!movegen::in_check(&Board::from_fen("4k3/8/8/8/8/8/3r4/4K3 b - - 0 1")));
+    }
+
+    #[test]
+    fn test_search_node_limit() {
+        let board = Board::from_fen(SYMMETRY_FENS[1]);
+        let limits = search::Limits { depth: search::MAX_DEPTH, nodes: Some(2000), movetime: None, silent: true };
+        let result = search::search_with(board, &limits);
+        assert!(!result.best_move.is_none());
+        assert!(result.nodes < 4000);
+    }
+
+    #[test]
+    fn test_endgames() {
+        let value = |fen: &str| eval::trace(&Board::from_fen(fen)).endgame_value;
+        let scale = |fen: &str| eval::trace(&Board::from_fen(fen)).scale;
+
+        // KPK: king in front of its pawn wins, opposition draws, a rook pawn
+        // with the defender in the corner draws, and an unstoppable pawn wins
+        assert!(value("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1").unwrap() > 1000);
+        assert_eq!(value("8/8/8/4k3/8/8/4P3/4K3 w - - 0 1"), Some(0));
+        assert_eq!(value("k7/8/K7/P7/8/8/8/8 w - - 0 1"), Some(0));
+        assert!(value("7k/8/8/8/8/8/P7/K7 w - - 0 1").unwrap() > 1000);
+        assert!(value("8/8/8/8/8/8/p7/k6K b - - 0 1").unwrap() < -1000);
+
+        assert_eq!(value("8/8/3k4/8/8/8/8/3NK3 w - - 0 1"), Some(0));
+        assert!(value("8/8/3k4/8/8/8/8/3RK3 w - - 0 1").unwrap() > 1000);
+
+        // KBNK drives the king to a corner the bishop controls
+        let right = value("7k/8/5K2/8/8/8/8/2B1N3 w - - 0 1").unwrap();
+        let wrong = value("k7/8/2K5/8/8/8/8/2B1N3 w - - 0 1").unwrap();
+        assert!(right > wrong);
+
+        assert_eq!(scale("k7/8/8/P7/8/8/8/2B1K3 w - - 0 1"), 0);
+        assert_eq!(scale("k7/8/8/P7/8/8/8/3BK3 w - - 0 1"), eval::SCALE_NORMAL);
+        assert_eq!(scale("4k3/5p2/4b3/8/8/2B5/4PP2/4K3 w - - 0 1"), eval::SCALE_NORMAL / 4);
+
+        for fen in ["4k3/8/4K3/4P3/8/8/8/8 w - - 0 1", "7k/8/5K2/8/8/8/8/2B1N3 w - - 0 1", "k7/8/8/P7/8/8/8/2B1K3 w - - 0 1"] {
+            let board = Board::from_fen(fen);
+            assert_eq!(eval::evaluate(&board)[0], eval::evaluate(&Board::from_fen(&flip_colors(fen)))[0], "{}", fen);
+            assert_eq!(eval::evaluate(&board)[0], eval::evaluate(&Board::from_fen(&mirror_files(fen)))[0], "{}", fen);
+        }
+    }
+
+    #[test]
+    fn test_syzygy_single_value_table() {
+        // A KRvK WDL file where every position is a win for the side with the
+        // rook: both sides stored, each as a single value
+        let mut bytes = vec![0x71, 0xe8, 0x23, 0x5d, 0x01, 0x00, 0x66, 0x44, 0xee, 0x00];
+        bytes.extend_from_slice(&[0x80, 4, 0x80, 0]);
+        bytes.resize(64, 0);
+
+        let dir = std::env::temp_dir().join(format!("vesper-syzygy-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("KRvK.rtbw"), &bytes).unwrap();
+        let tb = Tablebases::open(dir.to_str().unwrap()).unwrap();
+        assert_eq!((tb.table_count(), tb.max_pieces()), (1, 3));
+
+        let wdl = |fen: &str| tb.probe_wdl(&Board::from_fen(fen));
+        assert_eq!(wdl("7k/8/8/8/8/8/8/K1R5 w - - 0 1"), Some(syzygy::WDL_WIN));
+        assert_eq!(wdl("7k/8/8/8/8/8/8/K1R5 b - - 0 1"), Some(syzygy::WDL_LOSS));
+        assert_eq!(wdl(&flip_colors("7k/8/8/8/8/8/8/K1R5 w - - 0 1")), Some(syzygy::WDL_WIN));
+        // The rook can be taken, and KvK is a draw without a table
+        assert_eq!(wdl("8/8/8/8/8/8/6k1/K6R b - - 0 1"), Some(syzygy::WDL_DRAW));
+        // Black in check with White to move can't be in a table
+        assert_eq!(wdl("7k/8/8/8/8/8/8/K6R w - - 0 1"), None);
+        // No KQvK table
+        assert_eq!(wdl("7k/8/8/8/8/8/8/K1Q5 w - - 0 1"), None);
+
+        // Truncated or corrupt files fail the probe instead of panicking, and
+        // a fixed file is picked up without reopening the tables
+        let mut corrupt = bytes[..10].to_vec();
+        corrupt.extend_from_slice(&[0x00, 5, 10, 0, 1, 0, 0, 0, 1, 1, 0, 0, 1, 0]);
+        std::fs::write(dir.join("KRvK.rtbw"), &corrupt).unwrap();
+        let tb = Tablebases::open(dir.to_str().unwrap()).unwrap();
+        assert_eq!(tb.probe_wdl(&Board::from_fen("7k/8/8/8/8/8/8/K1R5 w - - 0 1")), None);
+        // A pair pointing past the symbol table
+        corrupt.extend_from_slice(&[0x05, 0x10, 0x00]);
+        corrupt.resize(64, 0);
+        std::fs::write(dir.join("KRvK.rtbw"), &corrupt).unwrap();
+        assert_eq!(tb.probe_wdl(&Board::from_fen("7k/8/8/8/8/8/8/K1R5 w - - 0 1")), None);
+        bytes.resize(128, 0);
+        std::fs::write(dir.join("KRvK.rtbw"), &bytes).unwrap();
+        assert_eq!(tb.probe_wdl(&Board::from_fen("7k/8/8/8/8/8/8/K1R5 w - - 0 1")), Some(syzygy::WDL_WIN));
+
+        // A KPvKP file, won for the side to move everywhere: a pawn on the
+        // first rank, which a made-up FEN can have, isn't probed
+        let mut bytes = vec![0x71, 0xe8, 0x23, 0x5d, 0x02];
+        for _ in 0..4 {
+            bytes.extend_from_slice(&[0x00, 0x01, 0x01, 0x09, 0x06, 0x0e]);
+        }
+        bytes.push(0);
+        for _ in 0..4 {
+            bytes.extend_from_slice(&[0x80, 4]);
+        }
+        bytes.resize(64, 0);
+        std::fs::write(dir.join("KPvKP.rtbw"), &bytes).unwrap();
+        let tb = Tablebases::open(dir.to_str().unwrap()).unwrap();
+        assert_eq!(tb.probe_wdl(&Board::from_fen("7K/8/8/8/4P3/8/p7/7k w - - 0 1")), Some(syzygy::WDL_WIN));
+        assert_eq!(tb.probe_wdl(&Board::from_fen("7K/8/8/8/4P3/8/8/p6k w - - 0 1")), None);
+        // Nor is a position with castling rights
+        assert_eq!(tb.probe_wdl(&Board::from_fen("7k/8/8/8/8/8/8/4K2R w K - 0 1")), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_polyglot_keys() {
+        // Reference keys from the Polyglot book format description
+        let cases: [(&str, u64); 9] = [
+            ("", 0x463b96181691fc9c),
+            ("e2e4", 0x823c9b50fd114196),
+            ("e2e4 d7d5", 0x0756b94461c50fb0),
+            ("e2e4 d7d5 e4e5", 0x662fafb965db29d4),
+            ("e2e4 d7d5 e4e5 f7f5", 0x22a48b5a8e47ff78),
+            ("e2e4 d7d5 e4e5 f7f5 e1e2", 0x652a607ca3f242c1),
+            ("e2e4 d7d5 e4e5 f7f5 e1e2 e8f7", 0x00fdd303c946bdd9),
+            ("a2a4 b7b5 h2h4 b5b4 c2c4", 0x3c8123ea7b067637),
+            ("a2a4 b7b5 h2h4 b5b4 c2c4 b4c3 a1a3", 0x5c3f9b829b279560),
+        ];
+        for (moves, expected) in cases {
+            let mut board = Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+            for m in moves.split_whitespace() {
+                let mv = movegen::legal_move_list(&board).iter().copied().find(|mv| mv.to_string() == m).unwrap();
+                board.play(mv);
+            }
+            assert_eq!(book::key(&board), expected, "{}", moves);
+        }
+
+        // Castling is the king taking its rook; promotions keep their piece
+        assert_eq!(book::encode_move(Move::with_kind(4, 6, Move::CASTLING)), 4 << 6 | 7);
+        assert_eq!(book::encode_move(Move::with_kind(60, 58, Move::CASTLING)), 60 << 6 | 56);
+        assert_eq!(book::encode_move(Move::promotion(52, 60, 2)), 2 << 12 | 52 << 6 | 60);
+    }
+
+    #[test]
+    fn test_book_from_pgn() {
+        let pgn = "[Event \"a\"]\n[Result \"1-0\"]\n\n1. e4 {best by test} e5 2. Nf3 (2. f4 exf4) Nc6 $1 1-0\n\n\
+            [Result \"1/2-1/2\"]\n1.e4 c5 2.Nf3 1/2-1/2\n\n[Result \"*\"]\n1. d4 d5 *\n\n\
+            [Result \"0-1\"]\n1. d4 Nf6 2. Nf3 g6 3. Bf4 Bg7 4. e3 O-O 0-1\n";
+        let games = pgn::parse(pgn);
+        assert_eq!(games.len(), 4);
+        let sans: Vec<String> = games[0].mainline().iter().scan(games[0].start, |board, mv| {
+            let san = san::to_san(board, *mv);
+            board.play(*mv);
+            Some(san)
+        }).collect();
+        assert_eq!(sans, ["e4", "e5", "Nf3", "Nc6"]);
+
+        let built = book::build(&games, 24, 1);
+        let book = Book::from_bytes(&built.to_bytes()).unwrap();
+        // e4 twice (win, draw), d4 once (loss, no weight); moves that only
+        // lost are dropped, and the last game's black moves are all kept
+        let start = Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+        let moves: Vec<(String, u16)> = book.moves(&start).iter().map(|(mv, w)| (mv.to_string(), *w)).collect();
+        assert_eq!(moves, vec![("e2e4".to_string(), 3)]);
+        assert_eq!(book.len(), 8);
+
+        let mut board = start;
+        board.play(san::parse_san(&board, "e4").unwrap());
+        let reply = book.moves(&board);
+        assert_eq!(reply.len(), 1);
+        assert_eq!(book.pick(&board, false, &mut crate::datagen::Rng::new(1)).unwrap().to_string(), "c7c5");
+
+        // The castling entry is played as the king's two-square move
+        let mut board = start;
+        for san in ["d4", "Nf6", "Nf3", "g6", "Bf4", "Bg7", "e3"] {
+            board.play(san::parse_san(&board, san).unwrap());
+        }
+        assert_eq!(book.pick(&board, true, &mut crate::datagen::Rng::new(1)).unwrap().to_string(), "e8g8");
+    }
+
+    #[test]
+    fn test_pgn() {
+        let text = "% exported by hand\n[Event \"Casual \\\"blitz\\\"\"]\n[White \"A\"]\n[Result \"1-0\"]\n\n\
+            {Opening} 1. e4 e5 2.Nf3!? (2. f4 exf4 (2... d5 $14) 3. Nf3) 2... Nc6 ; rest of line\n\
+            3. Bb5 {Spanish} a6 4. O-O Nf6 5. d3 1-0\n\
+            [Event \"Second\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 40\"]\n\n40... Kd7 41. e4 Ke6 *\n\
+            [Event \"Cut off\"]\n[Result \"0-1\"]\n1. d4 d5";
+        let games = pgn::parse(text);
+        assert_eq!(games.len(), 3);
+
+        let game = &games[0];
+        assert_eq!(game.tag("Event"), Some("Casual \"blitz\""));
+        assert_eq!(game.result, "1-0");
+        assert_eq!(game.root.comment.as_deref(), Some("Opening"));
+        assert_eq!(game.mainline().len(), 9);
+        assert!(game.error.is_none());
+        let e5 = &game.root.children[0].children[0];
+        assert_eq!(e5.children.len(), 2);
+        let (nf3, f4) = (&e5.children[0], &e5.children[1]);
+        assert_eq!((nf3.san.as_str(), nf3.nags.as_slice()), ("Nf3", &[5][..]));
+        assert_eq!(nf3.children[0].comment.as_deref(), Some("rest of line"));
+        assert_eq!(f4.children[0].san, "exf4");
+        assert_eq!(f4.children[1].san, "d5");
+        assert_eq!(f4.children[1].nags, [14]);
+        assert_eq!(f4.children[0].children[0].san, "Nf3");
+
+        assert_eq!(games[1].start_ply, 79);
+        assert_eq!(games[1].mainline().len(), 3);
+        assert_eq!(games[2].result, "0-1");
+        assert_eq!(games[2].mainline().len(), 2);
+
+        // Writing and reading back keeps the tree
+        let written = pgn::write(game);
+        let movetext = written.split_whitespace().collect::<Vec<_>>().join(" ");
+        assert!(movetext.contains("2. Nf3 $5 (2. f4 exf4 (2... d5 $14) 3. Nf3) 2... Nc6 {rest of line} 3. Bb5 {Spanish} 3... a6 4. O-O Nf6 5. d3 1-0"), "{}", written);
+        assert!(written.lines().all(|line| line.len() < 80));
+        let again = pgn::parse(&written);
+        assert_eq!(again.len(), 1);
+        assert_eq!(pgn::write(&again[0]), written);
+        assert_eq!(pgn::write(&pgn::parse(&pgn::write(&games[1]))[0]), pgn::write(&games[1]));
+        assert!(pgn::write(&games[1]).contains("40... Kd7 41. e4 Ke6 *"));
+
+        // A bad FEN tag is an error on its game rather than a panic
+        let text = "[FEN \"\"]\n1. e4 *\n[FEN \"rnbqkbnr/ppppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w\"]\n1. e4 *\n\
+            [Event \"Fine\"]\n1. e4 *";
+        let games = pgn::parse(text);
+        assert_eq!(games.len(), 3);
+        assert!(games[0].error.as_deref().unwrap().starts_with("FEN tag: empty FEN"));
+        assert!(games[1].error.as_deref().unwrap().contains("9 files"));
+        assert!(games[0].mainline().is_empty() && games[1].mainline().is_empty());
+        assert!(games[2].error.is_none());
+        assert!(Board::check_fen("4k3/8/8/8/8/8/4P3/4K3 x").is_err());
+        assert!(Board::check_fen("4k3/8/8/8/8/8/4P3/4K2X w").is_err());
+
+        // A promotion leaves a queen to move on
+        let games = pgn::parse("[FEN \"4k3/1P6/8/8/8/8/8/4K3 w - - 0 1\"]\n1. b8=Q Kd7 2. Qb7+ *");
+        assert_eq!(games[0].mainline().len(), 3);
+        assert!(games[0].error.is_none());
+        assert!(pgn::write(&games[0]).contains("1. b8=Q+ Kd7 2. Qb7+ *"));
+
+        // Whole games round-trip: castling on both wings, en passant and promotions
+        let text = "[Event \"Specials\"]\n[Result \"*\"]\n\n\
+            1. e4 d5 2. e5 f5 3. exf6 Nc6 4. fxg7 Bf5 5. gxh8=Q Qd7 6. Nf3 O-O-O 7. Be2 e5 8. O-O e4 *\n\n\
+            [FEN \"4k3/1P6/8/8/8/8/6p1/4K3 w - - 0 1\"]\n[Result \"*\"]\n\n1. b8=N g1=R+ 2. Kd2 *\n";
+        let games = pgn::parse(text);
+        assert_eq!(games.len(), 2);
+        for game in &games {
+            assert!(game.error.is_none(), "{:?}", game.error);
+            let written = pgn::write(game);
+            let again = pgn::parse(&written);
+            assert_eq!(again[0].mainline(), game.mainline());
+            assert_eq!(pgn::write(&again[0]), written);
+        }
+        assert_eq!(games[0].mainline().len(), 16);
+        let movetext = pgn::write(&games[0]).split_whitespace().collect::<Vec<_>>().join(" ");
+        assert!(movetext.contains("3. exf6 Nc6 4. fxg7 Bf5 5. gxh8=Q Qd7 6. Nf3 O-O-O 7. Be2 e5 8. O-O e4 *"), "{}", movetext);
+        let end = games[0].mainline().iter().fold(games[0].start, |mut board, &mv| {
+            board.play(mv);
+            board
+        });
+        assert_eq!(end.fen(0), "2kr1bnQ/pppq3p/2n5/3p1b2/4p3/5N2/PPPPBPPP/RNBQ1RK1 w - - 0 1");
+        assert_eq!(games[1].mainline().len(), 3);
+    }
+
+    #[test]
+    fn test_epd() {
+        let line = "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - bm Qg6; id \"WAC.001; a\"; c0 \"mate in 3\"; acd 12;";
+        let position = testsuite::parse_epd(line).unwrap();
+        assert_eq!(position.fen, "2rr3k/pp3pp1/1nnqbN1p/3pN3/2pP4/2P3Q1/PPB4P/R4RK1 w - - 0 1");
+        assert_eq!(position.best, ["Qg6"]);
+        assert!(position.avoid.is_empty());
+        assert_eq!(position.id.as_deref(), Some("WAC.001; a"));
+        assert_eq!(position.comment.as_deref(), Some("mate in 3"));
+
+        let position = testsuite::parse_epd("4k3/8/8/8/8/8/4P3/4K3 b - - am Kd7 Kf7;").unwrap();
+        assert_eq!(position.avoid, ["Kd7", "Kf7"]);
+        assert!(testsuite::parse_epd("4k3/8 w").is_none());
+
+        // A time limit stops an unbounded depth, keeping each finished iteration
+        let board = Board::from_fen(&position.fen);
+        let limits = search::Limits { depth: search::MAX_DEPTH, nodes: None, movetime: Some(50), silent: true };
+        let start = std::time::Instant::now();
+        let result = search::search_with(board, &limits);
+        assert!(start.elapsed().as_millis() < 2000);
+        assert!(!result.history.is_empty());
+        assert_eq!(result.history.last().unwrap().0, result.best_move);
+    }
+
+    #[test]
+    fn test_bench_deterministic() {
+        let nodes = bench::bench(2);
+        assert!(nodes > 0);
+        assert_eq!(bench::bench(2), nodes);
+    }
+
+    #[test]
+    fn test_match_statistics() {
+        assert_eq!(arena::elo(0, 0, 0), (0.0, 0.0));
+        let (diff, margin) = arena::elo(30, 30, 40);
+        assert!(diff.abs() < 1e-9 && margin > 0.0);
+        let (diff, margin) = arena::elo(60, 40, 0);
+        assert!((diff - 70.4).abs() < 0.1, "{}", diff);
+        assert!((margin - 70.1).abs() < 0.5, "{}", margin);
+
+        assert!((arena::llr(60, 40, 0, 0.0, 5.0) - 0.289).abs() < 0.001);
+        // Evidence against the patch when it scores below elo0
+        assert!(arena::llr(40, 60, 0, 0.0, 5.0) < 0.0);
+        assert_eq!(arena::llr(0, 0, 10, 0.0, 5.0), 0.0);
+
+        // Games recorded from moves come out in PGN with their start position
+        let fen = "4k3/8/8/8/8/8/4P3/4K3 b - - 0 40";
+        let board = Board::from_fen(fen);
+        let mv = san::parse_san(&board, "Kd7").unwrap();
+        let game = pgn::Game::from_moves(fen, &[mv], "1/2-1/2");
+        let written = pgn::write(&game);
+        assert!(written.starts_with("[SetUp \"1\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 40\"]\n"), "{}", written);
+        assert!(written.contains("40... Kd7 1/2-1/2"));
+    }
+
+    #[test]
+    fn test_match_internal_games() {
+        let args = |text: &str| text.split_whitespace().map(String::from).collect::<Vec<_>>();
+        let play = |text: &str, fen: &str| {
+            let options = arena::parse_options(&args(text)).unwrap();
+            let mut players = arena::start_players(&options).unwrap();
+            let opening = arena::Opening { fen: fen.to_string(), moves: Vec::new() };
+            let game = arena::play_game(&mut players, 0, &opening, &options, None);
+            (game.result, game.reason)
+        };
+        let start = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1";
+        let no_black_queen = "rnb1kbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1";
+
+        // Both report small scores from the start, so the draw rule ends it at once
+        assert_eq!(play("--engine cmd=internal name=a depth=1 --engine cmd=internal name=b depth=1 \
+            --draw movenumber=0 moves=1 score=200", start), ("1/2-1/2", "draw adjudication".to_string()));
+        // Black is a queen down and resigns on its first move
+        let resign = "--engine cmd=internal name=a depth=1 --engine cmd=internal name=b depth=1 --resign moves=1 score=500";
+        assert_eq!(play(resign, no_black_queen), ("1-0", "b resigns".to_string()));
+
+        // Each internal engine has its own options: with queens worth less than
+        // nothing, White thinks it is the one losing and resigns first
+        let dir = std::env::temp_dir().join(format!("vesper-match-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let params_path = dir.join("params.txt");
+        std::fs::write(&params_path, Params { queen: -2000, ..params::DEFAULT }.to_text()).unwrap();
+        let own_params = resign.replace("name=a depth=1", &format!("name=a depth=1 option.ParamsFile={}", params_path.display()));
+        assert_eq!(play(&own_params, no_black_queen), ("0-1", "a resigns".to_string()));
+        std::fs::remove_dir_all(&dir).unwrap();
+        let unknown = arena::parse_options(&args("--engine cmd=internal option.Hash=16 --engine cmd=internal")).unwrap();
+        assert!(arena::start_players(&unknown).is_err());
+
+        // With no time at all, the first move overruns the clock
+        assert_eq!(play("--engine cmd=internal name=a --engine cmd=internal name=b --tc 0+0 --timemargin 0", start),
+            ("0-1", "a loses on time".to_string()));
+    }
+
+    #[test]
+    fn test_search_info_callback() {
+        let board = Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1");
+        let limits = search::Limits { depth: 3, nodes: None, movetime: None, silent: true };
+        let mut reports = Vec::new();
+        let result = search::search_with_info(board, &limits, &mut |info| {
+            reports.push((info.depth, info.bound, info.nodes, info.pv.first().map(Move::to_string)));
+        });
+        let exact: Vec<_> = reports.iter().filter(|r| r.1.is_empty()).collect();
+        assert_eq!(exact.iter().map(|r| r.0).collect::<Vec<_>>(), [1, 2, 3]);
+        assert!(reports.windows(2).all(|w| w[0].2 <= w[1].2));
+        assert_eq!(exact[2].3, Some(result.best_move.to_string()));
+        assert_eq!(exact[2].2, result.nodes);
+    }
+
+    #[test]
+    fn test_san() {
+        // Every legal move round-trips and gets a distinct name
+        let extra = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w - - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "1k6/8/8/3N1N2/8/3N1N2/8/1K6 w - - 0 1",
+            "R6R/8/4k3/8/R6R/8/8/2K5 w - - 0 1",
+            "8/8/3Q1Q2/8/3Q1Q2/1k6/8/7K w - - 0 1",
+        ];
+        for fen in SYMMETRY_FENS.iter().chain(extra.iter()) {
+            for board in [Board::from_fen(fen), Board::from_fen(&flip_colors(fen))] {
+                let moves = movegen::legal_move_list(&board);
+                let names: Vec<String> = moves.iter().map(|mv| san::to_san(&board, *mv)).collect();
+                for (mv, name) in moves.iter().zip(names.iter()) {
+                    let parsed = san::parse_san(&board, name).unwrap();
+                    assert_eq!(parsed, *mv, "{} in {}", name, fen);
+                    assert_eq!(names.iter().filter(|n| *n == name).count(), 1, "{} in {}", name, fen);
+                }
+            }
+        }
+
+        let names = |fen: &str, moves: &[&str]| -> Vec<String> {
+            let board = Board::from_fen(fen);
+            moves.iter().map(|m| {
+                let mv = movegen::legal_move_list(&board).iter().copied().find(|mv| mv.to_string() == *m).unwrap();
+                san::to_san(&board, mv)
+            }).collect()
+        };
+        // Queens around e5 need the file, the rank or both
+        assert_eq!(names("8/8/3Q1Q2/8/3Q1Q2/1k6/8/7K w - - 0 1", &["d4e5", "f6f8", "f4h4"]), ["Qd4e5", "Qff8", "Q4h4"]);
+        assert_eq!(names("R6R/8/4k3/8/R6R/8/8/2K5 w - - 0 1", &["a4a6", "a8d8", "h4h6", "a4e4"]), ["R4a6+", "Rad8", "R4h6+", "Rae4+"]);
+        assert_eq!(names("rnbqkbnr/ppp1pppp/8/3p4/4P3/8/PPPP1PPP/RNBQKBNR w - - 0 1", &["e4d5", "g1f3", "f1b5"]), ["exd5", "Nf3", "Bb5+"]);
+        assert_eq!(names("7k/5ppp/8/8/8/8/8/R3K3 w - - 0 1", &["a1a8", "a1a7"]), ["Ra8#", "Ra7"]);
+        assert_eq!(names("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1", &["e1g1", "e1c1", "a1a8"]), ["O-O", "O-O-O", "Rxa8+"]);
+        assert_eq!(names("r3k3/1P6/8/8/8/8/8/4K3 w - - 0 1", &["b7b8q", "b7a8n", "b7b8r"]), ["b8=Q+", "bxa8=N", "b8=R+"]);
+        assert_eq!(names("rnbqkbnr/ppp1p1pp/8/3pPp2/8/8/PPPP1PPP/RNBQKBNR w KQkq f6 0 3", &["e5f6", "e5e6"]), ["exf6", "e6"]);
+
+        // Variants and mistakes that still name one move
+        let board = Board::from_fen("r3k3/1P6/8/8/8/8/8/4K1N1 w - - 0 1");
+        let uci_of = |s: &str| san::parse_san(&board, s).map(|mv| mv.to_string());
+        assert_eq!(uci_of("Nf3").unwrap(), "g1f3");
+        assert_eq!(uci_of("Ng1-f3!?").unwrap(), "g1f3");
+        assert_eq!(uci_of("Nf3+").unwrap(), "g1f3");
+        assert_eq!(uci_of("bxa8=Q").unwrap(), "b7a8q");
+        assert_eq!(uci_of("bxa8Q+").unwrap(), "b7a8q");
+        assert_eq!(uci_of("b8=N").unwrap(), "b7b8n");
+        assert!(uci_of("b8").is_err());
+        assert!(uci_of("a€").is_err());
+        assert!(uci_of("N€3").is_err());
+        assert!(uci_of("0-0").unwrap_err().contains("castling"));
+        assert!(uci_of("O-O-O").is_err());
+
+        let board = Board::from_fen("r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 0 1");
+        let uci_of = |s: &str| san::parse_san(&board, s).map(|mv| mv.to_string());
+        assert_eq!(uci_of("O-O").unwrap(), "e8g8");
+        assert_eq!(uci_of("0-0-0+").unwrap(), "e8c8");
+        assert!(uci_of("Kg8").is_err());
+        assert!(uci_of("Nf4").is_err());
+        assert!(uci_of("Zf3").is_err());
+
+        let board = Board::from_fen("rnbqkb1r/ppp2ppp/5n2/3pp3/4P3/5N2/PPPP1PPP/RNBQKB1R b - - 0 1");
+        let uci_of = |s: &str| san::parse_san(&board, s).map(|mv| mv.to_string());
+        assert_eq!(uci_of("Nbd7").unwrap(), "b8d7");
+        assert_eq!(uci_of("Nfd7").unwrap(), "f6d7");
+        assert!(uci_of("Nd7").unwrap_err().contains("ambiguous"));
+        assert_eq!(uci_of("dxe4").unwrap(), "d5e4");
+        assert_eq!(uci_of("Pd4").unwrap(), "d5d4");
+    }
+
+    #[test]
+    fn test_uci_position() {
+        let fen_of = |line: &str| uci::position(&line.split_whitespace().collect::<Vec<_>>()).map(|b| b.fen(0));
+        assert_eq!(fen_of("position startpos moves e2e4 c7c5").unwrap(), "rnbqkbnr/pp1ppppp/8/2p5/4P3/8/PPPP1PPP/RNBQKBNR w KQkq c6 0 1");
+        // EPD openings have four fields, and may have none but the board
+        assert_eq!(fen_of("position fen 4k3/8/8/8/8/8/4P3/4K3 w - - moves e2e4").unwrap(), "4k3/8/8/8/4P3/8/8/4K3 b - e3 0 1");
+        assert_eq!(fen_of("position fen 4k3/8/8/8/8/8/4P3/4K3 w").unwrap(), "4k3/8/8/8/8/8/4P3/4K3 w - - 0 1");
+        assert_eq!(fen_of("position fen r3k2r/8/8/8/8/8/8/R3K2R b KQkq - 3 20 moves e8c8").unwrap(), "2kr3r/8/8/8/8/8/8/R3K2R w KQ - 0 1");
+        assert!(fen_of("position fen").is_none());
+        assert!(fen_of("position").is_none());
+
+        // setoption values go through Settings::set
+        let mut settings = search::Settings::current();
+        settings.set("SyzygyProbeDepth", "0").unwrap();
+        assert_eq!(settings.tb_depth, 1);
+        settings.set("syzygyprobelimit", "9").unwrap();
+        assert_eq!(settings.tb_limit, 7);
+        assert!(settings.set("SyzygyProbeDepth", "deep").is_err());
+        assert!(settings.set("EvalFile", "/nonexistent.nnue").unwrap_err().contains("cannot load"));
+        assert!(settings.set("Hash", "16").is_err());
+    }
+
+    #[test]
+    fn test_cpu_path() {
+        #[cfg(target_arch = "x86_64")]
+        let expected = if is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("avx512bw") && is_x86_feature_detected!("avx512vl") {
+            cpu::Path::Avx512
+        } else if is_x86_feature_detected!("avx2") {
+            cpu::Path::Avx2
+        } else {
+            cpu::Path::Scalar
+        };
+        #[cfg(not(target_arch = "x86_64"))]
+        let expected = cpu::Path::Scalar;
+        assert_eq!(cpu::path(), expected);
+        // The tests themselves run, so this binary suits the CPU
+        assert!(cpu::check().is_ok());
+    }
+
+    // Every op of a Lane on the same inputs, flattened so backends can be compared
+    fn lane_ops<B: Backend>(seed: u64) -> Vec<u64> {
+        let mut seed = seed;
+        let mut next = || {
+            seed ^= seed << 13;
+            seed ^= seed >> 7;
+            seed ^= seed << 17;
+            seed
+        };
+        let mut out = Vec::new();
+        for _ in 0..64 {
+            let a = LaneOf::<B>::from_array(std::array::from_fn(|_| next()));
+            // Sparse, empty and full lanes as well
+            let b = LaneOf::<B>::from_array(std::array::from_fn(|i| match i % 4 {
+                0 => next(),
+                1 => next() & next(),
+                2 => 0,
+                _ => !0,
+            }));
+            let single = LaneOf::<B>::from_single(next());
+            // Scores either side of zero, for the signed arithmetic
+            let small = LaneOf::<B>::from_array(std::array::from_fn(|_| (next() % 2001).wrapping_sub(1000)));
+            let negated = LaneOf::<B>::EMPTY - small;
+            let lanes = [
+                a, b, single, a & b, a | b, a ^ b, !a,
+                a.eq(a), a.eq(b), (a & b & single).is_zero_mask(), b.is_not_zero_mask(),
+                a.shift_north(), a.shift_south(), a.shift_east(), a.shift_west(),
+                a.shift_north_east(), a.shift_north_west(), a.shift_south_east(), a.shift_south_west(),
+                a.fill_north(!b), a.fill_south(!b), a.fill_east(!b), a.fill_west(!b),
+                a.fill_north_east(!b), a.fill_north_west(!b), a.fill_south_east(!b), a.fill_south_west(!b),
+                a.knight_attacks(), a.king_attacks(), LaneOf::<B>::EMPTY,
+                a.popcount(), b.popcount(), a + b, a - b, b - a, a.mul_const(-37), a.mul_const(1 << 40),
+                a.gt(b), b.gt(a), a.gt(a), a.min(b), a.max(b), a.gt(b).select(single, b),
+                small.popcount(), small + a, small.gt(negated), small.min(negated), small.max(negated), small.mul_const(-5),
+            ];
+            for lane in lanes {
+                out.extend(lane.to_array());
+                out.extend((0..LANES).map(|i| lane.extract(i)));
+            }
+        }
+        out
+    }
+
+    #[test]
+    fn test_lane_backends_match_scalar() {
+        let expected = lane_ops::<Scalar>(0x2545_f491_4f6c_dd1d);
+        assert_eq!(lane_ops::<crate::lane::Selected>(0x2545_f491_4f6c_dd1d), expected);
+        #[cfg(all(target_arch = "x86_64", not(feature = "lanes8")))]
+        if is_x86_feature_detected!("avx2") {
+            assert_eq!(lane_ops::<crate::lane_avx2::Avx2>(0x2545_f491_4f6c_dd1d), expected);
+        }
+        // Elsewhere the eight-lane build runs on the scalar emulation only
+        #[cfg(all(target_arch = "x86_64", feature = "lanes8"))]
+        if is_x86_feature_detected!("avx512f") && is_x86_feature_detected!("avx512bw") && is_x86_feature_detected!("avx512vl") {
+            assert_eq!(lane_ops::<crate::lane_avx512::Avx512>(0x2545_f491_4f6c_dd1d), expected);
+        }
+        #[cfg(feature = "simd")]
+        assert_eq!(lane_ops::<crate::lane_simd::Simd>(0x2545_f491_4f6c_dd1d), expected);
     }
 }
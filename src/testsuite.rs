@@ -0,0 +1,185 @@
+use std::fs;
+
+use crate::board::Board;
+use crate::movegen::Move;
+use crate::san;
+use crate::search::{self, Limits};
+
+struct Options {
+    epd: String,
+    movetime: u64,
+}
+
+// One EPD record: the four position fields and the operations we use
+pub struct EpdPosition {
+    pub fen: String,
+    pub id: Option<String>,
+    // Best and avoid moves, in SAN
+    pub best: Vec<String>,
+    pub avoid: Vec<String>,
+    pub comment: Option<String>,
+}
+
+fn usage() {
+    eprintln!("usage: vesper testsuite <file.epd> [--movetime N]");
+}
+
+fn parse_options(args: &[String]) -> Option<Options> {
+    let mut options = Options {
+        epd: args.first()?.clone(),
+        movetime: 1000,
+    };
+
+    let mut i = 1;
+    while i < args.len() {
+        let value = args.get(i + 1)?;
+        match args[i].as_str() {
+            "--movetime" => options.movetime = value.parse().ok()?,
+            _ => return None,
+        }
+        i += 2;
+    }
+
+    Some(options)
+}
+
+// `<board> <side> <castling> <ep> op operand...; op ...;`. Operands may be
+// quoted, and a quoted `;` doesn't end its operation. Other opcodes are ignored.
+pub fn parse_epd(line: &str) -> Option<EpdPosition> {
+    let fields: Vec<&str> = line.split_whitespace().take(4).collect();
+    if fields.len() < 4 {
+        return None;
+    }
+    let mut position = EpdPosition {
+        fen: format!("{} 0 1", fields.join(" ")),
+        id: None,
+        best: Vec::new(),
+        avoid: Vec::new(),
+        comment: None,
+    };
+
+    // Skip past the four fields, keeping the operations' own spacing
+    let mut rest = line.trim_start();
+    for _ in 0..4 {
+        rest = rest.split_once(char::is_whitespace).map_or("", |(_, r)| r).trim_start();
+    }
+
+    // Words of the operation so far, with quoted strings as single words
+    let mut words: Vec<String> = Vec::new();
+    let mut word = String::new();
+    let mut quoted = false;
+    for c in rest.chars().chain(std::iter::once(';')) {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                if !quoted {
+                    words.push(std::mem::take(&mut word));
+                }
+            }
+            _ if quoted => word.push(c),
+            ';' | ' ' | '\t' => {
+                if !word.is_empty() {
+                    words.push(std::mem::take(&mut word));
+                }
+                if c == ';' && !words.is_empty() {
+                    let operands = words.split_off(1);
+                    match words[0].as_str() {
+                        "bm" => position.best = operands,
+                        "am" => position.avoid = operands,
+                        "id" => position.id = operands.into_iter().next(),
+                        "c0" => position.comment = operands.into_iter().next(),
+                        _ => {}
+                    }
+                    words.clear();
+                }
+            }
+            _ => word.push(c),
+        }
+    }
+    Some(position)
+}
+
+// The moves named in SAN as UCI strings, or why one can't be played
+fn parse_moves(board: &Board, sans: &[String]) -> Result<Vec<String>, String> {
+    sans.iter().map(|s| san::parse_san(board, s).map(|mv| mv.to_string())).collect()
+}
+
+// Runs every position for `--movetime` ms. A position is solved when the
+// move played is a best move (if any are given) and not an avoid move; its
+// time to solve is when the search settled on a solving move for good.
+pub fn run(args: &[String]) {
+    let Some(options) = parse_options(args) else {
+        usage();
+        return;
+    };
+    let text = match fs::read_to_string(&options.epd) {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("cannot read {}: {}", options.epd, e);
+            return;
+        }
+    };
+
+    let limits = Limits { depth: search::MAX_DEPTH, nodes: None, movetime: Some(options.movetime), silent: true };
+    let (mut solved, mut failed, mut skipped) = (0, 0, 0);
+    let mut solve_time = 0;
+
+    for (n, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let Some(position) = parse_epd(line) else {
+            println!("line {}: not an EPD record", n + 1);
+            skipped += 1;
+            continue;
+        };
+        let id = position.id.clone().unwrap_or_else(|| format!("line {}", n + 1));
+
+        let board = Board::from_fen(&position.fen);
+        let moves = parse_moves(&board, &position.best).and_then(|best| Ok((best, parse_moves(&board, &position.avoid)?)));
+        let (best, avoid) = match moves {
+            Ok(moves) if !moves.0.is_empty() || !moves.1.is_empty() => moves,
+            Ok(_) => {
+                println!("{}: skipped, no bm or am", id);
+                skipped += 1;
+                continue;
+            }
+            Err(e) => {
+                println!("{}: skipped, {}", id, e);
+                skipped += 1;
+                continue;
+            }
+        };
+        let solves = |mv: Move| {
+            let mv = mv.to_string();
+            (best.is_empty() || best.contains(&mv)) && !avoid.contains(&mv)
+        };
+
+        let result = search::search_with(board, &limits);
+        let played = san::to_san(&board, result.best_move);
+        if solves(result.best_move) {
+            // The first iteration after the last one that didn't solve it
+            let settled = result.history.iter().rposition(|(mv, _)| !solves(*mv)).map_or(0, |i| i + 1);
+            let ms = result.history.get(settled).map_or(0, |&(_, ms)| ms);
+            println!("{}: solved {} in {} ms", id, played, ms);
+            solved += 1;
+            solve_time += ms;
+        } else {
+            let mut expected = Vec::new();
+            if !position.best.is_empty() {
+                expected.push(format!("bm {}", position.best.join(" ")));
+            }
+            if !position.avoid.is_empty() {
+                expected.push(format!("am {}", position.avoid.join(" ")));
+            }
+            let comment = position.comment.as_ref().map_or(String::new(), |c| format!(" \"{}\"", c));
+            println!("{}: failed, played {} ({}){}", id, played, expected.join(", "), comment);
+            failed += 1;
+        }
+    }
+
+    let average = solve_time.checked_div(solved).unwrap_or(0);
+    println!("solved {} of {}, failed {}, skipped {}, average time to solve {} ms",
+        solved, solved + failed, failed, skipped, average);
+}
@@ -0,0 +1,326 @@
+use std::fs;
+use std::io;
+use std::thread;
+
+use crate::board::Board;
+use crate::datagen;
+use crate::eval::{self, Feature, PHASE_MAX};
+use crate::params::{self, Params};
+
+const PST_MIN: i32 = -128;
+const PST_MAX: i32 = 127;
+
+struct Entry {
+    features: Vec<Feature>,
+    phase: i32,
+    result: f64,
+}
+
+struct Options {
+    data: String,
+    epochs: usize,
+    learning_rate: f64,
+    init: Option<String>,
+    out: String,
+    rust: Option<String>,
+}
+
+fn usage() {
+    eprintln!("usage: vesper tune <positions.txt|datagen.bin> [--epochs N] [--lr X] [--init params.txt] [--out params.txt] [--rust src/params.rs]");
+}
+
+fn parse_options(args: &[String]) -> Option<Options> {
+    let mut options = Options {
+        data: args.first()?.clone(),
+        epochs: 500,
+        learning_rate: 1.0,
+        init: None,
+        out: "tuned_params.txt".to_string(),
+        rust: None,
+    };
+
+    let mut i = 1;
+    while i < args.len() {
+        let value = args.get(i + 1)?;
+        match args[i].as_str() {
+            "--epochs" => options.epochs = value.parse().ok()?,
+            "--lr" => options.learning_rate = value.parse().ok()?,
+            "--init" => options.init = Some(value.clone()),
+            "--out" => options.out = value.clone(),
+            "--rust" => options.rust = Some(value.clone()),
+            _ => return None,
+        }
+        i += 2;
+    }
+
+    Some(options)
+}
+
+pub fn run(args: &[String]) {
+    let Some(options) = parse_options(args) else {
+        usage();
+        return;
+    };
+
+    let initial = match &options.init {
+        Some(path) => match fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|t| Params::from_text(&t)) {
+            Ok(p) => p,
+            Err(e) => {
+                eprintln!("cannot load {}: {}", path, e);
+                return;
+            }
+        },
+        None => params::DEFAULT,
+    };
+
+    // Datagen's binary records are recognised by extension, anything else is text
+    let entries = match fs::read(&options.data) {
+        Ok(bytes) if options.data.ends_with(".bin") => load_binary_entries(&bytes, &initial),
+        Ok(bytes) => load_entries(&String::from_utf8_lossy(&bytes), &initial),
+        Err(e) => {
+            eprintln!("cannot read {}: {}", options.data, e);
+            return;
+        }
+    };
+    if entries.is_empty() {
+        eprintln!("no labelled positions in {}", options.data);
+        return;
+    }
+    println!("loaded {} positions", entries.len());
+
+    let mut weights: Vec<f64> = initial.to_vec().iter().map(|&v| v as f64).collect();
+    let k = fit_k(&entries, &weights);
+    println!("K = {:.4}, initial error {:.6}", k, mean_error(&entries, &weights, k));
+
+    adam(&entries, &mut weights, k, options.epochs, options.learning_rate);
+
+    let tuned = Params::from_slice(&round_weights(&weights));
+    println!("final error {:.6}", mean_error(&entries, &weights, k));
+
+    if let Err(e) = fs::write(&options.out, tuned.to_text()) {
+        eprintln!("cannot write {}: {}", options.out, e);
+    } else {
+        println!("wrote {}", options.out);
+    }
+    if let Some(path) = &options.rust {
+        match write_rust(path, &tuned) {
+            Ok(()) => println!("wrote {}", path),
+            Err(e) => eprintln!("cannot write {}: {}", path, e),
+        }
+    }
+}
+
+// Accepts `fen [1.0]`, `fen "1-0";`, datagen's `fen | score | 1.0` and
+// similar: the result is taken from the last 1-0, 0-1, 1/2-1/2 or bracketed
+// number on the line, or the last `|` field
+pub fn parse_result(line: &str) -> Option<f64> {
+    if let Some((_, last)) = line.rsplit_once('|') {
+        return last.trim().parse().ok();
+    }
+    for token in line.split_whitespace().rev() {
+        let t = token.trim_matches(|c| c == '"' || c == ';' || c == '[' || c == ']' || c == ',');
+        match t {
+            "1-0" => return Some(1.0),
+            "0-1" => return Some(0.0),
+            "1/2-1/2" => return Some(0.5),
+            _ => {}
+        }
+        if token.starts_with('[')
+            && let Ok(v) = t.parse::<f64>()
+        {
+            return Some(v);
+        }
+    }
+    None
+}
+
+// Positions under a specialised endgame rule aren't linear in the parameters
+fn entry(board: &Board, result: f64, params: &Params) -> Option<Entry> {
+    let trace = eval::trace(board);
+    if trace.endgame.is_some() {
+        return None;
+    }
+    let (features, phase) = eval::features(board, params);
+    Some(Entry { features, phase, result })
+}
+
+// Corrupt records are skipped, with a count of them at the end
+fn load_binary_entries(bytes: &[u8], params: &Params) -> Vec<Entry> {
+    let mut skipped = 0;
+    let entries = bytes.chunks_exact(datagen::RECORD_BYTES)
+        .filter_map(|record| match datagen::read_binary_record(record) {
+            Ok((board, _, result)) => entry(&board, result, params),
+            Err(_) => {
+                skipped += 1;
+                None
+            }
+        })
+        .collect();
+    if skipped > 0 {
+        eprintln!("skipped {} corrupt records", skipped);
+    }
+    entries
+}
+
+fn load_entries(text: &str, params: &Params) -> Vec<Entry> {
+    text.lines()
+        .filter_map(|line| {
+            let result = parse_result(line)?;
+            Board::check_fen(line).ok()?;
+            entry(&Board::from_fen(line), result, params)
+        })
+        .collect()
+}
+
+fn linear_eval(entry: &Entry, weights: &[f64]) -> f64 {
+    let mut mg = 0.0;
+    let mut eg = 0.0;
+    for f in entry.features.iter() {
+        let w = weights[f.index as usize];
+        mg += f.mg as f64 * w;
+        eg += f.eg as f64 * w;
+    }
+    let phase = entry.phase as f64 / PHASE_MAX as f64;
+    mg * phase + eg * (1.0 - phase)
+}
+
+fn sigmoid(score: f64, k: f64) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-k * score / 400.0))
+}
+
+fn threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+fn mean_error(entries: &[Entry], weights: &[f64], k: f64) -> f64 {
+    let chunk = entries.len().div_ceil(threads());
+    let total: f64 = thread::scope(|s| {
+        let handles: Vec<_> = entries
+            .chunks(chunk)
+            .map(|part| {
+                s.spawn(move || {
+                    part.iter()
+                        .map(|e| (e.result - sigmoid(linear_eval(e, weights), k)).powi(2))
+                        .sum::<f64>()
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).sum()
+    });
+    total / entries.len() as f64
+}
+
+// Golden-section search for the K that best fits the initial weights
+fn fit_k(entries: &[Entry], weights: &[f64]) -> f64 {
+    let ratio = (5f64.sqrt() - 1.0) / 2.0;
+    let (mut lo, mut hi) = (0.0f64, 4.0f64);
+    let mut a = hi - ratio * (hi - lo);
+    let mut b = lo + ratio * (hi - lo);
+    let mut fa = mean_error(entries, weights, a);
+    let mut fb = mean_error(entries, weights, b);
+
+    for _ in 0..40 {
+        if fa < fb {
+            hi = b;
+            b = a;
+            fb = fa;
+            a = hi - ratio * (hi - lo);
+            fa = mean_error(entries, weights, a);
+        } else {
+            lo = a;
+            a = b;
+            fa = fb;
+            b = lo + ratio * (hi - lo);
+            fb = mean_error(entries, weights, b);
+        }
+    }
+
+    (lo + hi) / 2.0
+}
+
+fn gradient(entries: &[Entry], weights: &[f64], k: f64) -> Vec<f64> {
+    let chunk = entries.len().div_ceil(threads());
+    let partials: Vec<Vec<f64>> = thread::scope(|s| {
+        let handles: Vec<_> = entries
+            .chunks(chunk)
+            .map(|part| {
+                s.spawn(move || {
+                    let mut grad = vec![0.0; weights.len()];
+                    for e in part {
+                        let sig = sigmoid(linear_eval(e, weights), k);
+                        // d/dw of (result - sigmoid)^2, chained through the taper
+                        let common = -2.0 * (e.result - sig) * sig * (1.0 - sig) * k * 10f64.ln() / 400.0;
+                        let phase = e.phase as f64 / PHASE_MAX as f64;
+                        for f in e.features.iter() {
+                            grad[f.index as usize] += common * (f.mg as f64 * phase + f.eg as f64 * (1.0 - phase));
+                        }
+                    }
+                    grad
+                })
+            })
+            .collect();
+        handles.into_iter().map(|h| h.join().unwrap()).collect()
+    });
+
+    let mut grad = vec![0.0; weights.len()];
+    for partial in partials {
+        for (g, p) in grad.iter_mut().zip(partial) {
+            *g += p;
+        }
+    }
+    let n = entries.len() as f64;
+    grad.iter_mut().for_each(|g| *g /= n);
+    grad
+}
+
+fn adam(entries: &[Entry], weights: &mut [f64], k: f64, epochs: usize, learning_rate: f64) {
+    const BETA1: f64 = 0.9;
+    const BETA2: f64 = 0.999;
+    const EPSILON: f64 = 1e-8;
+
+    let mut m = vec![0.0; weights.len()];
+    let mut v = vec![0.0; weights.len()];
+
+    for epoch in 1..=epochs {
+        let grad = gradient(entries, weights, k);
+        for (i, g) in grad.iter().enumerate() {
+            if !Params::is_tunable(i) {
+                continue;
+            }
+            m[i] = BETA1 * m[i] + (1.0 - BETA1) * g;
+            v[i] = BETA2 * v[i] + (1.0 - BETA2) * g * g;
+            let m_hat = m[i] / (1.0 - BETA1.powi(epoch as i32));
+            let v_hat = v[i] / (1.0 - BETA2.powi(epoch as i32));
+            weights[i] -= learning_rate * m_hat / (v_hat.sqrt() + EPSILON);
+        }
+
+        if epoch % 50 == 0 || epoch == epochs {
+            println!("epoch {} error {:.6}", epoch, mean_error(entries, weights, k));
+        }
+    }
+}
+
+// PSTs are clamped so that each table still fits the bit-sliced layout
+fn round_weights(weights: &[f64]) -> Vec<i32> {
+    weights
+        .iter()
+        .enumerate()
+        .map(|(i, w)| {
+            let v = w.round() as i32;
+            if (params::PST_MG..params::MOBILITY).contains(&i) { v.clamp(PST_MIN, PST_MAX) } else { v }
+        })
+        .collect()
+}
+
+// Rewrites everything from the generated marker onwards, or writes just the
+// `DEFAULT` definition to a new file
+fn write_rust(path: &str, tuned: &Params) -> io::Result<()> {
+    let source = match fs::read_to_string(path) {
+        Ok(existing) => match existing.rfind(params::GENERATED_MARKER) {
+            Some(pos) => format!("{}{}", &existing[..pos], tuned.to_rust()),
+            None => return Err(io::Error::other("file exists but has no generated marker")),
+        },
+        Err(_) => tuned.to_rust(),
+    };
+    fs::write(path, source)
+}
@@ -1,10 +1,23 @@
 use std::io;
+use std::sync::atomic::Ordering;
+use std::time::{SystemTime, UNIX_EPOCH};
+use crate::bench;
 use crate::board::Board;
-use crate::search;
-use crate::movegen::MoveField;
+use crate::book::{self, Book};
+use crate::cpu;
+use crate::datagen::Rng;
+use crate::eval;
+use crate::nnue;
+use crate::san;
+use crate::search::{self, Settings};
+use crate::movegen::{self, Move};
+
+const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
 
 pub fn main_loop() {
-    let mut board = Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
+    let mut board = Board::from_fen(START_FEN);
+    let mut rng = Rng::new(SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_nanos() as u64));
+    println!("info string using the {} code path", cpu::path().name());
 
     let stdin = io::stdin();
     for line in stdin.lines() {
@@ -16,33 +29,59 @@ pub fn main_loop() {
             "uci" => {
                 println!("id name Vesper");
                 println!("id author Jules");
+                println!("option name EvalFile type string default <empty>");
+                println!("option name ParamsFile type string default <empty>");
+                println!("option name SyzygyPath type string default <empty>");
+                println!("option name SyzygyProbeDepth type spin default 1 min 1 max 100");
+                println!("option name SyzygyProbeLimit type spin default 7 min 0 max 7");
+                println!("option name OwnBook type check default false");
+                println!("option name BookFile type string default <empty>");
+                println!("option name BestBookMove type check default false");
                 println!("uciok");
             }
             "isready" => println!("readyok"),
-            "ucinewgame" => {
-                board = Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
-            }
+            "ucinewgame" => board = Board::from_fen(START_FEN),
             "position" => {
-                if parts.len() > 1 {
-                    if parts[1] == "startpos" {
-                        board = Board::from_fen("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1");
-                        if parts.len() > 2 && parts[2] == "moves" {
-                            apply_uci_moves(&mut board, &parts[3..]);
-                        }
-                    } else if parts[1] == "fen" {
-                        // Reconstruct FEN
-                        let fen_parts = &parts[2..8];
-                        let fen = fen_parts.join(" ");
-                        board = Board::from_fen(&fen);
-                        if parts.len() > 8 && parts[8] == "moves" {
-                            apply_uci_moves(&mut board, &parts[9..]);
-                        }
-                    }
+                if let Some(b) = position(&parts) {
+                    board = b;
                 }
             }
             "go" => {
-                let best_move = search::search(board, 4);
-                println!("bestmove {}", move_to_uci(&best_move));
+                if book::OWN_BOOK.load(Ordering::Relaxed) {
+                    let best = book::BEST_BOOK_MOVE.load(Ordering::Relaxed);
+                    if let Some(mv) = book::book().and_then(|b| b.pick(&board, best, &mut rng)) {
+                        println!("info string book move");
+                        println!("bestmove {}", mv);
+                        continue;
+                    }
+                }
+                let value = |name: &str| -> Option<u64> {
+                    parts.iter().position(|&p| p == name).and_then(|i| parts.get(i + 1)).and_then(|v| v.parse().ok())
+                };
+                let (time, inc) = if board.white_to_move(0) { ("wtime", "winc") } else { ("btime", "binc") };
+                let movetime = value("movetime").or_else(|| {
+                    value(time).map(|t| search::time_for_move(t, value(inc).unwrap_or(0), value("movestogo")))
+                });
+                let nodes = value("nodes");
+                // With no limit at all, a shallow fixed depth
+                let depth = value("depth").map_or(if movetime.is_some() || nodes.is_some() { search::MAX_DEPTH } else { 4 }, |d| d as i32);
+                let limits = search::Limits { depth, nodes, movetime, silent: false };
+                let result = search::search_with(board, &limits);
+                println!("bestmove {}", result.best_move);
+            }
+            "setoption" => set_option(&parts[1..]),
+            // Not UCI: node-count signature of the search
+            "bench" => bench::run(&parts[1..]),
+            "eval" => {
+                println!("{}", eval::weights().as_deref().unwrap_or(&eval::DEFAULT_WEIGHTS).trace(&board));
+                if let Some(net) = nnue::network() {
+                    println!("NNUE evaluation: {} (side to move)", net.evaluate_board(&board));
+                }
+            }
+            // Not UCI: legal moves in SAN, for interactive use
+            "moves" => {
+                let moves: Vec<String> = movegen::legal_move_list(&board).iter().map(|m| san::to_san(&board, *m)).collect();
+                println!("{}", moves.join(" "));
             }
             "quit" => break,
             _ => {}
@@ -50,30 +89,78 @@ pub fn main_loop() {
     }
 }
 
-fn apply_uci_moves(board: &mut Board, moves: &[&str]) {
-    for m_str in moves {
-        // Find the move in legal moves
-        let legal_moves = crate::movegen::generate_moves_for_lane(board, 0);
-        for m in legal_moves {
-            if move_to_uci(&m) == *m_str {
-                board.apply_move(&m);
-                break;
+// setoption name <name> value <value>; the value may contain spaces
+fn set_option(parts: &[&str]) {
+    let value_at = parts.iter().position(|&p| p == "value");
+    let name = parts.get(1..value_at.unwrap_or(parts.len())).unwrap_or(&[]).join(" ");
+    let value = value_at.map_or(String::new(), |i| parts[i + 1..].join(" "));
+
+    if name.eq_ignore_ascii_case("OwnBook") {
+        book::OWN_BOOK.store(value.eq_ignore_ascii_case("true"), Ordering::Relaxed);
+    } else if name.eq_ignore_ascii_case("BestBookMove") {
+        book::BEST_BOOK_MOVE.store(value.eq_ignore_ascii_case("true"), Ordering::Relaxed);
+    } else if name.eq_ignore_ascii_case("BookFile") {
+        if value.is_empty() || value == "<empty>" {
+            book::set_book(None);
+            return;
+        }
+        match Book::load(&value) {
+            Ok(b) => {
+                println!("info string loaded book {} ({} entries)", value, b.len());
+                book::set_book(Some(b));
+            }
+            Err(e) => println!("info string cannot load book {}: {}", value, e),
+        }
+    } else {
+        // Evaluation and tablebase options parse the same way `match` engines do
+        let mut settings = Settings::current();
+        if let Err(e) = settings.set(&name, &value) {
+            println!("info string {}", e);
+            return;
+        }
+        settings.publish();
+        if name.eq_ignore_ascii_case("EvalFile") {
+            match &settings.net {
+                Some(net) => println!("info string loaded network {} ({} hidden neurons)", value, net.hidden_size()),
+                None => println!("info string using classical evaluation"),
+            }
+        } else if name.eq_ignore_ascii_case("ParamsFile") {
+            match &settings.weights {
+                Some(_) => println!("info string loaded evaluation weights {}", value),
+                None => println!("info string using the built-in evaluation weights"),
             }
+        } else if let (true, Some(tb)) = (name.eq_ignore_ascii_case("SyzygyPath"), &settings.tb) {
+            println!("info string found {} tablebases, up to {} pieces", tb.table_count(), tb.max_pieces());
         }
     }
 }
 
-fn sq_to_uci(sq: u32) -> String {
-    let file = (sq % 8) as u8 + b'a';
-    let rank = (sq / 8) as u8 + b'1';
-    format!("{}{}", file as char, rank as char)
+// position startpos|fen <fen> [moves <moves>]; None if neither is given
+pub(crate) fn position(parts: &[&str]) -> Option<Board> {
+    let end = parts.iter().position(|&p| p == "moves").unwrap_or(parts.len());
+    let mut board = match parts.get(1) {
+        Some(&"startpos") => Board::from_fen(START_FEN),
+        // from_fen defaults any fields an EPD leaves out
+        Some(&"fen") if end > 2 => Board::from_fen(&parts[2..end].join(" ")),
+        _ => return None,
+    };
+    apply_uci_moves(&mut board, parts.get(end + 1..).unwrap_or(&[]));
+    Some(board)
+}
+
+fn apply_uci_moves(board: &mut Board, moves: &[&str]) {
+    for m_str in moves {
+        // Find the move in legal moves, falling back to SAN for hand-typed input
+        let found = movegen::legal_move_list(board).iter().copied().find(|m| m.to_string() == *m_str);
+        if let Some(m) = found.or_else(|| san::parse_san(board, m_str).ok()) {
+            board.play(m);
+        }
+    }
 }
 
-fn move_to_uci(m: &MoveField) -> String {
-    let f = m.from.extract(0);
-    let t = m.to.extract(0);
-    if f == 0 || t == 0 { return "0000".to_string(); }
-    let from = f.trailing_zeros();
-    let to = t.trailing_zeros();
-    format!("{}{}", sq_to_uci(from), sq_to_uci(to))
+pub fn print_info(info: &search::Info) {
+    let pv: Vec<String> = info.pv.iter().map(Move::to_string).collect();
+    let bound = if info.bound.is_empty() { String::new() } else { format!(" {}", info.bound) };
+    println!("info depth {} score cp {}{} nodes {} tbhits {} time {} pv {}",
+        info.depth, info.score, bound, info.nodes, info.tb_hits, info.time, pv.join(" "));
 }
@@ -0,0 +1,43 @@
+use crate::board::Board;
+
+const fn splitmix64(state: u64) -> (u64, u64) {
+    let state = state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    (state, z ^ (z >> 31))
+}
+
+const fn pawn_keys() -> [[u64; 64]; 2] {
+    let mut keys = [[0u64; 64]; 2];
+    let mut state = 0x5645_5350_4552u64;
+    let mut color = 0;
+    while color < 2 {
+        let mut sq = 0;
+        while sq < 64 {
+            let (next, key) = splitmix64(state);
+            state = next;
+            keys[color][sq] = key;
+            sq += 1;
+        }
+        color += 1;
+    }
+    keys
+}
+
+// [white, black][square]
+pub const PAWNS: [[u64; 64]; 2] = pawn_keys();
+
+// Key over pawn placement only, used to index the pawn hash table
+pub fn pawn_key(board: &Board, lane: usize) -> u64 {
+    let pawns = board.pawns.extract(lane);
+    let mut key = 0u64;
+    for (color, side) in [board.white, board.black].iter().enumerate() {
+        let mut p = pawns & side.extract(lane);
+        while p != 0 {
+            key ^= PAWNS[color][p.trailing_zeros() as usize];
+            p &= p - 1;
+        }
+    }
+    key
+}